@@ -22,7 +22,9 @@ layout (location = 0) out vec4 frag_color;
 
 #define is_leaf(node) ((node & 16777216) > 0)
 #define is_subdiv(node) ((node & 33554432) > 0)
-#define child_idx(node, mask) ((node & 65535) + mask)
+// Child is only found through the parent node, never through its own idx,
+// so the same traversal works for the octree and the dag with shared blocks
+#define child_idx(node, mask) (((node & 65535) | ((node >> 10) & 4128768)) + mask)
 
 struct PosInfo {
     vec3 local_pos;
@@ -42,12 +44,24 @@ struct LocInfo {
     uint parent_list[16];
     uint last_hit_idx[16];
 
-    uint padding[2];
+    uint node_idx;
+    uint padding;
 
     uint depth;
     float span;
 };
 
+struct Material {
+    vec4 albedo;
+    vec4 emission;
+
+    float roughness;
+    uint material_id;
+
+    float occupancy;
+    uint padding;
+};
+
 layout (set = 0, binding = 0) uniform Uniform {
     mat4 view_proj;
     vec4 pos;
@@ -67,8 +81,9 @@ layout (set = 0, binding = 0) uniform Uniform {
     uint padding[2];
 } uniform_buffer;
 
-layout (set = 1, binding = 0) buffer NodeData { uint node_data[4096]; };
+layout (set = 1, binding = 0) buffer NodeData { uint node_data[]; };
 layout (set = 2, binding = 0) buffer LocationData { LocInfo loc_info[16]; };
+layout (set = 4, binding = 0) buffer MaterialData { Material material_data[]; };
 
 uint get_child(uint parent, uint mask) {
     return node_data[child_idx(parent, mask)];
@@ -159,7 +174,11 @@ void main() {
                 vec3 stepped = step(vec3(span), local_pos);
                 pos_mask = vec_to_mask(stepped);
             } else if (is_leaf(node)) {
-                frag_color = vec4(1,0,0,0);
+                Material material = material_data[child_idx(parent_list[depth - 1], pos_mask)];
+
+                // simple shading based on view direction
+                float diffuse = mix(0.5, 1.0, dot(normalize(vec3(1, 2, 3)), -ray.dir) * 0.5 + 0.5);
+                frag_color = vec4(material.albedo.rgb * diffuse + material.emission.rgb, material.albedo.a);
                 return;
 
             // Else move forward
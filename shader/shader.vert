@@ -16,7 +16,7 @@ layout (location = 4) flat out uint loc_idx;
 
 #define is_leaf(node) ((node & 16777216) > 0)
 #define is_subdiv(node) ((node & 33554432) > 0)
-#define child_idx(node, mask) ((parent & 65535) + mask)
+#define child_idx(node, mask) (((node & 65535) | ((node >> 10) & 4128768)) + mask)
 
 layout (set = 0, binding = 0) uniform Uniform {
     mat4 view_proj;
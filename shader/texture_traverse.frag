@@ -21,7 +21,9 @@ layout (location = 0) out vec4 frag_color;
 
 #define is_leaf(node) ((node & 16777216) > 0)
 #define is_subdiv(node) ((node & 33554432) > 0)
-#define child_idx(node, mask) ((node & 65535) + mask)
+// Child is only found through the parent node, never through its own idx,
+// so the same traversal works for the octree and the dag with shared blocks
+#define child_idx(node, mask) (((node & 65535) | ((node >> 10) & 4128768)) + mask)
 
 #define pos_to_px(pos) (vec2(pos.x, pos.y + (pos.z * TEXTURE_ALIGN)))
 
@@ -38,12 +40,24 @@ struct Ray {
     vec3 inv_ray_dir; // Used for RayCube Intersection
 };
 
+struct Material {
+    vec4 albedo;
+    vec4 emission;
+
+    float roughness;
+    uint material_id;
+
+    float occupancy;
+    uint padding;
+};
+
 struct LocInfo {
     // For proper alignment set depth to 16
     uint parent_list[16];
     uint last_hit_idx[16];
 
-    uint padding[2];
+    uint node_idx;
+    uint padding;
 
     uint depth;
     float span;
@@ -68,9 +82,36 @@ layout (set = 0, binding = 0) uniform Uniform {
     uint padding[2];
 } uniform_buffer;
 
-layout (set = 1, binding = 0) buffer NodeData { uint node_data[4096]; };
+layout (set = 1, binding = 0) buffer NodeData { uint node_data[]; };
 layout (set = 2, binding = 0) buffer LocationData { LocInfo loc_info[16]; };
 layout (set = 3, binding = 0) uniform sampler2D brick_texture;
+layout (set = 4, binding = 0) buffer MaterialData { Material material_data[]; };
+
+struct NodeHit {
+    uint node;
+    uint idx;
+
+    vec3 pos_on_edge;
+    float span;
+};
+
+// Move down from the cube node at idx to the node that contains local_pos,
+// pos on edge comes from the path, as a shared block has many positions
+NodeHit find_node(uint idx, vec3 local_pos, float span) {
+    NodeHit node_hit = NodeHit(node_data[idx], idx, vec3(0), span);
+
+    for (uint depth = 0; depth < 16 && is_subdiv(node_hit.node); depth += 1) {
+        node_hit.span *= 0.5;
+
+        vec3 stepped = step(vec3(node_hit.span), local_pos - node_hit.pos_on_edge);
+        node_hit.pos_on_edge += stepped * node_hit.span;
+
+        node_hit.idx = child_idx(node_hit.node, vec_to_mask(stepped));
+        node_hit.node = node_data[node_hit.idx];
+    }
+
+    return node_hit;
+}
 
 vec3 rayCubeIntersect(vec3 origin, vec3 dir, vec3 inv_ray_dir, float span) {
     float size_cp = span * 0.5;
@@ -79,66 +120,42 @@ vec3 rayCubeIntersect(vec3 origin, vec3 dir, vec3 inv_ray_dir, float span) {
     return -inv_pos * inv_ray_dir;
 }
 
-// todo: fix shader, dunno maybe look into cpu side
 // todo: edit to support sdf with jfa
-// todo: be happy :)
 void main() {
     frag_color = vec4(0);
 
     vec3 world_pos = world_pos.xyz;
 
     float span = loc_info[loc_idx].span;
+    uint cube_idx = loc_info[loc_idx].node_idx;
 
     vec3 ray_dir = normalize(world_pos - uniform_buffer.cam_front.xyz);
     vec3 inv_ray_dir = 1.0 / max(abs(ray_dir), 0.001);
     Ray ray = Ray(world_pos, ray_dir, inv_ray_dir);
 
-    vec3 base_pos_on_edge = pos_on_edge.xyz;
-
-    vec3 hit = rayCubeIntersect(world_pos - base_pos_on_edge, ray.dir, ray.inv_ray_dir, span);
-    vec3 hit_mask_vec = vec3(lessThan(hit, min(hit.yzx, hit.zxy)));
-    float len = dot(hit, hit_mask_vec);
-
-    vec3 pos_on_edge = floor(world_pos);
-    vec3 local_pos = world_pos - pos_on_edge;
-
-    if (gl_FragCoord.xy.x < 4 && gl_FragCoord.xy.y < 4) {
-        //debugPrintfEXT("\n%f %d %d", span, depth, loc_info[loc_idx].parent_list[0]);
-    }
-
-    len = 50.0;
-
-    float max_len = len;
-    float dist = 0.0;
-
-    vec2 base_pos = vec2(0); // todo
-    // vec2 max_pos = vec2(TEXTURE_ALIGN, TEXTURE_ALIGN + (TEXTURE_ALIGN * TEXTURE_ALIGN)) + base_pos;
-
-    vec4 col = texelFetch(brick_texture, ivec2(pos_to_px(pos_on_edge)), 0);
-
-    bool out_parent = false;
+    // Fragment is on the surface of the cube, keep the start inside of it
+    vec3 local_pos = clamp(world_pos - pos_on_edge.xyz, vec3(0), vec3(span * 0.9999));
 
     for (uint iter = 0; iter < MAX_STEP; iter += 1) {
-        if (col.w == 0.0) {
-            frag_color = vec4(0, 1, 0, 0);
+        if (any(lessThan(local_pos, vec3(0))) || any(greaterThanEqual(local_pos, vec3(span)))) {
             return;
         }
 
-        hit = rayCubeIntersect(local_pos, ray.dir, ray.inv_ray_dir, 1.0);
-        hit_mask_vec = vec3(lessThan(hit, min(hit.yzx, hit.zxy)));
-
-        len = dot(hit, hit_mask_vec);
-
-        vec3 local_pos_on_edge = hit_mask_vec * sign(ray.dir);
+        NodeHit node_hit = find_node(cube_idx, local_pos, span);
 
-        local_pos += ray.dir * len - local_pos_on_edge;
-        pos_on_edge += local_pos_on_edge;
+        if (is_leaf(node_hit.node)) {
+            Material material = material_data[node_hit.idx];
 
-        dist += len;
+            // simple shading based on view direction
+            float diffuse = mix(0.5, 1.0, dot(normalize(vec3(1, 2, 3)), -ray.dir) * 0.5 + 0.5);
+            frag_color = vec4(material.albedo.rgb * diffuse + material.emission.rgb, material.albedo.a);
+            return;
+        }
 
-        local_pos += len * ray.dir;
+        // Skip the empty node, step a bit further to end up in the next one
+        vec3 hit = rayCubeIntersect(local_pos - node_hit.pos_on_edge, ray.dir, ray.inv_ray_dir, node_hit.span);
+        float len = min(hit.x, min(hit.y, hit.z));
 
-        col = texelFetch(brick_texture, ivec2(pos_to_px(pos_on_edge)), 0);
-        out_parent = dist > max_len;
+        local_pos += ray.dir * (len + node_hit.span * 0.001);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,260 @@
+use std::{error::Error, fs, path::Path, time::Duration};
+
+use crate::Pref;
+
+// First frames are not counted, pipelines and caches are still warming up
+pub const BENCH_WARMUP_FRAMES: usize = 10;
+
+/// Statistics of a list of frame times in milliseconds,
+/// percentiles are nearest rank.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameStats {
+    pub min: f64,
+    pub avg: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl FrameStats {
+    pub fn from_list(time_list: &[Duration]) -> Option<Self> {
+        if time_list.is_empty() {
+            return None;
+        }
+
+        let mut ms_list: Vec<f64> = time_list
+            .iter()
+            .map(|time| time.as_secs_f64() * 1000.0)
+            .collect();
+        ms_list.sort_by(f64::total_cmp);
+
+        let percentile = |factor: f64| {
+            let rank = (factor * ms_list.len() as f64).ceil() as usize;
+            ms_list[rank.clamp(1, ms_list.len()) - 1]
+        };
+
+        Some(Self {
+            min: ms_list[0],
+            avg: ms_list.iter().sum::<f64>() / ms_list.len() as f64,
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+        })
+    }
+
+    pub fn to_json(self) -> String {
+        format!(
+            "{{ \"min\": {:.4}, \"avg\": {:.4}, \"p95\": {:.4}, \"p99\": {:.4} }}",
+            self.min, self.avg, self.p95, self.p99
+        )
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+
+    for char in value.chars() {
+        match char {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            char if (char as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", char as u32)),
+            char => json.push(char),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+fn json_option<T, Function: Fn(&T) -> String>(value: &Option<T>, to_json: Function) -> String {
+    value.as_ref().map_or("null".to_string(), to_json)
+}
+
+/// Frame times of a benchmark run. The gpu time of a frame is only read
+/// before the next draw, so gpu times are pushed one frame late and the
+/// one of the last frame after the loop.
+pub struct Bench {
+    pub frame_count: u32,
+    pub cpu_time_list: Vec<Duration>,
+    pub gpu_time_list: Vec<Duration>,
+
+    warmup_left: usize,
+    gpu_warmup_left: usize,
+}
+
+impl Bench {
+    pub fn new(frame_count: u32) -> Self {
+        Self {
+            frame_count,
+            cpu_time_list: vec![],
+            gpu_time_list: vec![],
+
+            warmup_left: BENCH_WARMUP_FRAMES,
+            gpu_warmup_left: BENCH_WARMUP_FRAMES,
+        }
+    }
+
+    pub fn push(&mut self, cpu_time: Duration) {
+        if self.warmup_left > 0 {
+            self.warmup_left -= 1;
+            return;
+        }
+
+        self.cpu_time_list.push(cpu_time);
+    }
+
+    /// Gpu time of the oldest pushed frame without one.
+    pub fn push_gpu(&mut self, gpu_time: Duration) {
+        if self.gpu_warmup_left > 0 {
+            self.gpu_warmup_left -= 1;
+            return;
+        }
+
+        if self.gpu_time_list.len() < self.cpu_time_list.len() {
+            self.gpu_time_list.push(gpu_time);
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.cpu_time_list.len() >= self.frame_count as usize
+    }
+
+    /// Stats together with the settings that change the result,
+    /// so runs with different scenes or traversals can be compared.
+    pub fn to_json(&self, pref: &Pref) -> String {
+        let field_list = [
+            ("frame_count", self.cpu_time_list.len().to_string()),
+            ("scene", json_string(&format!("{:?}", pref.scene))),
+            ("seed", pref.seed.to_string()),
+            (
+                "scene_file",
+                json_option(&pref.scene_file, |path| {
+                    json_string(&path.to_string_lossy())
+                }),
+            ),
+            (
+                "replay_file",
+                json_option(&pref.replay_file, |path| {
+                    json_string(&path.to_string_lossy())
+                }),
+            ),
+            (
+                "render_res",
+                format!("[{}, {}]", pref.render_res.width, pref.render_res.height),
+            ),
+            (
+                "lod_detail",
+                json_option(&pref.lod_detail, |detail| detail.to_string()),
+            ),
+            (
+                "lod_depth",
+                json_option(&pref.lod_depth, |depth| depth.to_string()),
+            ),
+            ("use_dag", pref.use_dag.to_string()),
+            (
+                "cpu_frame_ms",
+                json_option(&FrameStats::from_list(&self.cpu_time_list), |stats| {
+                    stats.to_json()
+                }),
+            ),
+            (
+                "gpu_frame_ms",
+                json_option(&FrameStats::from_list(&self.gpu_time_list), |stats| {
+                    stats.to_json()
+                }),
+            ),
+        ];
+
+        let field_list: Vec<String> = field_list
+            .iter()
+            .map(|(key, value)| format!("  \"{}\": {}", key, value))
+            .collect();
+
+        format!("{{\n{}\n}}\n", field_list.join(",\n"))
+    }
+
+    pub fn save(&self, pref: &Pref, path: &Path) -> Result<(), Box<dyn Error>> {
+        log::info!(
+            "Saving bench stats of {} frames to {} ...",
+            self.cpu_time_list.len(),
+            path.display()
+        );
+
+        Ok(fs::write(path, self.to_json(pref))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::{json_string, Bench, FrameStats, BENCH_WARMUP_FRAMES};
+    use crate::Pref;
+
+    #[test]
+    fn stats_of_frame_times() {
+        let time_list: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        let stats = FrameStats::from_list(&time_list).unwrap();
+
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.avg, 50.5);
+        assert_eq!(stats.p95, 95.0);
+        assert_eq!(stats.p99, 99.0);
+
+        let stats = FrameStats::from_list(&[Duration::from_millis(4)]).unwrap();
+        assert_eq!((stats.min, stats.p99), (4.0, 4.0));
+        assert!(FrameStats::from_list(&[]).is_none());
+    }
+
+    #[test]
+    fn bench_skips_warmup() {
+        let mut bench = Bench::new(5);
+
+        // Gpu time of frame idx arrives with the draw of frame idx + 1
+        for idx in 0..BENCH_WARMUP_FRAMES + 4 {
+            if idx > 0 {
+                bench.push_gpu(Duration::from_millis(100 + idx as u64 - 1));
+            }
+            bench.push(Duration::from_millis(idx as u64));
+        }
+        assert!(!bench.is_done());
+
+        bench.push_gpu(Duration::from_millis(100 + BENCH_WARMUP_FRAMES as u64 + 3));
+        bench.push(Duration::from_millis(1));
+        assert!(bench.is_done());
+        assert_eq!(
+            bench.cpu_time_list[0],
+            Duration::from_millis(BENCH_WARMUP_FRAMES as u64)
+        );
+        assert_eq!(bench.gpu_time_list.len(), 4);
+
+        // Last frame is read after the loop, frames are paired by idx
+        bench.push_gpu(Duration::from_millis(200));
+        bench.push_gpu(Duration::from_millis(300));
+        assert_eq!(bench.gpu_time_list.len(), 5);
+        assert_eq!(
+            bench.gpu_time_list[0],
+            Duration::from_millis(100 + BENCH_WARMUP_FRAMES as u64)
+        );
+        assert_eq!(bench.gpu_time_list[4], Duration::from_millis(200));
+    }
+
+    #[test]
+    fn bench_json() {
+        let mut bench = Bench::new(1);
+        bench.push(Duration::from_millis(2));
+
+        let pref = Pref {
+            scene_file: Some(PathBuf::from("dir\\\"scene\".vox")),
+
+            ..Default::default()
+        };
+        let json = bench.to_json(&pref);
+
+        assert!(json.contains("\"frame_count\": 0,"));
+        assert!(json.contains("\"scene\": \"TEST\","));
+        assert!(json.contains("\"scene_file\": \"dir\\\\\\\"scene\\\".vox\","));
+        assert!(json.contains("\"gpu_frame_ms\": null\n"));
+
+        assert_eq!(json_string("a\tb"), "\"a\\u0009b\"");
+    }
+}
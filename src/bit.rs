@@ -62,9 +62,15 @@ macro_rules! create_mask {
         // Create mask of the range
         // For Example
         // mask = 0000 0000 1111 1111 0000 0000 0000 0000
-
-        let len = $e - $s;
-        (!0u32 >> (32 - len)) << $s
+        // Checked shifts, so an empty range is 0 and
+        // a range over all 32 bits does not overflow
+
+        let len: u32 = $e - $s;
+        (!0u32)
+            .checked_shr(32 - len)
+            .unwrap_or(0)
+            .checked_shl($s)
+            .unwrap_or(0)
     }};
 }
 
@@ -115,3 +121,91 @@ macro_rules! vec_to_mask {
         (x_bit << 0) | (y_bit << 1) | (z_bit << 2)
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn create_mask_edges() {
+        assert_eq!(create_mask!(0, 0), 0);
+        assert_eq!(create_mask!(7, 7), 0);
+        assert_eq!(create_mask!(32, 32), 0);
+        assert_eq!(create_mask!(0, 32), !0);
+        assert_eq!(create_mask!(31, 32), 1 << 31);
+        assert_eq!(create_mask!(0, 1), 1);
+        assert_eq!(create_mask!(16, 24), 0x00FF_0000);
+    }
+
+    #[test]
+    fn create_mask_every_range() {
+        for s in 0..=32u32 {
+            for e in s..=32u32 {
+                let mask = create_mask!(s, e);
+
+                assert_eq!(mask.count_ones(), e - s);
+                (0..32).for_each(|bit| assert_eq!(bitcheck!(mask, bit), bit >= s && bit < e));
+            }
+        }
+    }
+
+    #[test]
+    fn bitrange_round_trip() {
+        let mut rng = StdRng::seed_from_u64(14);
+
+        for s in 0..32u32 {
+            for e in s..32u32 {
+                let mask = create_mask!(s, e + 1);
+
+                for _ in 0..16 {
+                    let num: u32 = rng.gen();
+                    let value: u32 = rng.gen::<u32>() & (mask >> s);
+                    let written = write_bitrange!(num, value, s, e);
+
+                    // Range holds the value, everything else is untouched
+                    assert_eq!(read_bitrange!(written, s, e), value);
+                    assert_eq!(written & !mask, num & !mask);
+                    assert_eq!(read_bitrange!(num, s, e), (num & mask) >> s);
+
+                    // Bits above the range are cut off
+                    assert_eq!(
+                        write_bitrange!(num, value | !(mask >> s), s, e) & !mask,
+                        num & !mask
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_bit() {
+        let mut rng = StdRng::seed_from_u64(15);
+
+        for _ in 0..64 {
+            let num: u32 = rng.gen();
+
+            for bit in 0..32u32 {
+                assert!(bitcheck!(bitset!(num, bit), bit));
+                assert!(!bitcheck!(bitclear!(num, bit), bit));
+                assert_eq!(bitcheck!(bitflip!(num, bit), bit), !bitcheck!(num, bit));
+                assert_eq!(set_bit!(num, bit, true), bitset!(num, bit));
+                assert_eq!(set_bit!(num, bit, false), bitclear!(num, bit));
+                assert_eq!(bitflip!(bitflip!(num, bit), bit), num);
+            }
+        }
+    }
+
+    #[test]
+    fn mask_vec_round_trip() {
+        for mask in 0..8u32 {
+            let vec = mask_to_vec!(mask);
+
+            assert_eq!(vec.w, 0.0);
+            assert_eq!(vec_to_mask!(vec), mask);
+            assert_eq!(
+                vec_to_mask!(vec * 2.0 - nalgebra_glm::Vec4::repeat(1.0)),
+                mask
+            );
+        }
+    }
+}
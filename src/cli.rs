@@ -0,0 +1,238 @@
+use std::{
+    error::Error,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use crate::{pref::Pref, tree::gen::SceneKind};
+
+pub const USAGE: &str = "\
+Usage: Pathie [command] [options] [--key value ...]
+
+Commands:
+  view [scene]                  Open the window, the default command
+  render [scene] -o <file>      Render one image and exit, --cpu renders without vulkan
+  bench [scene] -o <file>       Draw bench_frames frames along replay_file or an orbit,
+                                write frame time stats as json and exit
+  convert <scene> <file>        Save the scene as octree file or .vox
+  info [scene]                  Print node counts and depth statistics
+  help                          Print this text
+
+A scene is a generated scene (TEST, TERRAIN, CAVES, PRIMITIVES) or a
+scene file (octree file, .vox, .obj or .stl). Without scene the scene
+of the pref file is used. Every key of the pref file can be set with
+--key value, the pref file itself with --pref <file>.";
+
+/// What the binary does, the scene and all other settings are in pref.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    VIEW,
+    RENDER,
+    BENCH,
+    CONVERT { out_file: PathBuf },
+    INFO,
+    HELP,
+}
+
+// Flags of the command line that are not pref keys
+const SHORT_FLAG_LIST: [&str; 2] = ["-o", "-h"];
+
+fn invalid_input(msg: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(ErrorKind::InvalidInput, msg))
+}
+
+/// Names of generated scenes are used as scene, everything else as scene file.
+fn scene_arg(scene: &str) -> String {
+    if SceneKind::from_str(scene).is_ok() {
+        format!("--scene={}", scene)
+    } else {
+        format!("--scene_file={}", scene)
+    }
+}
+
+/// Split the command line into command and pref. Scene, output file and
+/// the render kind are turned into pref arguments, so they override the
+/// pref file like any other argument.
+pub fn parse_args<Args: IntoIterator<Item = String>>(
+    arg_list: Args,
+) -> Result<(Command, Pref), Box<dyn Error>> {
+    let mut positional_list = vec![];
+    let mut pref_arg_list = vec![];
+    let mut out_file = None;
+    let mut use_cpu = false;
+
+    let mut arg_list = arg_list.into_iter().peekable();
+    while let Some(arg) = arg_list.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok((Command::HELP, Pref::default())),
+            "-o" | "--out" => {
+                out_file = Some(
+                    arg_list
+                        .next()
+                        .ok_or_else(|| invalid_input("ERR_CLI_NO_OUT_FILE".to_string()))?,
+                )
+            }
+            "--cpu" => use_cpu = true,
+            _ if arg.starts_with("--") => {
+                // Same rule as in Pref::from_args, the value is the next arg,
+                // so negative values are kept. Only short flags are no value
+                let has_value = !arg.contains('=')
+                    && arg_list.peek().is_some_and(|next| {
+                        !next.starts_with("--") && !SHORT_FLAG_LIST.contains(&next.as_str())
+                    });
+
+                pref_arg_list.push(arg);
+                if has_value {
+                    pref_arg_list.extend(arg_list.next());
+                }
+            }
+            _ => positional_list.push(arg),
+        }
+    }
+
+    let (name, scene_list) = match positional_list.split_first() {
+        Some((name, scene_list)) => (name.as_str(), scene_list),
+        None => ("view", &[][..]),
+    };
+
+    let has_out_file = matches!(name, "render" | "bench");
+    if (use_cpu && name != "render") || (out_file.is_some() && !has_out_file) {
+        return Err(invalid_input(format!("ERR_CLI_OUT_FILE -> {}", name)));
+    }
+
+    let command = match (name, scene_list) {
+        ("view" | "render" | "bench" | "info", [scene]) | ("convert", [scene, _]) => {
+            pref_arg_list.push(scene_arg(scene));
+
+            match name {
+                "view" => Command::VIEW,
+                "render" => Command::RENDER,
+                "bench" => Command::BENCH,
+                "info" => Command::INFO,
+                _ => Command::CONVERT {
+                    out_file: PathBuf::from(&scene_list[1]),
+                },
+            }
+        }
+        ("view", []) => Command::VIEW,
+        ("render", []) => Command::RENDER,
+        ("bench", []) => Command::BENCH,
+        ("info", []) => Command::INFO,
+        ("help", []) => Command::HELP,
+
+        _ => {
+            return Err(invalid_input(format!(
+                "ERR_CLI_ARGS -> {}",
+                positional_list.join(" ")
+            )))
+        }
+    };
+
+    // Output files of the pref file must not turn view into a render or
+    // bench, only the one of the command is kept
+    let out_key = match &command {
+        Command::VIEW => None,
+        Command::RENDER if use_cpu => Some("cpu_render_file"),
+        Command::RENDER => Some("offscreen_file"),
+        Command::BENCH => Some("bench_file"),
+        _ => return Ok((command, Pref::from_args(pref_arg_list)?)),
+    };
+
+    if let Some(out_key) = out_key {
+        let out_file = out_file.ok_or_else(|| invalid_input("ERR_CLI_NO_OUT_FILE".to_string()))?;
+        pref_arg_list.push(format!("--{}={}", out_key, out_file));
+    }
+
+    for key in ["cpu_render_file", "offscreen_file", "bench_file"] {
+        if Some(key) != out_key {
+            pref_arg_list.push(format!("--{}=none", key));
+        }
+    }
+
+    Ok((command, Pref::from_args(pref_arg_list)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{parse_args, Command};
+    use crate::tree::gen::SceneKind;
+
+    fn arg_list(arg_list: &str) -> Vec<String> {
+        arg_list
+            .split_whitespace()
+            .map(|arg| arg.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn parse_commands() {
+        let (command, pref) = parse_args(arg_list("--seed 3")).unwrap();
+        assert_eq!(command, Command::VIEW);
+        assert_eq!(pref.seed, 3);
+
+        let (command, pref) = parse_args(arg_list("view TERRAIN --use_dag")).unwrap();
+        assert_eq!(command, Command::VIEW);
+        assert_eq!(pref.scene, SceneKind::TERRAIN);
+        assert!(pref.use_dag);
+
+        let (command, pref) = parse_args(arg_list("render CAVES --use_dag -o out.png")).unwrap();
+        assert_eq!(command, Command::RENDER);
+        assert!(pref.use_dag);
+        assert_eq!(pref.offscreen_file, Some(PathBuf::from("out.png")));
+        assert_eq!(pref.cpu_render_file, None);
+
+        let (_, pref) = parse_args(arg_list("render -o out.png --cpu")).unwrap();
+        assert_eq!(pref.cpu_render_file, Some(PathBuf::from("out.png")));
+        assert_eq!(pref.offscreen_file, None);
+
+        let (command, pref) = parse_args(arg_list("bench TERRAIN -o stats.json")).unwrap();
+        assert_eq!(command, Command::BENCH);
+        assert_eq!(pref.bench_file, Some(PathBuf::from("stats.json")));
+        assert_eq!(pref.offscreen_file, None);
+
+        let (command, pref) = parse_args(arg_list("convert PRIMITIVES scene.oct")).unwrap();
+        assert_eq!(
+            command,
+            Command::CONVERT {
+                out_file: PathBuf::from("scene.oct")
+            }
+        );
+        assert_eq!(pref.scene, SceneKind::PRIMITIVES);
+
+        assert_eq!(parse_args(arg_list("info")).unwrap().0, Command::INFO);
+        assert_eq!(parse_args(arg_list("info -h")).unwrap().0, Command::HELP);
+    }
+
+    #[test]
+    fn reject_bad_commands() {
+        for args in [
+            "draw",
+            "render TEST",
+            "render TEST -o",
+            "view -o out.png",
+            "bench TEST --cpu -o stats.json",
+            "convert TEST",
+            "info TEST TERRAIN",
+            "info missing.vox",
+        ] {
+            assert!(parse_args(arg_list(args)).is_err(), "{}", args);
+        }
+    }
+
+    #[test]
+    fn negative_value_is_no_flag() {
+        // Value reaches the pref check instead of becoming a scene
+        let err = parse_args(arg_list("view --mov_speed -0.2")).err().unwrap();
+        assert!(err.to_string().contains("mov_speed must be positive"), "{}", err);
+
+        let (command, pref) = parse_args(arg_list("render --use_dag -o out.png")).unwrap();
+        assert_eq!(command, Command::RENDER);
+        assert!(pref.use_dag);
+
+        assert_eq!(parse_args(arg_list("view --use_dag -h")).unwrap().0, Command::HELP);
+    }
+}
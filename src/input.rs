@@ -1,12 +1,32 @@
-use nalgebra_glm::Vec2;
+use std::{
+    error::Error,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    str::FromStr,
+};
+
+use nalgebra_glm::{cross, normalize, vec3_to_vec4, Vec2, Vec4};
+use strum_macros::{Display, EnumString};
 use winit::{
     dpi::PhysicalPosition,
-    event::{ElementState, VirtualKeyCode},
+    event::{ElementState, MouseButton, VirtualKeyCode},
+    window::Fullscreen,
 };
 
-use crate::{interface::interface::Interface, uniform::Uniform, tree::octree::Octree, Pref};
+use crate::{
+    interface::interface::Interface,
+    tree::{
+        material::Material,
+        octree::Octree,
+        trace::{Hit, Ray},
+    },
+    uniform::Uniform,
+    Pref,
+};
 
-#[derive(PartialEq, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq, Clone, Copy, Debug, Display, EnumString)]
 pub enum Action {
     NONE,
 
@@ -22,11 +42,85 @@ pub enum Action {
     ESCAPE,
 
     RESET,
+
+    PLACE,
+    REMOVE,
 }
 
 pub struct Input {
     pub binding_list: [Action; 256],
     pub key_down: [bool; 256],
+
+    // Left, right and middle button
+    pub mouse_binding_list: [Action; 3],
+    // Material of placed voxel
+    pub place_material: Material,
+}
+
+// Mov speed is multiplied with this while SHIFT is down
+const SPRINT_FACTOR: f32 = 6.0;
+
+// Keys that can be bound in a binding file, named like the VirtualKeyCode
+const KEY_LIST: [VirtualKeyCode; 80] = {
+    use VirtualKeyCode::*;
+
+    [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+        F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+        Up, Down, Left, Right,
+        Escape, Tab, Space, Return, Back, Delete, Insert, Home, End, PageUp, PageDown,
+        LShift, RShift, LControl, RControl, LAlt, RAlt,
+        Comma, Period, Minus, Equals, Slash, Backslash, Semicolon, Apostrophe, Grave,
+        LBracket, RBracket,
+    ]
+};
+
+fn invalid_data(msg: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    KEY_LIST
+        .iter()
+        .find(|key| format!("{:?}", key) == name)
+        .copied()
+}
+
+/// Ray from the cam along the look dir into the octree, the cursor is
+/// always centered, so this is the voxel under the crosshair.
+pub fn pick(octree: &Octree, uniform: &Uniform) -> Option<Hit> {
+    let ray = Ray {
+        origin: uniform.cam_pos,
+        dir: uniform.look_dir,
+    };
+
+    octree.cast_ray(&ray, octree.root_span * 2.0)
+}
+
+/// Insert a leaf next to the hit face. Return None if the cam is inside
+/// the hit leaf or the new leaf would be outside of the root.
+pub fn place_voxel(octree: &mut Octree, hit: &Hit, material: Material) -> Option<Vec4> {
+    if hit.normal == Vec4::zeros() {
+        return None;
+    }
+
+    let pos = hit.pos + hit.normal * octree.leaf_span() * 0.5;
+    if pos.xyz().iter().any(|&value| value < 0.0 || value >= octree.root_span) {
+        return None;
+    }
+
+    octree.insert_node(pos, material);
+
+    Some(pos)
+}
+
+/// Remove the leaf span cube behind the hit face. A coarser hit leaf is
+/// subdivided first, see Octree::remove_node. Return None if nothing is there.
+pub fn remove_voxel(octree: &mut Octree, hit: &Hit) -> Option<Vec4> {
+    let pos = hit.pos - hit.normal * octree.leaf_span() * 0.5;
+
+    octree.remove_node(pos).map(|_| pos)
 }
 
 impl Input {
@@ -46,55 +140,195 @@ impl Input {
 
         binding_list[VirtualKeyCode::R as usize] = Action::RESET;
 
-        Input { binding_list, key_down: [false; 256] }
+        let mouse_binding_list = [Action::REMOVE, Action::PLACE, Action::NONE];
+
+        Input {
+            binding_list,
+            key_down: [false; 256],
+            mouse_binding_list,
+            place_material: Material::default(),
+        }
     }
 
+    /// Read a binding file, see parse_bindings.
+    pub fn load_bindings(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        log::info!("Reading binding file {} ...", path.display());
+
+        self.parse_bindings(&fs::read_to_string(path)?)
+    }
+
+    /// Bind keys with lines like "W = FORWARD", keys are named like the
+    /// VirtualKeyCode, mouse buttons are MouseLeft, MouseRight and MouseMiddle.
+    /// Only listed keys change, "W = NONE" removes the default of a key.
+    /// Everything after # is a comment.
+    pub fn parse_bindings(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, action) = line.split_once('=').ok_or_else(|| {
+                invalid_data(format!("ERR_BINDING_LINE -> {}", line_idx + 1))
+            })?;
+            let (key, action) = (key.trim(), action.trim());
+
+            let action = Action::from_str(action).map_err(|_| {
+                invalid_data(format!("ERR_BINDING_ACTION -> {} {}", line_idx + 1, action))
+            })?;
+
+            match key {
+                "MouseLeft" => self.mouse_binding_list[0] = action,
+                "MouseRight" => self.mouse_binding_list[1] = action,
+                "MouseMiddle" => self.mouse_binding_list[2] = action,
+                _ => {
+                    let keycode = key_from_name(key).ok_or_else(|| {
+                        invalid_data(format!("ERR_BINDING_KEY -> {} {}", line_idx + 1, key))
+                    })?;
+
+                    self.binding_list[keycode as usize] = action;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// True while any key bound to the action is down.
+    pub fn is_down(&self, action: Action) -> bool {
+        self.binding_list
+            .iter()
+            .zip(self.key_down.iter())
+            .any(|(&binding, &down)| down && binding == action)
+    }
+
+    /// Track key state and run the actions that happen once per press.
+    /// Held actions like movement are applied every frame, see apply_movement.
     pub fn handle_key_input(
         &mut self,
         keycode: &VirtualKeyCode,
         state: &ElementState,
         uniform: &mut Uniform,
-        pref: &Pref,
-        octree: &Octree,
         interface: &Interface,
     ) {
-        if state == &ElementState::Pressed {
-            self.key_down[*keycode as usize] = true;
-            /*
-            match self.binding_list[*keycode as usize] {
-                Action::FORWARD => uniform.velocity += nalgebra_glm::normalize(&uniform.look_dir) * pref.mov_speed,
-                Action::BACKWARD => uniform.velocity -= nalgebra_glm::normalize(&uniform.look_dir) * pref.mov_speed,
-                Action::LEFT => uniform.velocity -= normalize(&cross(&nalgebra_glm::normalize(&uniform.look_dir), &uniform.cam_up)) * pref.mov_speed,
-                Action::RIGHT => uniform.velocity += normalize(&cross(&nalgebra_glm::normalize(&uniform.look_dir), &uniform.cam_up)) * pref.mov_speed,
-
-                // Action::JUMP => uniform.apply_velocity(Vector3::new(0.0, MOVEMENT_INC, 0.0), octree),
-                // Action::SHIFT => uniform.apply_velocity(Vector3::new(0.0, -MOVEMENT_INC, 0.0), octree),
-
-                Action::FULLSCREEN => interface.window.set_fullscreen(Some(Fullscreen::Exclusive(
-                    interface
-                        .monitor
-                        .video_modes()
-                        .next()
-                        .expect("ERR_NO_MONITOR_MODE")
-                        .clone(),
-                ))),
-                Action::ESCAPE => interface.window.set_fullscreen(None),
-                Action::RESET => interface
-                    .window
-                    .set_cursor_position(PhysicalPosition::new(
-                        uniform.res.x / 2.0,
-                        uniform.res.x / 2.0,
-                    ))
-                    .unwrap(),
-
-                _ => (),
+        let key_idx = *keycode as usize;
+
+        if state == &ElementState::Released {
+            self.key_down[key_idx] = false;
+            return;
+        }
+
+        // Pressed is repeated while the key is held
+        if self.key_down[key_idx] {
+            return;
+        }
+        self.key_down[key_idx] = true;
+
+        match self.binding_list[key_idx] {
+            Action::FULLSCREEN => interface
+                .window()
+                .set_fullscreen(Some(Fullscreen::Borderless(interface.monitor.clone()))),
+            Action::ESCAPE => interface.window().set_fullscreen(None),
+            Action::RESET => {
+                *uniform = Uniform {
+                    res: uniform.res,
+
+                    ..Uniform::new(uniform.root_span)
+                };
+                uniform.move_mouse(Vec2::zeros());
             }
-            */
-        } else if state == &ElementState::Released {
-            self.key_down[*keycode as usize] = false;
+
+            _ => (),
         }
     }
 
+    /// Move the cam for every held movement action. JUMP moves up along
+    /// cam up, SHIFT multiplies the speed.
+    pub fn apply_movement(&self, uniform: &mut Uniform, pref: &Pref) {
+        let look_dir = normalize(&uniform.look_dir);
+        let side_dir = vec3_to_vec4(&normalize(&cross(
+            &look_dir.xyz(),
+            &uniform.cam_up.xyz(),
+        )));
+
+        let mut dir = Vec4::zeros();
+        if self.is_down(Action::FORWARD) {
+            dir += look_dir;
+        }
+        if self.is_down(Action::BACKWARD) {
+            dir -= look_dir;
+        }
+        if self.is_down(Action::LEFT) {
+            dir -= side_dir;
+        }
+        if self.is_down(Action::RIGHT) {
+            dir += side_dir;
+        }
+        if self.is_down(Action::JUMP) {
+            dir += uniform.cam_up;
+        }
+
+        if dir == Vec4::zeros() {
+            return;
+        }
+
+        let speed = if self.is_down(Action::SHIFT) {
+            pref.mov_speed * SPRINT_FACTOR
+        } else {
+            pref.mov_speed
+        };
+
+        uniform.velocity += dir * speed;
+        uniform.apply_velocity();
+    }
+
+    /// Place or remove the voxel under the crosshair, depending on the
+    /// action of the button. The upload happens with the next frame
+    /// through the dirty ranges of the octree. Return true if it changed.
+    pub fn handle_mouse_button(
+        &mut self,
+        button: &MouseButton,
+        state: &ElementState,
+        uniform: &Uniform,
+        octree: &mut Octree,
+    ) -> bool {
+        let button_idx = match button {
+            MouseButton::Left => 0,
+            MouseButton::Right => 1,
+            MouseButton::Middle => 2,
+            MouseButton::Other(_) => return false,
+        };
+
+        if state != &ElementState::Pressed {
+            return false;
+        }
+
+        let action = self.mouse_binding_list[button_idx];
+        if action != Action::PLACE && action != Action::REMOVE {
+            return false;
+        }
+
+        let Some(hit) = pick(octree, uniform) else {
+            return false;
+        };
+
+        let edit_pos = match action {
+            Action::PLACE => place_voxel(octree, &hit, self.place_material),
+            _ => remove_voxel(octree, &hit),
+        };
+
+        if let Some(pos) = edit_pos {
+            log::info!(
+                "Edit at [ {} {} {} ] ...",
+                pos.x,
+                pos.y,
+                pos.z
+            );
+        }
+
+        edit_pos.is_some()
+    }
+
     pub fn handle_mouse_input(&self, position: PhysicalPosition<f64>, uniform: &mut Uniform) {
         let mouse_pos = Vec2::new(position.x as f32, position.y as f32);
         let mouse_delta = mouse_pos - uniform.res / 2.0;
@@ -102,3 +336,115 @@ impl Input {
         uniform.move_mouse(mouse_delta);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+
+    use winit::event::VirtualKeyCode;
+
+    use super::{pick, place_voxel, remove_voxel, Action, Input};
+    use crate::{
+        tree::{material::Material, octree::Octree},
+        uniform::Uniform,
+        Pref,
+    };
+
+    #[test]
+    fn parse_binding_file() {
+        let mut input = Input::new();
+
+        input
+            .parse_bindings(
+                "# Arrow keys instead of wasd\n\
+                 Up = FORWARD\n\
+                 W = NONE   # unbind\n\
+                 \n\
+                 Key1 = FULLSCREEN\n\
+                 MouseMiddle = PLACE\n",
+            )
+            .unwrap();
+
+        assert!(input.binding_list[VirtualKeyCode::Up as usize] == Action::FORWARD);
+        assert!(input.binding_list[VirtualKeyCode::W as usize] == Action::NONE);
+        assert!(input.binding_list[VirtualKeyCode::Key1 as usize] == Action::FULLSCREEN);
+        assert!(input.binding_list[VirtualKeyCode::S as usize] == Action::BACKWARD);
+        assert!(input.mouse_binding_list[2] == Action::PLACE);
+
+        assert!(input.parse_bindings("W FORWARD").is_err());
+        assert!(input.parse_bindings("W = FLY").is_err());
+        assert!(input.parse_bindings("Foo = FORWARD").is_err());
+    }
+
+    #[test]
+    fn movement_follows_bindings() {
+        let mut input = Input::new();
+        input.parse_bindings("Up = FORWARD").unwrap();
+        let pref = Pref::default();
+
+        let mut uniform = Uniform::new(256.0);
+        uniform.look_at(Vec4::new(0.0, 0.0, 1.0, 0.0));
+
+        input.key_down[VirtualKeyCode::Up as usize] = true;
+        input.apply_movement(&mut uniform, &pref);
+        assert!((uniform.cam_pos.z - pref.mov_speed).abs() < 1e-6);
+
+        // Forward and backward cancel, sprint jump moves up only
+        input.key_down[VirtualKeyCode::S as usize] = true;
+        input.key_down[VirtualKeyCode::Space as usize] = true;
+        input.key_down[VirtualKeyCode::LShift as usize] = true;
+        assert!(input.is_down(Action::SHIFT));
+        input.apply_movement(&mut uniform, &pref);
+        assert!((uniform.cam_pos.y - pref.mov_speed * 6.0).abs() < 1e-6);
+        assert!((uniform.cam_pos.z - pref.mov_speed).abs() < 1e-6);
+    }
+
+    #[test]
+    fn place_and_remove_under_crosshair() {
+        let mut octree = Octree::default();
+        let material = Material::from_color(Vec4::new(0.0, 1.0, 0.0, 1.0));
+        octree.insert_node(Vec4::new(100.0, 100.0, 100.0, 0.0), Material::default());
+
+        let mut uniform = Uniform::new(octree.root_span);
+        uniform.cam_pos = Vec4::new(101.0, 101.0, 10.0, 0.0);
+        uniform.look_at(Vec4::new(101.0, 101.0, 101.0, 0.0));
+
+        // New voxel is in front of the hit face
+        let hit = pick(&octree, &uniform).unwrap();
+        assert_eq!(hit.pos.z, 100.0);
+        assert_eq!(place_voxel(&mut octree, &hit, material).unwrap().z, 99.0);
+
+        let hit = pick(&octree, &uniform).unwrap();
+        assert_eq!(hit.pos.z, 98.0);
+        assert_eq!(octree.material_data[hit.idx as usize], material);
+
+        // Remove both again
+        assert!(remove_voxel(&mut octree, &hit).is_some());
+        let hit = pick(&octree, &uniform).unwrap();
+        assert_eq!(hit.pos.z, 100.0);
+        assert!(remove_voxel(&mut octree, &hit).is_some());
+        assert!(pick(&octree, &uniform).is_none());
+    }
+
+    #[test]
+    fn place_outside_of_root() {
+        let mut octree = Octree::default();
+        octree.insert_node(Vec4::new(1.0, 1.0, 1.0, 0.0), Material::default());
+
+        let mut uniform = Uniform::new(octree.root_span);
+        uniform.cam_pos = Vec4::new(1.0, 1.0, 50.0, 0.0);
+        uniform.look_at(Vec4::new(1.0, 1.0, 1.0, 0.0));
+
+        // Hit the leaf from the far side, there is room in front of it
+        let hit = pick(&octree, &uniform).unwrap();
+        assert_eq!(hit.normal, Vec4::new(0.0, 0.0, 1.0, 0.0));
+        assert!(place_voxel(&mut octree, &hit, Material::default()).is_some());
+
+        // Nothing can be placed below y = 0
+        uniform.cam_pos = Vec4::new(1.0, -20.0, 1.0, 0.0);
+        uniform.look_at(Vec4::new(1.0, 1.0, 1.0, 0.0));
+        let hit = pick(&octree, &uniform).unwrap();
+        assert_eq!(hit.normal, Vec4::new(0.0, -1.0, 0.0, 0.0));
+        assert!(place_voxel(&mut octree, &hit, Material::default()).is_none());
+    }
+}
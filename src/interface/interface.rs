@@ -1,5 +1,10 @@
 use crate::{
-    interface::{phydev::PhyDeviceGroup, surface::SurfaceGroup, swapchain::SwapchainGroup},
+    interface::{
+        phydev::PhyDeviceGroup,
+        surface::SurfaceGroup,
+        swapchain::SwapchainGroup,
+        timestamp::TimestampPool,
+    },
     Pref,
 };
 use ash::{
@@ -12,19 +17,29 @@ use ash::{
 use raw_window_handle::HasRawDisplayHandle;
 use std::{
     error::Error,
-    ffi::{c_void, CStr, CString},
+    ffi::{c_char, c_void, CStr, CString},
+    time::Duration,
+};
+use winit::{
+    event_loop::EventLoop,
+    monitor::MonitorHandle,
+    window::{Window, WindowBuilder},
 };
-use winit::{event_loop::EventLoop, monitor::MonitorHandle, window::WindowBuilder};
 
 pub struct Interface {
+    // Loader, instance and messenger only have to live as long as the device
+    #[allow(dead_code)]
     pub entry: Entry,
+    #[allow(dead_code)]
     pub instance: Instance,
+    #[allow(dead_code)]
     pub debug_util_loader: DebugUtils,
+    #[allow(dead_code)]
     pub debug_call_back: vk::DebugUtilsMessengerEXT,
 
-    pub window: winit::window::Window,
-    pub monitor_list: Vec<MonitorHandle>,
-    pub monitor: MonitorHandle,
+    // None in offscreen mode, then there is no surface and swapchain
+    pub window: Option<Window>,
+    pub monitor: Option<MonitorHandle>,
 
     pub surface: SurfaceGroup,
     pub phy_device: PhyDeviceGroup,
@@ -45,6 +60,10 @@ pub struct Interface {
     pub setup_cmd_fence: vk::Fence,
     pub comp_cmd_fence: vk::Fence,
     pub draw_cmd_fence: vk::Fence,
+
+    // Gpu scopes of the comp and draw cmd buffer, None if the queue has no timestamps
+    pub comp_timestamp_pool: Option<TimestampPool>,
+    pub draw_timestamp_pool: Option<TimestampPool>,
 }
 
 #[macro_export]
@@ -100,14 +119,82 @@ impl Interface {
 
             let entry = Entry::load().unwrap();
 
+            let ext_name_list =
+                ash_window::enumerate_required_extensions(window.raw_display_handle())
+                    .unwrap()
+                    .to_vec();
+
+            let (instance, debug_util_loader, debug_call_back) =
+                Self::create_instance(&entry, pref, ext_name_list);
+
+            let mut surface = SurfaceGroup::new(&entry, &instance, &window);
+
+            log::info!("Creating PhyDevice ...");
+            let phy_device = PhyDeviceGroup::default()
+                .get_phy_device_list(&instance)
+                .get_suitable_phy_device(&instance, Some(&surface))
+                .get_phy_device_prop(&instance);
+
+            log::info!("Load Surface information ...");
+            surface = surface.get_surface_info(&phy_device, &window, pref);
+
+            Self::create_device(
+                entry,
+                instance,
+                debug_util_loader,
+                debug_call_back,
+                Some(window),
+                Some(monitor),
+                surface,
+                phy_device,
+            )
+        }
+    }
+
+    /// Init without window, surface and swapchain. The device only needs
+    /// graphic support, so it also runs on software drivers like lavapipe.
+    /// Images are rendered with render res of pref.
+    pub fn init_offscreen(pref: &Pref) -> Self {
+        unsafe {
+            log::info!("Creating offscreen Interface ...");
+            let entry = Entry::load().unwrap();
+
+            let (instance, debug_util_loader, debug_call_back) =
+                Self::create_instance(&entry, pref, vec![]);
+
+            log::info!("Creating PhyDevice ...");
+            let phy_device = PhyDeviceGroup::default()
+                .get_phy_device_list(&instance)
+                .get_suitable_phy_device(&instance, None)
+                .get_phy_device_prop(&instance);
+
+            let surface = SurfaceGroup::offscreen(&entry, &instance, pref);
+
+            Self::create_device(
+                entry,
+                instance,
+                debug_util_loader,
+                debug_call_back,
+                None,
+                None,
+                surface,
+                phy_device,
+            )
+        }
+    }
+
+    /// Create instance with debug messenger, the debug util extension
+    /// is added to the extension list.
+    fn create_instance(
+        entry: &Entry,
+        pref: &Pref,
+        mut ext_name_list: Vec<*const c_char>,
+    ) -> (Instance, DebugUtils, vk::DebugUtilsMessengerEXT) {
+        unsafe {
             log::info!("Creating VulkanInstance ...");
             let name = CString::new(pref.name.clone()).unwrap();
             let engine_name = CString::new(pref.engine_name.clone()).unwrap();
 
-            let mut ext_name_list =
-                ash_window::enumerate_required_extensions(window.raw_display_handle())
-                    .unwrap()
-                    .to_vec();
             ext_name_list.push(DebugUtils::name().as_ptr());
 
             #[cfg(any(target_os = "macos", target_os = "ios"))]
@@ -162,28 +249,37 @@ impl Interface {
                 )
                 .pfn_user_callback(Some(vulkan_debug_callback));
 
-            let debug_util_loader = DebugUtils::new(&entry, &instance);
+            let debug_util_loader = DebugUtils::new(entry, &instance);
             let debug_call_back = debug_util_loader
                 .create_debug_utils_messenger(&debug_info, None)
                 .unwrap();
 
-            let mut surface = SurfaceGroup::new(&entry, &instance, &window);
-
-            log::info!("Creating PhyDevice ...");
-            let phy_device = PhyDeviceGroup::default()
-                .get_phy_device_list(&instance)
-                .get_suitable_phy_device(&instance, &surface)
-                .get_phy_device_prop(&instance);
-
-            log::info!("Load Surface information ...");
-            surface = surface.get_surface_info(&phy_device, &window, pref);
+            (instance, debug_util_loader, debug_call_back)
+        }
+    }
 
-            let device_ext_list = [
-                Swapchain::name().as_ptr(),
+    /// Create device, queue, swapchain (only with window), command buffer
+    /// and sync objects. Shared by windowed and offscreen init.
+    #[allow(clippy::too_many_arguments)]
+    fn create_device(
+        entry: Entry,
+        instance: Instance,
+        debug_util_loader: DebugUtils,
+        debug_call_back: vk::DebugUtilsMessengerEXT,
+        window: Option<Window>,
+        monitor: Option<MonitorHandle>,
+        surface: SurfaceGroup,
+        phy_device: PhyDeviceGroup,
+    ) -> Self {
+        unsafe {
+            let mut device_ext_list = vec![
                 DynamicRendering::name().as_ptr(),
                 #[cfg(any(target_os = "macos", target_os = "ios",))]
                 KhrPortabilitySubsetFn::name().as_ptr(),
             ];
+            if window.is_some() {
+                device_ext_list.push(Swapchain::name().as_ptr());
+            }
 
             let feature = vk::PhysicalDeviceFeatures {
                 shader_clip_distance: 1,
@@ -210,8 +306,11 @@ impl Interface {
 
             let present_queue = device.get_device_queue(phy_device.queue_family_index, 0);
 
-            log::info!("Creating Swapchain ...");
-            let mut swapchain = SwapchainGroup::new(&instance, &device).create_swapchain(&surface);
+            let mut swapchain = SwapchainGroup::new(&instance, &device);
+            if window.is_some() {
+                log::info!("Creating Swapchain ...");
+                swapchain = swapchain.create_swapchain(&surface);
+            }
 
             log::info!("Creating CommandPool ...");
             let pool_create_info = vk::CommandPoolCreateInfo::builder()
@@ -232,10 +331,12 @@ impl Interface {
 
             let setup_cmd_buffer = command_buffer_list[0];
             let comp_cmd_buffer = command_buffer_list[1];
-            let draw_cmd_buffer = command_buffer_list[1];
+            let draw_cmd_buffer = command_buffer_list[2];
 
-            log::info!("Load PresentImgList ...");
-            swapchain = swapchain.get_present_img(&surface, &device);
+            if window.is_some() {
+                log::info!("Load PresentImgList ...");
+                swapchain = swapchain.get_present_img(&surface, &device);
+            }
 
             log::info!("Init Fence ...");
             let fence_create_info =
@@ -261,6 +362,9 @@ impl Interface {
                 .create_semaphore(&semaphore_create_info, None)
                 .unwrap();
 
+            let comp_timestamp_pool = TimestampPool::new(&instance, &device, &phy_device);
+            let draw_timestamp_pool = TimestampPool::new(&instance, &device, &phy_device);
+
             log::info!("Interface finished ...");
             Interface {
                 entry,
@@ -270,7 +374,6 @@ impl Interface {
                 debug_call_back,
 
                 window,
-                monitor_list,
                 monitor,
 
                 surface,
@@ -293,10 +396,26 @@ impl Interface {
                 setup_cmd_fence,
                 comp_cmd_fence,
                 draw_cmd_fence,
+
+                comp_timestamp_pool,
+                draw_timestamp_pool,
             }
         }
     }
 
+    pub fn window(&self) -> &Window {
+        self.window.as_ref().expect("ERR_NO_WINDOW_IN_OFFSCREEN_MODE")
+    }
+
+    /// Number of image targets, one per swapchain image or a single
+    /// one in offscreen mode.
+    pub fn target_count(&self) -> usize {
+        match self.window {
+            Some(_) => self.swapchain.img_list.len(),
+            None => 1,
+        }
+    }
+
     pub fn swap_draw_next<Function: FnOnce(u32)>(
         &self,
         function: Function,
@@ -402,6 +521,37 @@ impl Interface {
         }
     }
 
+    /// Record into a labelled gpu scope of the timestamp pool,
+    /// without timestamps it only records.
+    pub fn gpu_scope<Function: FnOnce()>(
+        &self,
+        timestamp_pool: &Option<TimestampPool>,
+        cmd_buffer: vk::CommandBuffer,
+        label: &str,
+        function: Function,
+    ) {
+        let scope_idx = timestamp_pool
+            .as_ref()
+            .and_then(|pool| pool.begin_scope(&self.device, cmd_buffer, label));
+
+        function();
+
+        if let Some(pool) = timestamp_pool {
+            pool.end_scope(&self.device, cmd_buffer, scope_idx);
+        }
+    }
+
+    /// Gpu scopes of the last drawn frame. Waits until the frame is done,
+    /// so it should be called right before the next draw.
+    pub fn read_draw_scopes(&self) -> Option<Vec<(String, Duration)>> {
+        self.draw_timestamp_pool.as_ref()?.read(&self.device)
+    }
+
+    /// Gpu scopes of all comp submits since the comp pool was cleared.
+    pub fn read_comp_scopes(&self) -> Option<Vec<(String, Duration)>> {
+        self.comp_timestamp_pool.as_ref()?.read(&self.device)
+    }
+
     pub fn wait_for_gpu(&self) -> Result<(), Box<dyn Error>> {
         unsafe { Ok(self.device.device_wait_idle().unwrap()) }
     }
@@ -416,6 +566,13 @@ impl Drop for Interface {
             self.device.destroy_semaphore(self.present_complete, None);
             self.device.destroy_semaphore(self.render_complete, None);
 
+            for timestamp_pool in [&self.comp_timestamp_pool, &self.draw_timestamp_pool]
+                .into_iter()
+                .flatten()
+            {
+                timestamp_pool.destroy(&self.device);
+            }
+
             self.device.free_command_buffers(self.pool, &[self.setup_cmd_buffer, self.draw_cmd_buffer]);
 
             self.device.destroy_command_pool(self.pool, None);
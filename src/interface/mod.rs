@@ -1,4 +1,5 @@
 pub mod interface;
 pub mod phydev;
 pub mod surface;
-pub mod swapchain;
\ No newline at end of file
+pub mod swapchain;
+pub mod timestamp;
\ No newline at end of file
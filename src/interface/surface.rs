@@ -62,6 +62,33 @@ impl SurfaceGroup {
         }
     }
 
+    /// Surface group for offscreen mode, there is no surface khr.
+    /// Format and resolution are fixed, render res is taken from pref.
+    pub fn offscreen(entry: &Entry, instance: &Instance, pref: &Pref) -> Self {
+        let loader = Surface::new(entry, instance);
+
+        Self {
+            loader,
+            surface: vk::SurfaceKHR::null(),
+
+            format: vk::SurfaceFormatKHR {
+                format: vk::Format::R8G8B8A8_UNORM,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+            capa: Default::default(),
+
+            swap_img_count: 1,
+
+            render_res: pref.render_res,
+            surface_res: pref.render_res,
+
+            pre_transform: Default::default(),
+
+            present_mode_list: Default::default(),
+            present_mode: vk::PresentModeKHR::FIFO,
+        }
+    }
+
     /// Set other param. of surface group. This function will
     /// gather information about surface format, surface capability,
     /// swapchain image count, surface resolution, surface pre transform and
@@ -118,14 +145,15 @@ impl SurfaceGroup {
                 .get_physical_device_surface_present_modes(phy_device.device, result.surface)
                 .unwrap();
 
-            // Select preferred present mode if possible
-            result.present_mode = result
-                .present_mode_list
-                .iter()
-                .cloned()
-                .find(|&mode| mode == pref.pref_present_mode)
-                // Else use present mode fifo
+            // Select preferred present mode if possible,
+            // else mailbox which does not wait for vsync either, else fifo which is always supported
+            result.present_mode = [pref.pref_present_mode, vk::PresentModeKHR::MAILBOX]
+                .into_iter()
+                .find(|mode| result.present_mode_list.contains(mode))
                 .unwrap_or(vk::PresentModeKHR::FIFO);
+            if result.present_mode != pref.pref_present_mode {
+                log::info!("Preferred present mode [ {} ] is not supported ...", pref.pref_present_mode.as_raw());
+            }
             log::info!("Selected present mode is [ {} ]...", result.present_mode.as_raw());
 
             result
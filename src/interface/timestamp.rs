@@ -0,0 +1,218 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
+use ash::{vk, Device, Instance};
+
+use super::phydev::PhyDeviceGroup;
+
+// Scopes per command buffer, each scope uses a start and an end query
+pub const MAX_SCOPE_COUNT: u32 = 16;
+
+/// Labelled timestamp scopes on the GPU. A scope resets and writes its
+/// own queries in the command buffer, so scopes of several submits can be
+/// collected. Queries are read once the fence of the last submit is signaled.
+pub struct TimestampPool {
+    pub pool: vk::QueryPool,
+
+    // Nanoseconds per tick
+    pub period: f32,
+    // Bits of a timestamp that are written by the queue
+    pub valid_mask: u64,
+
+    // Label of every scope since the last clear, scope idx = position
+    label_list: RefCell<Vec<String>>,
+    // Scopes over the max are not measured, only warned about once
+    overflow: Cell<bool>,
+}
+
+impl TimestampPool {
+    /// Return None if the queue of the device has no timestamps.
+    pub fn new(instance: &Instance, device: &Device, phy_device: &PhyDeviceGroup) -> Option<Self> {
+        unsafe {
+            let valid_bits = instance
+                .get_physical_device_queue_family_properties(phy_device.device)
+                .get(phy_device.queue_family_index as usize)?
+                .timestamp_valid_bits;
+
+            if valid_bits == 0 {
+                log::info!("Queue has no timestamps, gpu times are not measured ...");
+                return None;
+            }
+
+            let pool_create_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(MAX_SCOPE_COUNT * 2);
+
+            let pool = device
+                .create_query_pool(&pool_create_info, None)
+                .expect("ERR_CREATE_QUERY_POOL");
+
+            Some(Self {
+                pool,
+
+                period: phy_device.device_prop.limits.timestamp_period,
+                valid_mask: u64::MAX.checked_shr(64 - valid_bits).unwrap_or(u64::MAX),
+
+                label_list: RefCell::new(vec![]),
+                overflow: Cell::new(false),
+            })
+        }
+    }
+
+    /// Forget all scopes, the next scope uses the first queries again.
+    /// Called before recording a command buffer that is measured as a whole.
+    pub fn clear(&self) {
+        self.label_list.borrow_mut().clear();
+    }
+
+    /// Reset the queries of a new scope and write its start.
+    /// Has to be recorded outside of rendering, like a barrier.
+    pub fn begin_scope(
+        &self,
+        device: &Device,
+        cmd_buffer: vk::CommandBuffer,
+        label: &str,
+    ) -> Option<u32> {
+        let mut label_list = self.label_list.borrow_mut();
+
+        let scope_idx = label_list.len() as u32;
+        if scope_idx >= MAX_SCOPE_COUNT {
+            if !self.overflow.replace(true) {
+                log::info!(
+                    "More than {} gpu scopes, {} is not measured ...",
+                    MAX_SCOPE_COUNT,
+                    label
+                );
+            }
+            return None;
+        }
+        label_list.push(label.to_string());
+
+        unsafe {
+            device.cmd_reset_query_pool(cmd_buffer, self.pool, scope_idx * 2, 2);
+            device.cmd_write_timestamp(
+                cmd_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.pool,
+                scope_idx * 2,
+            );
+        }
+
+        Some(scope_idx)
+    }
+
+    pub fn end_scope(
+        &self,
+        device: &Device,
+        cmd_buffer: vk::CommandBuffer,
+        scope_idx: Option<u32>,
+    ) {
+        if let Some(scope_idx) = scope_idx {
+            unsafe {
+                device.cmd_write_timestamp(
+                    cmd_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    self.pool,
+                    scope_idx * 2 + 1,
+                );
+            }
+        }
+    }
+
+    /// Label and gpu time of every scope, waits until all are written.
+    /// None if there are no scopes.
+    pub fn read(&self, device: &Device) -> Option<Vec<(String, Duration)>> {
+        let label_list = self.label_list.borrow();
+        if label_list.is_empty() {
+            return None;
+        }
+
+        let mut timestamp_list = vec![0u64; label_list.len() * 2];
+        unsafe {
+            device
+                .get_query_pool_results(
+                    self.pool,
+                    0,
+                    timestamp_list.len() as u32,
+                    &mut timestamp_list,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                )
+                .ok()?;
+        }
+
+        Some(
+            label_list
+                .iter()
+                .zip(timestamp_list.chunks(2))
+                .map(|(label, pair)| {
+                    (
+                        label.clone(),
+                        elapsed(pair[0], pair[1], self.period, self.valid_mask),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_query_pool(self.pool, None);
+        }
+    }
+}
+
+/// Time between two timestamps, the counter may wrap at the valid bits.
+pub fn elapsed(start: u64, end: u64, period: f32, valid_mask: u64) -> Duration {
+    let tick_count = end.wrapping_sub(start) & valid_mask;
+
+    Duration::from_nanos((tick_count as f64 * period as f64) as u64)
+}
+
+pub fn scope_time(scope_list: &[(String, Duration)], label: &str) -> Option<Duration> {
+    scope_list
+        .iter()
+        .find(|(scope_label, _)| scope_label == label)
+        .map(|(_, time)| *time)
+}
+
+/// One line like "frame 1.250 ms | graphic 1.100 ms".
+pub fn report(scope_list: &[(String, Duration)]) -> String {
+    scope_list
+        .iter()
+        .map(|(label, time)| format!("{} {:.3} ms", label, time.as_secs_f64() * 1000.0))
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{elapsed, report, scope_time};
+
+    #[test]
+    fn elapsed_wraps_at_valid_bits() {
+        let mask = (1u64 << 36) - 1;
+
+        assert_eq!(elapsed(100, 300, 1.0, mask), Duration::from_nanos(200));
+        assert_eq!(elapsed(100, 300, 2.5, mask), Duration::from_nanos(500));
+        assert_eq!(elapsed(mask - 9, 10, 1.0, mask), Duration::from_nanos(20));
+    }
+
+    #[test]
+    fn report_of_scopes() {
+        let scope_list = vec![
+            ("frame".to_string(), Duration::from_micros(1250)),
+            ("graphic".to_string(), Duration::from_micros(1100)),
+        ];
+
+        assert_eq!(report(&scope_list), "frame 1.250 ms | graphic 1.100 ms");
+        assert_eq!(
+            scope_time(&scope_list, "graphic"),
+            Some(Duration::from_micros(1100))
+        );
+        assert_eq!(scope_time(&scope_list, "copy"), None);
+    }
+}
@@ -1,31 +1,44 @@
 use std::{
     borrow::BorrowMut,
+    env,
     io::Write,
-    mem, thread,
+    mem,
+    process,
+    thread,
     time::{Duration, Instant},
 };
 
-use ash::vk;
-use cgmath::{Vector2, num_traits::Pow};
+use bench::{Bench, BENCH_WARMUP_FRAMES};
+use cli::Command;
 use env_logger::fmt::{Color, Formatter};
 use input::Input;
-use interface::interface::Interface;
+use interface::{interface::Interface, timestamp};
 use log::Record;
-use nalgebra_glm::{cross, normalize, vec3_to_vec4, Vec2};
-use pipe::engine::Engine;
-use tree::octree::Octree;
+use nalgebra_glm::{Vec2, Vec4};
+use pipe::{cpu, engine::Engine};
+use pref::Pref;
+use replay::{CamFrame, CamPath, Replay};
+use tree::{
+    file, gen,
+    octree::Octree,
+    world::{ChunkPos, World},
+};
 use uniform::Uniform;
 use winit::{
     dpi::PhysicalPosition,
-    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event::{Event, KeyboardInput, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     platform::run_return::EventLoopExtRunReturn,
 };
 
+mod bench;
 mod bit;
+mod cli;
 mod input;
 mod interface;
 mod pipe;
+mod pref;
+mod replay;
 mod tree;
 mod uniform;
 mod vector;
@@ -38,6 +51,8 @@ pub struct RenderState {
     pub idle: bool,
 
     pub frame_time: Duration,
+    // Gpu scopes are logged at most once per second
+    pub last_report: Instant,
 }
 
 // Complete Render Pipeline
@@ -49,27 +64,83 @@ pub struct Render {
     uniform: Uniform,
     octree: Octree,
 
+    // Chunks around the cam, if world radius is set. The octree is the chunk
+    // at chunk pos and the cam is relative to it, see World::follow
+    world: Option<World>,
+    chunk_pos: ChunkPos,
+
     input: Input,
+    // Cam is driven by the replay instead of the input
+    replay: Option<Replay>,
+    // Cam state of every drawn frame, saved on exit
+    recording: Option<CamPath>,
+    // Frame times of the bench, saved on exit
+    bench: Option<Bench>,
 
     interface: Interface,
     graphic_pipe: Engine,
 }
 
-// General Setting
-pub struct Pref {
-    pub pref_present_mode: vk::PresentModeKHR,
-    pub img_filter: vk::Filter,
-    pub img_scale: f32,
+fn create_octree(pref: &Pref) -> Octree {
+    match &pref.scene_file {
+        Some(path) => file::load_scene(path).expect("ERR_LOAD_SCENE"),
+        None => gen::generate(pref.scene, pref.seed),
+    }
+}
+
+/// Chunks around the cam, if a world radius is set. Chunks of a scene
+/// file are only loaded once, the generator fills the others with nothing.
+fn create_world(pref: &Pref, root_span: f32) -> Option<World> {
+    let radius = pref.world_radius?;
+
+    Some(match &pref.scene_file {
+        Some(path) => {
+            let mut world = World::new(
+                root_span,
+                radius,
+                Box::new(move |_| Octree {
+                    root_span,
+
+                    ..Default::default()
+                }),
+            );
+            file::load_scene_world(path, &mut world).expect("ERR_LOAD_SCENE");
+
+            world
+        }
+        None => World::new(
+            root_span,
+            radius,
+            gen::chunk_generator(pref.scene, pref.seed),
+        ),
+    })
+}
+
+/// Reduced octree for the upload, if a lod detail or depth is set or the dag is used.
+fn upload_octree(pref: &Pref, octree: &Octree, cam_pos: Vec4) -> Option<Octree> {
+    let truncated = pref.lod_depth.map(|depth| octree.truncate(depth));
+    let lod = match pref.lod_detail {
+        Some(detail) => Some(truncated.as_ref().unwrap_or(octree).lod_by_distance(cam_pos, detail)),
+        None => truncated,
+    };
 
-    pub name: String,
-    pub engine_name: String,
+    match (pref.use_dag, lod) {
+        (true, lod) => Some(lod.as_ref().unwrap_or(octree).to_dag()),
+        (false, lod) => lod,
+    }
+}
 
-    pub start_window_size: vk::Extent2D,
+/// Create engine with all pipes and run the jump flooding on the brick texture.
+fn create_engine(interface: &Interface, uniform: &Uniform, octree: &Octree) -> Engine {
+    let mut engine = Engine::create_base(interface, uniform, octree);
+    // engine = engine.create_compute(interface, uniform, octree);
+    engine = engine
+        .create_jfa_comp(interface, uniform, octree)
+        .create_graphic(interface, uniform, octree);
 
-    pub use_render_res: bool,
-    pub render_res: vk::Extent2D,
+    engine.run_jfa(interface);
 
-    pub mov_speed: f32,
+    engine
 }
 
 fn main() {
@@ -94,102 +165,165 @@ fn main() {
     log::info!("Starting Application ...");
     thread::spawn(|| loop {});
 
-    let mut render = Render::get_render();
-    render.execute(Instant::now());
+    let (command, pref) = match cli::parse_args(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            log::error!("{}", error);
+            println!("{}", cli::USAGE);
+            process::exit(2);
+        }
+    };
 
-    render.graphic_pipe.drop_graphic(&render.interface);
-}
+    match command {
+        Command::HELP => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Command::INFO => {
+            let octree = create_octree(&pref);
+            println!("{}", octree.info());
+            println!("Dag nodes    {}", octree.to_dag().octant_data.len());
+            return;
+        }
+        Command::CONVERT { out_file } => {
+            let mut octree = create_octree(&pref);
+            octree.compact();
+            file::save_scene(&octree, &out_file).expect("ERR_SAVE_SCENE");
+            return;
+        }
+        Command::VIEW | Command::RENDER | Command::BENCH => (),
+    }
 
-impl Render {
-    pub fn get_render() -> Render {
-        let event_loop = EventLoop::new();
+    let cpu_world = pref
+        .cpu_render_file
+        .as_ref()
+        .and_then(|_| create_world(&pref, Octree::default().root_span));
+    if let (Some(path), Some(mut world)) = (&pref.cpu_render_file, cpu_world) {
+        let res = [pref.render_res.width, pref.render_res.height];
+        let uniform = Uniform::overview(world.root_span, res);
+        world.update(uniform.cam_pos);
+
+        let img = cpu::render_world(&world, &uniform, res);
+        log::info!("Saving image to {} ...", path.display());
+        img.save(path).expect("ERR_CPU_RENDER");
+        return;
+    }
 
-        let pref = Pref {
-            pref_present_mode: vk::PresentModeKHR::IMMEDIATE,
-            img_filter: vk::Filter::LINEAR,
-            img_scale: 1.0,
+    if let Some(path) = &pref.cpu_render_file {
+        let octree = create_octree(&pref);
+        let res = [pref.render_res.width, pref.render_res.height];
 
-            name: env!("CARGO_PKG_NAME").to_string(),
-            engine_name: "Engine".to_string(),
+        cpu::render_to_file(&octree, &Uniform::overview(octree.root_span, res), res, path)
+            .expect("ERR_CPU_RENDER");
+        return;
+    }
 
-            start_window_size: vk::Extent2D {
-                width: 800,
-                height: 600,
-            },
+    if let Some(path) = &pref.offscreen_file {
+        let octree = create_octree(&pref);
+        let interface = Interface::init_offscreen(&pref);
 
-            use_render_res: true,
-            render_res: vk::Extent2D {
-                width: 1920,
-                height: 1080,
-            },
+        let res = [pref.render_res.width, pref.render_res.height];
+        let mut uniform = Uniform::overview(octree.root_span, res);
+        uniform.update_uniform(Duration::ZERO);
 
-            mov_speed: 0.05,
-        };
+        let upload = upload_octree(&pref, &octree, uniform.cam_pos);
+        let engine = create_engine(&interface, &uniform, upload.as_ref().unwrap_or(&octree));
+        engine
+            .draw_offscreen(&interface)
+            .save(path)
+            .expect("ERR_SAVE_OFFSCREEN_IMAGE");
+
+        interface.wait_for_gpu().expect("DEVICE_LOST");
+        engine.drop_graphic(&interface);
+        return;
+    }
+
+    let mut render = Render::get_render(pref);
+    render.execute(Instant::now());
+
+    render.graphic_pipe.drop_graphic(&render.interface);
+}
+
+impl Render {
+    pub fn get_render(pref: Pref) -> Render {
+        let event_loop = EventLoop::new();
 
         let state = RenderState {
             out_of_date: false,
             idle: false,
             frame_time: Duration::ZERO,
+            last_report: Instant::now(),
         };
 
-        let mut octree = Octree::default();
+        // World starts with the cam in chunk (0, 0, 0)
+        let mut world = create_world(&pref, Octree::default().root_span);
+        let mut octree = match &mut world {
+            Some(world) => {
+                world.update(Vec4::zeros());
+                world.chunk_map.remove(&[0; 3]).unwrap_or_default()
+            }
+            None => create_octree(&pref),
+        };
 
-        let input = Input::new();
+        let mut input = Input::new();
+        if let Some(path) = &pref.binding_file {
+            input.load_bindings(path).expect("ERR_BINDING_FILE");
+        }
         let mut uniform = Uniform::new(octree.root_span);
 
-        octree.test_scene();
-
         let interface = Interface::init(&event_loop, &pref);
         uniform.res = Vec2::new(
             interface.surface.surface_res.width as f32,
             interface.surface.surface_res.height as f32,
         );
 
-        let mut graphic_pipe = Engine::create_base(&interface, &uniform, &octree);
-        // graphic_pipe = graphic_pipe.create_compute(&interface, &uniform, &octree);
-        graphic_pipe = graphic_pipe
-            .create_jfa_comp(&interface, &uniform, &octree)
-            .create_graphic(&interface, &uniform, &octree);
-
-        graphic_pipe.run_jfa_iteration(
-            &interface,
-            vk::Extent3D {
-                width: 4096,
-                height: 4096,
-                depth: 1,
-            },
-            1,
-        );
+        // Full octree is kept for the input, only the upload is reduced
+        let upload = upload_octree(&pref, &octree, uniform.cam_pos);
+        let graphic_pipe = create_engine(&interface, &uniform, upload.as_ref().unwrap_or(&octree));
+        octree.dirty_list.clear();
 
-        for idx in 0..4 {
-            graphic_pipe.run_jfa_iteration(
-                &interface,
-                vk::Extent3D {
-                    width: 4096,
-                    height: 4096,
-                    depth: 1,
-                },
-                (8.0 * 0.5.pow(idx)) as u32,
-            );
+        if pref.profile_gpu {
+            if let Some(scope_list) = interface.read_comp_scopes() {
+                log::info!("Gpu jfa: {}", timestamp::report(&scope_list));
+            }
         }
 
-        graphic_pipe.run_jfa_iteration(
-            &interface,
-            vk::Extent3D {
-                width: 4096,
-                height: 4096,
-                depth: 1,
-            },
-            1,
-        );
-        
+        // Bench without replay file orbits once in bench and warmup frames
+        let bench_frame_count = pref.bench_frames as usize + BENCH_WARMUP_FRAMES;
+        let cam_path = match (&pref.replay_file, &pref.bench_file) {
+            (Some(path), _) => Some(CamPath::load(path).expect("ERR_LOAD_CAM_PATH")),
+            (None, Some(_)) => Some(CamPath::orbit(
+                octree.root_span,
+                bench_frame_count as f32 * pref.replay_step,
+            )),
+            (None, None) => None,
+        };
+        let replay = cam_path.map(|path| Replay::new(path, pref.replay_step));
+
+        if let (Some(replay), Some(_)) = (&replay, &pref.bench_file) {
+            if replay.frame_count() < bench_frame_count {
+                log::warn!(
+                    "Replay path has {} frames, the bench needs {} with warmup ...",
+                    replay.frame_count(),
+                    bench_frame_count
+                );
+            }
+        }
+        let recording = pref.record_file.as_ref().map(|_| CamPath::default());
+        let bench = pref.bench_file.as_ref().map(|_| Bench::new(pref.bench_frames));
+
         Render {
             state,
             event_loop,
             pref,
             uniform,
             octree,
+            world,
+            chunk_pos: [0; 3],
             input,
+            replay,
+            recording,
+            bench,
             interface,
             graphic_pipe,
         }
@@ -220,8 +354,6 @@ impl Render {
                             &keycode,
                             &state,
                             &mut self.uniform,
-                            &self.pref,
-                            &self.octree,
                             &self.interface,
                         )
                     }
@@ -231,9 +363,9 @@ impl Render {
                         ..
                     } => {
                         self.input.handle_mouse_input(position, &mut self.uniform);
-                        self.interface.window.set_cursor_visible(false);
+                        self.interface.window().set_cursor_visible(false);
                         self.interface
-                            .window
+                            .window()
                             .set_cursor_position(PhysicalPosition::new(
                                 self.uniform.res.x / 2.0,
                                 self.uniform.res.y / 2.0,
@@ -241,6 +373,22 @@ impl Render {
                             .unwrap();
                     }
 
+                    Event::WindowEvent {
+                        event: WindowEvent::MouseInput { state, button, .. },
+                        ..
+                    } => {
+                        let edited = self.input.handle_mouse_button(
+                            &button,
+                            &state,
+                            &self.uniform,
+                            &mut self.octree,
+                        );
+
+                        if edited && self.pref.reduced_upload() {
+                            log::info!("Edits are not uploaded with lod or dag ...");
+                        }
+                    }
+
                     Event::WindowEvent {
                         event: WindowEvent::CloseRequested,
                         ..
@@ -249,7 +397,7 @@ impl Render {
                     // Adjust Surface and Draw
                     {
                         if self.state.out_of_date {
-                            let dim = self.interface.window.inner_size();
+                            let dim = self.interface.window().inner_size();
                             if dim.width > 0 && dim.height > 0 {
                                 // Not Minimized
                                 self.graphic_pipe.recreate_swapchain(
@@ -265,13 +413,70 @@ impl Render {
                                 self.state.idle = true;
                             }
                         } else {
-                            // Update Octree
-                            // self.octree = Octree::default();
-                            // self.octree.test_scene();
-                            // self.graphic_pipe.update_buffer(&self.interface, self.graphic_pipe.octree_buffer_memory, &self.octree.data.clone(), );
+                            // Cam paths are in world space, the cam is relative to the chunk
+                            let origin = self.world.as_ref().map_or(Vec4::zeros(), |world| {
+                                world.chunk_origin(self.chunk_pos)
+                            });
+
+                            // Replay moves the cam with fixed steps and ends the loop
+                            let time = match &mut self.replay {
+                                Some(replay) => match replay.next_frame(&mut self.uniform, origin) {
+                                    Some(time) => time,
+                                    None => {
+                                        if let Some(bench) =
+                                            self.bench.as_ref().filter(|bench| !bench.is_done())
+                                        {
+                                            log::warn!(
+                                                "Bench path ended after {} of {} frames ...",
+                                                bench.cpu_time_list.len(),
+                                                bench.frame_count
+                                            );
+                                        }
+
+                                        *control_flow = ControlFlow::Exit;
+                                        return;
+                                    }
+                                },
+                                None => app_start.elapsed(),
+                            };
+
+                            if let Some(recording) = &mut self.recording {
+                                recording.push(CamFrame::capture(&self.uniform, origin, time));
+                            }
+
+                            // Cam stays relative to the uploaded chunk
+                            let mut chunk_changed = false;
+                            if let Some(world) = &mut self.world {
+                                let shift = world.follow(
+                                    &mut self.chunk_pos,
+                                    &mut self.octree,
+                                    self.uniform.cam_pos,
+                                );
+                                if let Some(shift) = shift {
+                                    self.uniform.cam_pos -= shift;
+                                    self.uniform.pos -= shift;
+                                    chunk_changed = true;
+                                }
+                            }
+
+                            // Upload edits, the reduced upload of lod and dag
+                            // can not be patched with ranges of the full octree,
+                            // it is built again for the chunk the cam entered
+                            if !self.pref.reduced_upload() {
+                                self.graphic_pipe
+                                    .upload_dirty(&self.interface, &mut self.octree);
+                            } else if chunk_changed {
+                                if let Some(mut upload) =
+                                    upload_octree(&self.pref, &self.octree, self.uniform.cam_pos)
+                                {
+                                    upload.mark_dirty(0..upload.octant_data.len());
+                                    self.graphic_pipe.upload_dirty(&self.interface, &mut upload);
+                                }
+                                self.octree.dirty_list.clear();
+                            }
 
                             // Update Uniform
-                            self.uniform.update_uniform(app_start.elapsed());
+                            self.uniform.update_uniform(time);
 
                             self.graphic_pipe.uniform_buffer.rewrite_mem(
                                 &self.interface,
@@ -280,6 +485,25 @@ impl Render {
                                 &[self.uniform],
                             );
 
+                            // Gpu time of the frame before, read before the draw
+                            // so the cpu does not wait for the frame in flight
+                            let scope_list = (self.bench.is_some() || self.pref.profile_gpu)
+                                .then(|| self.interface.read_draw_scopes())
+                                .flatten();
+                            let gpu_time = scope_list
+                                .as_ref()
+                                .and_then(|scope_list| timestamp::scope_time(scope_list, "frame"));
+                            if let (Some(bench), Some(gpu_time)) = (&mut self.bench, gpu_time) {
+                                bench.push_gpu(gpu_time);
+                            }
+
+                            if let (true, Some(scope_list)) = (self.pref.profile_gpu, &scope_list) {
+                                if self.state.last_report.elapsed() >= Duration::from_secs(1) {
+                                    log::info!("Gpu frame: {}", timestamp::report(scope_list));
+                                    self.state.last_report = Instant::now();
+                                }
+                            }
+
                             // Draw and capture FrameTime
                             let start = Instant::now();
                             self.state.out_of_date = self
@@ -288,41 +512,43 @@ impl Render {
                                 .expect("RENDER_FAILED");
                             self.state.frame_time = start.elapsed();
 
-                            if self.input.key_down[VirtualKeyCode::W as usize] == true {
-                                self.uniform.velocity +=
-                                    nalgebra_glm::normalize(&self.uniform.look_dir)
-                                        * self.pref.mov_speed;
-                                self.uniform.apply_velocity();
-                            }
-                            if self.input.key_down[VirtualKeyCode::S as usize] == true {
-                                self.uniform.velocity -=
-                                    nalgebra_glm::normalize(&self.uniform.look_dir)
-                                        * self.pref.mov_speed;
-                                self.uniform.apply_velocity();
-                            }
-                            if self.input.key_down[VirtualKeyCode::A as usize] == true {
-                                self.uniform.velocity -= vec3_to_vec4(&normalize(&cross(
-                                    &nalgebra_glm::normalize(&self.uniform.look_dir.xyz()),
-                                    &self.uniform.cam_up.xyz(),
-                                ))) * self.pref.mov_speed;
-                                self.uniform.apply_velocity();
-                            }
-                            if self.input.key_down[VirtualKeyCode::D as usize] == true {
-                                self.uniform.velocity += vec3_to_vec4(&normalize(&cross(
-                                    &nalgebra_glm::normalize(&self.uniform.look_dir.xyz()),
-                                    &self.uniform.cam_up.xyz(),
-                                ))) * self.pref.mov_speed;
-                                self.uniform.apply_velocity();
+                            if let Some(bench) = &mut self.bench {
+                                bench.push(self.state.frame_time);
+
+                                if bench.is_done() {
+                                    *control_flow = ControlFlow::Exit;
+                                }
                             }
-                            if self.input.key_down[VirtualKeyCode::LShift as usize] == true {
-                                self.pref.mov_speed = 0.3;
-                            } else {
-                                self.pref.mov_speed = 0.05;
+
+                            if self.replay.is_none() {
+                                self.input.apply_movement(&mut self.uniform, &self.pref);
                             }
                         }
                     }
 
-                    Event::LoopDestroyed => self.interface.wait_for_gpu().expect("DEVICE_LOST"),
+                    Event::LoopDestroyed => {
+                        self.interface.wait_for_gpu().expect("DEVICE_LOST");
+
+                        if let (Some(recording), Some(path)) =
+                            (&self.recording, &self.pref.record_file)
+                        {
+                            recording.save(path).expect("ERR_SAVE_CAM_PATH");
+                        }
+
+                        if let (Some(bench), Some(path)) = (&mut self.bench, &self.pref.bench_file)
+                        {
+                            // Gpu time of the last frame, it has no next draw
+                            let gpu_time = self
+                                .interface
+                                .read_draw_scopes()
+                                .and_then(|scope_list| timestamp::scope_time(&scope_list, "frame"));
+                            if let Some(gpu_time) = gpu_time {
+                                bench.push_gpu(gpu_time);
+                            }
+
+                            bench.save(&self.pref, path).expect("ERR_SAVE_BENCH");
+                        }
+                    }
                     _ => (),
                 }
             });
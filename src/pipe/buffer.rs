@@ -131,6 +131,49 @@ impl BufferSet {
         }
     }
 
+    /// Write data at byte offset of a host visible buffer,
+    /// the rest of the buffer is left as it is.
+    pub fn rewrite_mem_at<Type: Copy>(
+        &self,
+        interface: &Interface,
+        alignment: u64,
+        offset: u64,
+        data: &[Type],
+    ) {
+        unsafe {
+            let size = std::mem::size_of_val(data) as u64;
+            let buffer_ptr = interface
+                .device
+                .map_memory(self.mem, offset, size, vk::MemoryMapFlags::empty())
+                .unwrap();
+
+            let mut aligned_slice = Align::new(buffer_ptr, alignment, size);
+
+            aligned_slice.copy_from_slice(data);
+            interface.device.unmap_memory(self.mem);
+        }
+    }
+
+    /// Copy len values from the start of a host visible buffer.
+    pub fn read_mem<Type: Copy>(&self, interface: &Interface, len: usize) -> Vec<Type> {
+        unsafe {
+            let buffer_ptr = interface
+                .device
+                .map_memory(
+                    self.mem,
+                    0,
+                    std::mem::size_of::<Type>() as u64 * len as u64,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .unwrap();
+
+            let data = std::slice::from_raw_parts(buffer_ptr as *const Type, len).to_vec();
+            interface.device.unmap_memory(self.mem);
+
+            data
+        }
+    }
+
     pub fn destroy(&self, device: &Device) {
         unsafe {
             device.free_memory(self.mem, None);
@@ -0,0 +1,165 @@
+use std::{path::Path, thread};
+
+use image::{ImageResult, Rgba, RgbaImage};
+use nalgebra_glm::{Vec3, Vec4};
+
+use crate::{
+    tree::{material::Material, octree::Octree, trace::Ray, world::World},
+    uniform::Uniform,
+};
+
+// Same as the projection in update uniform
+pub const FOV_Y: f32 = 45.0;
+
+/// Ray through the center of pixel (x, y), y is going down like in the image.
+pub fn primary_ray(uniform: &Uniform, res: [u32; 2], x: u32, y: u32) -> Ray {
+    let front = uniform.look_dir.xyz().normalize();
+    let right = front.cross(&uniform.cam_up.xyz()).normalize();
+    let up = right.cross(&front);
+
+    let scale = (FOV_Y.to_radians() * 0.5).tan();
+    let aspect = res[0] as f32 / res[1] as f32;
+
+    let screen_x = (2.0 * (x as f32 + 0.5) / res[0] as f32 - 1.0) * scale * aspect;
+    let screen_y = (1.0 - 2.0 * (y as f32 + 0.5) / res[1] as f32) * scale;
+
+    let dir = front + right * screen_x + up * screen_y;
+
+    Ray {
+        origin: uniform.cam_pos,
+        dir: Vec4::new(dir.x, dir.y, dir.z, 0.0),
+    }
+}
+
+/// Color of a single ray, uses the same shading as the fragment shader.
+/// Nothing hit is transparent black.
+pub fn shade(octree: &Octree, ray: &Ray) -> Vec4 {
+    let material = octree
+        .cast_ray(ray, f32::MAX)
+        .map(|hit| octree.material_data[hit.idx as usize]);
+
+    shade_material(ray, material)
+}
+
+pub fn shade_world(world: &World, ray: &Ray) -> Vec4 {
+    let material = world
+        .cast_ray(ray, f32::MAX)
+        .map(|(chunk_pos, hit)| world.material(chunk_pos, &hit));
+
+    shade_material(ray, material)
+}
+
+fn shade_material(ray: &Ray, material: Option<Material>) -> Vec4 {
+    match material {
+        Some(material) => {
+            let dir = ray.dir.xyz().normalize();
+
+            // simple shading based on view direction
+            let light = Vec3::new(1.0, 2.0, 3.0).normalize().dot(&-dir) * 0.5 + 0.5;
+            let diffuse = 0.5 + 0.5 * light;
+
+            let color = material.albedo.xyz() * diffuse + material.emission.xyz();
+            Vec4::new(color.x, color.y, color.z, material.albedo.w)
+        }
+        None => Vec4::zeros(),
+    }
+}
+
+/// Render octree without any device, rows are split over all cores.
+/// The result only depends on octree, cam and res.
+pub fn render(octree: &Octree, uniform: &Uniform, res: [u32; 2]) -> RgbaImage {
+    render_with(uniform, res, |ray| shade(octree, ray))
+}
+
+/// Render all loaded chunks of the world, like render.
+pub fn render_world(world: &World, uniform: &Uniform, res: [u32; 2]) -> RgbaImage {
+    render_with(uniform, res, |ray| shade_world(world, ray))
+}
+
+fn render_with<Function: Fn(&Ray) -> Vec4 + Sync>(
+    uniform: &Uniform,
+    res: [u32; 2],
+    shade: Function,
+) -> RgbaImage {
+    log::info!("Rendering [ {} x {} ] on cpu ...", res[0], res[1]);
+
+    let mut img = RgbaImage::new(res[0], res[1]);
+    if res[0] == 0 || res[1] == 0 {
+        return img;
+    }
+
+    let thread_count = thread::available_parallelism().map_or(1, |count| count.get());
+    let row_len = res[0] as usize * 4;
+    let rows_per_thread = (res[1] as usize).div_ceil(thread_count);
+    let shade = &shade;
+
+    thread::scope(|scope| {
+        for (chunk_idx, chunk) in img.chunks_mut(row_len * rows_per_thread).enumerate() {
+            scope.spawn(move || {
+                for (row_idx, row) in chunk.chunks_mut(row_len).enumerate() {
+                    let y = (chunk_idx * rows_per_thread + row_idx) as u32;
+
+                    for (x, pixel) in row.chunks_mut(4).enumerate() {
+                        let color = shade(&primary_ray(uniform, res, x as u32, y));
+                        let Rgba(value) = to_rgba(color);
+                        pixel.copy_from_slice(&value);
+                    }
+                }
+            });
+        }
+    });
+
+    img
+}
+
+pub fn render_to_file(
+    octree: &Octree,
+    uniform: &Uniform,
+    res: [u32; 2],
+    path: &Path,
+) -> ImageResult<()> {
+    let img = render(octree, uniform, res);
+
+    log::info!("Saving image to {} ...", path.display());
+    img.save(path)
+}
+
+fn to_rgba(color: Vec4) -> Rgba<u8> {
+    Rgba([0, 1, 2, 3].map(|idx| (color[idx].clamp(0.0, 1.0) * 255.0).round() as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+
+    use super::render;
+    use crate::{
+        tree::{material::Material, octree::Octree},
+        uniform::Uniform,
+    };
+
+    #[test]
+    fn render_is_reproducible() {
+        let mut octree = Octree::default();
+        let leaf_span = octree.leaf_span();
+        let material = Material::from_color(Vec4::new(1.0, 0.0, 0.0, 1.0));
+
+        for x in 48..80 {
+            for z in 48..80 {
+                octree.insert_node(Vec4::new(x as f32, 40.0, z as f32, 0.0) * leaf_span, material);
+            }
+        }
+
+        let mut uniform = Uniform::overview(octree.root_span, [64, 48]);
+        uniform.cam_pos = Vec4::new(128.0, 200.0, 10.0, 0.0);
+        uniform.look_at(Vec4::new(128.0, 80.0, 128.0, 0.0));
+
+        let img = render(&octree, &uniform, [64, 48]);
+        assert_eq!(img, render(&octree, &uniform, [64, 48]));
+
+        // Plate in the middle, nothing in the corner
+        let center = img.get_pixel(32, 24).0;
+        assert!(center[0] > 127 && center[1] == 0 && center[2] == 0 && center[3] == 255);
+        assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0, 0]);
+    }
+}
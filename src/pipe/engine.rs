@@ -1,20 +1,25 @@
 use std::{
     error::Error,
     mem::{self, align_of},
+    ops::Range,
     path::Path,
 };
 
 use ash::vk;
 use cgmath::Vector3;
-use nalgebra_glm::Vec2;
 
 use crate::{
     interface::interface::Interface,
     pipe::{
         descriptor::DescriptorPool,
-        pipe::{JFAPush, LocInfo, Pipe, Vertex},
+        obj::{BASE_CUBE_IDX, BASE_CUBE_VERT},
+        layout::{
+            brick_px, slot_ranges, CubeLayout, BRICK_HEIGHT, BRICK_TEXTURE_LEN, BRICK_WIDTH,
+        },
+        pipe::{JFAPush, LocInfo, Pipe, Vertex, MAX_CUBE_COUNT},
     },
     tree::{
+        material::Material,
         octant::Octant,
         octree::{Octree, MAX_DEPTH},
         trace::{BranchInfo, PosInfo},
@@ -24,7 +29,26 @@ use crate::{
     Pref, DEFAULT_STORAGE_BUFFER_SIZE, DEFAULT_UNIFORM_BUFFER_SIZE,
 };
 
-use super::{buffer::BufferSet, image::ImageTarget};
+use super::{
+    buffer::BufferSet,
+    image::{ImageTarget, SUBRES_RANGE},
+};
+
+// Smallest number of values a storage buffer has room for
+const MIN_STORAGE_LEN: usize = 1 << 16;
+
+const BRICK_TEXTURE_EXTENT: vk::Extent3D = vk::Extent3D {
+    width: BRICK_TEXTURE_LEN,
+    height: BRICK_TEXTURE_LEN,
+    depth: 1,
+};
+
+const BRICK_SUBRES_LAYERS: vk::ImageSubresourceLayers = vk::ImageSubresourceLayers {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    mip_level: 0,
+    base_array_layer: 0,
+    layer_count: 1,
+};
 
 #[derive(Clone)]
 pub struct Engine {
@@ -34,13 +58,15 @@ pub struct Engine {
     pub vk_img_buffer: BufferSet,
     pub brick_texture: ImageTarget,
 
-    pub index_data: Vec<u32>,
+    // Last uploaded vertex data, edits only write the cubes that changed
+    pub cube_layout: CubeLayout,
 
     pub index_buffer: BufferSet,
     pub vertex_buffer: BufferSet,
 
     pub uniform_buffer: BufferSet,
     pub octree_buffer: BufferSet,
+    pub material_buffer: BufferSet,
     pub loc_info_buffer: BufferSet,
 
     pub pool_comp: DescriptorPool,
@@ -57,159 +83,202 @@ pub struct Engine {
 
 impl Engine {
     pub fn create_base(interface: &Interface, uniform: &Uniform, octree: &Octree) -> Self {
-        unsafe {
-            let mut result = Self::default();
-
-            result.image_target_list = interface
-                .swapchain
-                .img_list
-                .iter()
-                .map(|_| ImageTarget::attachment_img(interface, interface.surface.render_res))
-                .collect();
-
-            result.depth_image =
-                ImageTarget::depth_img(interface, interface.surface.render_res.into());
-
-            result.brick_texture = ImageTarget::storage_texture(
-                interface,
-                vk::Format::R8G8B8A8_UNORM,
-                vk::Extent3D {
-                    width: 4096,
-                    height: 4096,
-                    depth: 1,
-                },
-                vk::ImageType::TYPE_2D,
-                vk::ImageViewType::TYPE_2D,
-                1,
-            );
-
-            result.img_buffer = image::ImageBuffer::<image::Rgba<u8>, Vec<u8>>::from_pixel(
-                4096,
-                4096,
-                image::Rgba([0, 0, 0, 255]),
-            );
-
-            //image.put_pixel(0, 0, image::Rgb([0, 0, 0]));
-
-            let (vertex_data, index_data, loc_info) =
-                Pipe::get_octree_vert_data(octree, &mut result.img_buffer);
-
-            result.img_buffer = image::ImageBuffer::<image::Rgba<u8>, Vec<u8>>::from_pixel(
-                4096,
-                4096,
-                image::Rgba([0, 0, 0, 255]),
-            );
-
-            result.img_buffer.put_pixel(0, 0, image::Rgba([255, 255, 255, 0]));
-            result.img_buffer.put_pixel(7, 123, image::Rgba([255, 255, 255, 0]));
-            result.img_buffer.put_pixel(5, 453, image::Rgba([255, 255, 255, 0]));
-            result.img_buffer.put_pixel(12, 367, image::Rgba([255, 255, 255, 0]));
-
-            let mut img_data = result.img_buffer.clone().into_raw();
-
-            // result.img_buffer.save("out.png").unwrap();
-
-            log::info!("Creating ImageBuffer ...");
-            result.vk_img_buffer = BufferSet::new(
-                (std::mem::size_of::<u8>() * img_data.len()) as u64,
-                vk::BufferUsageFlags::TRANSFER_SRC,
-                vk::SharingMode::EXCLUSIVE,
-                &interface.device,
-            )
-            .create_memory(
-                &interface.device,
-                &interface.phy_device,
-                align_of::<u8>() as u64,
-                (std::mem::size_of::<u8>() * img_data.len()) as u64,
-                &img_data,
-            );
-
-            log::info!("Creating IndexBuffer ...");
-            result.index_data = index_data;
-            result.index_buffer = BufferSet::new(
-                mem::size_of_val(&result.index_data[..]) as u64,
-                vk::BufferUsageFlags::INDEX_BUFFER,
-                vk::SharingMode::EXCLUSIVE,
-                &interface.device,
-            )
-            .create_memory(
-                &interface.device,
-                &interface.phy_device,
-                align_of::<u32>() as u64,
-                mem::size_of_val(&result.index_data[..]) as u64,
-                &result.index_data,
-            );
-
-            log::info!("Creating VertexBuffer ...");
-            result.vertex_buffer = BufferSet::new(
-                mem::size_of_val(&vertex_data[..]) as u64,
-                vk::BufferUsageFlags::VERTEX_BUFFER,
-                vk::SharingMode::EXCLUSIVE,
-                &interface.device,
-            )
-            .create_memory(
-                &interface.device,
-                &interface.phy_device,
-                align_of::<Vertex>() as u64,
-                mem::size_of_val(&vertex_data[..]) as u64,
-                &vertex_data,
-            );
+        let image_target_list = (0..interface.target_count())
+            .map(|_| ImageTarget::attachment_img(interface, interface.surface.render_res))
+            .collect();
 
-            log::info!("Creating UniformBuffer ...");
-            result.uniform_buffer = BufferSet::new(
-                mem::size_of::<Uniform>() as u64,
-                vk::BufferUsageFlags::UNIFORM_BUFFER,
-                vk::SharingMode::EXCLUSIVE,
-                &interface.device,
-            )
-            .create_memory(
-                &interface.device,
-                &interface.phy_device,
-                align_of::<Uniform>() as u64,
-                mem::size_of::<Uniform>() as u64,
-                &[uniform.clone()],
-            );
+        let depth_image = ImageTarget::depth_img(interface, interface.surface.render_res.into());
+
+        let brick_texture = ImageTarget::storage_texture(
+            interface,
+            vk::Format::R8G8B8A8_UNORM,
+            BRICK_TEXTURE_EXTENT,
+            vk::ImageType::TYPE_2D,
+            vk::ImageViewType::TYPE_2D,
+            1,
+        );
+
+        let mut img_buffer = image::ImageBuffer::<image::Rgba<u8>, Vec<u8>>::from_pixel(
+            BRICK_TEXTURE_LEN,
+            BRICK_TEXTURE_LEN,
+            image::Rgba([0, 0, 0, 255]),
+        );
+
+        //image.put_pixel(0, 0, image::Rgb([0, 0, 0]));
+
+        let cube_layout = CubeLayout::new(octree, Some(&mut img_buffer));
+
+        let img_data = img_buffer.as_raw();
+
+        // img_buffer.save("out.png").unwrap();
+
+        log::info!("Creating ImageBuffer ...");
+        let vk_img_buffer = BufferSet::new(
+            (std::mem::size_of::<u8>() * img_data.len()) as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::SharingMode::EXCLUSIVE,
+            &interface.device,
+        )
+        .create_memory(
+            &interface.device,
+            &interface.phy_device,
+            align_of::<u8>() as u64,
+            (std::mem::size_of::<u8>() * img_data.len()) as u64,
+            img_data,
+        );
+
+        // Vertex data buffers are big enough for every cube, so edits
+        // can rewrite them in place, see upload_dirty
+        let index_capacity = (mem::size_of::<u32>() * BASE_CUBE_IDX.len() * MAX_CUBE_COUNT) as u64;
+        let vertex_capacity =
+            (mem::size_of::<Vertex>() * BASE_CUBE_VERT.len() * MAX_CUBE_COUNT) as u64;
+        let loc_info_capacity = (mem::size_of::<LocInfo>() * MAX_CUBE_COUNT) as u64;
+
+        log::info!("Creating IndexBuffer ...");
+        let index_buffer = BufferSet::new(
+            index_capacity,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            vk::SharingMode::EXCLUSIVE,
+            &interface.device,
+        )
+        .create_memory(
+            &interface.device,
+            &interface.phy_device,
+            align_of::<u32>() as u64,
+            mem::size_of_val(&cube_layout.index_data[..]) as u64,
+            &cube_layout.index_data,
+        );
+
+        log::info!("Creating VertexBuffer ...");
+        let vertex_buffer = BufferSet::new(
+            vertex_capacity,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::SharingMode::EXCLUSIVE,
+            &interface.device,
+        )
+        .create_memory(
+            &interface.device,
+            &interface.phy_device,
+            align_of::<Vertex>() as u64,
+            mem::size_of_val(&cube_layout.vertex_data[..]) as u64,
+            &cube_layout.vertex_data,
+        );
+
+        log::info!("Creating UniformBuffer ...");
+        let uniform_buffer = BufferSet::new(
+            mem::size_of::<Uniform>() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::SharingMode::EXCLUSIVE,
+            &interface.device,
+        )
+        .create_memory(
+            &interface.device,
+            &interface.phy_device,
+            align_of::<Uniform>() as u64,
+            mem::size_of::<Uniform>() as u64,
+            std::slice::from_ref(uniform),
+        );
+
+        log::info!("Creating OctreeBuffer ...");
+        let octree_buffer = BufferSet::new(
+            DEFAULT_STORAGE_BUFFER_SIZE,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::SharingMode::EXCLUSIVE,
+            &interface.device,
+        )
+        .create_memory(
+            &interface.device,
+            &interface.phy_device,
+            align_of::<u32>() as u64,
+            DEFAULT_STORAGE_BUFFER_SIZE,
+            &octree.octant_data,
+        );
+
+        log::info!("Creating MaterialBuffer ...");
+        let material_buffer = BufferSet::new(
+            storage_capacity::<Material>(octree.material_data.len()),
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::SharingMode::EXCLUSIVE,
+            &interface.device,
+        )
+        .create_memory(
+            &interface.device,
+            &interface.phy_device,
+            align_of::<Material>() as u64,
+            mem::size_of_val(&octree.material_data[..]) as u64,
+            &octree.material_data,
+        );
+
+        log::info!("Creating Location Info Buffer ...");
+        let loc_info_buffer = BufferSet::new(
+            loc_info_capacity,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::SharingMode::EXCLUSIVE,
+            &interface.device,
+        )
+        .create_memory(
+            &interface.device,
+            &interface.phy_device,
+            align_of::<LocInfo>() as u64,
+            mem::size_of_val(&cube_layout.loc_data[..]) as u64,
+            &cube_layout.loc_data,
+        );
+
+        let result = Self {
+            image_target_list,
+            depth_image,
+            img_buffer,
+            vk_img_buffer,
+            brick_texture,
+            cube_layout,
+            index_buffer,
+            vertex_buffer,
+            uniform_buffer,
+            octree_buffer,
+            material_buffer,
+            loc_info_buffer,
 
-            log::info!("Creating OctreeBuffer ...");
-            result.octree_buffer = BufferSet::new(
-                DEFAULT_STORAGE_BUFFER_SIZE,
-                vk::BufferUsageFlags::STORAGE_BUFFER,
-                vk::SharingMode::EXCLUSIVE,
-                &interface.device,
-            )
-            .create_memory(
-                &interface.device,
-                &interface.phy_device,
-                align_of::<u32>() as u64,
-                DEFAULT_STORAGE_BUFFER_SIZE,
-                &octree.octant_data,
-            );
-
-            log::info!("Creating Location Info Buffer ...");
-            result.loc_info_buffer = BufferSet::new(
-                (mem::size_of::<LocInfo>() * loc_info.len()) as u64,
-                vk::BufferUsageFlags::STORAGE_BUFFER,
-                vk::SharingMode::EXCLUSIVE,
-                &interface.device,
-            )
-            .create_memory(
-                &interface.device,
-                &interface.phy_device,
-                align_of::<LocInfo>() as u64,
-                (mem::size_of::<LocInfo>() * loc_info.len()) as u64,
-                &loc_info,
-            );
+            ..Default::default()
+        };
+
+        let full_copy = vk::BufferImageCopy::builder()
+            .image_subresource(BRICK_SUBRES_LAYERS)
+            .image_extent(BRICK_TEXTURE_EXTENT)
+            .build();
+        result.upload_brick_texture(
+            interface,
+            &result.vk_img_buffer,
+            &[full_copy],
+            vk::ImageLayout::UNDEFINED,
+        );
+
+        result
+    }
 
+    /// Copy the regions of staging into the brick texture, with the old layout
+    /// UNDEFINED the rest of the texture is dropped. Wait for the copy, so the
+    /// jump flooding can follow.
+    fn upload_brick_texture(
+        &self,
+        interface: &Interface,
+        staging: &BufferSet,
+        region_list: &[vk::BufferImageCopy],
+        old_layout: vk::ImageLayout,
+    ) {
+        unsafe {
             interface.record_submit_cmd(
                 interface.setup_cmd_fence,
                 interface.setup_cmd_buffer,
                 &[],
                 &[],
                 |cmd_buffer| {
+                    // Jump flooding of the last edit wrote the texture
                     let texture_barrier = vk::ImageMemoryBarrier {
+                        src_access_mask: vk::AccessFlags::SHADER_WRITE,
                         dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                        old_layout,
                         new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                        image: result.brick_texture.img,
+                        image: self.brick_texture.img,
                         subresource_range: vk::ImageSubresourceRange {
                             aspect_mask: vk::ImageAspectFlags::COLOR,
                             level_count: 1,
@@ -220,7 +289,7 @@ impl Engine {
                     };
                     interface.device.cmd_pipeline_barrier(
                         cmd_buffer,
-                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                        vk::PipelineStageFlags::COMPUTE_SHADER,
                         vk::PipelineStageFlags::TRANSFER,
                         vk::DependencyFlags::empty(),
                         &[],
@@ -228,33 +297,19 @@ impl Engine {
                         &[texture_barrier],
                     );
 
-                    let buffer_copy = vk::BufferImageCopy::builder()
-                        .image_subresource(
-                            vk::ImageSubresourceLayers::builder()
-                                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                                .layer_count(1)
-                                .build(),
-                        )
-                        .image_extent(vk::Extent3D {
-                            width: 4096,
-                            height: 4096,
-                            depth: 1,
-                        })
-                        .build();
-
                     interface.device.cmd_copy_buffer_to_image(
                         cmd_buffer,
-                        result.vk_img_buffer.buffer,
-                        result.brick_texture.img,
+                        staging.buffer,
+                        self.brick_texture.img,
                         vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                        &[buffer_copy],
+                        region_list,
                     );
                     let texture_barrier_end = vk::ImageMemoryBarrier {
                         src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
                         dst_access_mask: vk::AccessFlags::SHADER_READ,
                         old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                         new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                        image: result.brick_texture.img,
+                        image: self.brick_texture.img,
                         subresource_range: vk::ImageSubresourceRange {
                             aspect_mask: vk::ImageAspectFlags::COLOR,
                             level_count: 1,
@@ -266,7 +321,8 @@ impl Engine {
                     interface.device.cmd_pipeline_barrier(
                         cmd_buffer,
                         vk::PipelineStageFlags::TRANSFER,
-                        vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        vk::PipelineStageFlags::FRAGMENT_SHADER
+                            | vk::PipelineStageFlags::COMPUTE_SHADER,
                         vk::DependencyFlags::empty(),
                         &[],
                         &[],
@@ -275,7 +331,10 @@ impl Engine {
                 },
             );
 
-            result
+            interface
+                .device
+                .wait_for_fences(&[interface.setup_cmd_fence], true, u64::MAX)
+                .expect("DEVICE_LOST");
         }
     }
 
@@ -439,6 +498,13 @@ impl Engine {
                     1,
                     vk::ShaderStageFlags::FRAGMENT,
                     &interface.device,
+                )
+                // Material Set
+                .create_descriptor_set_layout(
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    1,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    &interface.device,
                 );
 
             result.pool_graphic = result
@@ -482,6 +548,15 @@ impl Engine {
                 &interface.device,
             );
 
+            result.pool_graphic.write_buffer_desc(
+                &self.material_buffer,
+                vk::WHOLE_SIZE,
+                4,
+                0,
+                vk::DescriptorType::STORAGE_BUFFER,
+                &interface.device,
+            );
+
             result.pipe_graphic = Pipe::create_graphic_pipe(
                 &interface.device,
                 &interface.surface,
@@ -582,10 +657,11 @@ impl Engine {
         }
     }
 
+    /// One jump flooding pass over every region of the brick texture.
     pub fn run_jfa_iteration(
         &self,
         interface: &Interface,
-        tex_extent: vk::Extent3D,
+        region_list: &[vk::Rect2D],
         dist_between: u32,
     ) {
         unsafe {
@@ -595,13 +671,6 @@ impl Engine {
                 &[],
                 &[],
                 |cmd_buffer| {
-                    let gcx = tex_extent.width / dist_between;
-                    let gcy = tex_extent.height / dist_between;
-
-                    let push = JFAPush {
-                        px_per_group: Vec2::new(dist_between as f32, dist_between as f32),
-                    };
-
                     // Dispatch Compute Pipe
                     interface.device.cmd_bind_pipeline(
                         cmd_buffer,
@@ -609,14 +678,6 @@ impl Engine {
                         self.jfa_pipe.pipe,
                     );
 
-                    interface.device.cmd_push_constants(
-                        cmd_buffer,
-                        self.jfa_pipe.pipe_layout,
-                        vk::ShaderStageFlags::COMPUTE,
-                        0,
-                        &[dist_between as u8],
-                    );
-
                     interface.device.cmd_bind_descriptor_sets(
                         cmd_buffer,
                         vk::PipelineBindPoint::COMPUTE,
@@ -626,124 +687,93 @@ impl Engine {
                         &[],
                     );
 
-                    interface.device.cmd_dispatch(cmd_buffer, gcx, gcy, 1);
+                    interface.gpu_scope(
+                        &interface.comp_timestamp_pool,
+                        cmd_buffer,
+                        &format!("jfa {}", dist_between),
+                        || {
+                            for region in region_list {
+                                let push = JFAPush {
+                                    offset: [region.offset.x as u32, region.offset.y as u32],
+                                    px_per_group: dist_between,
+                                };
+
+                                interface.device.cmd_push_constants(
+                                    cmd_buffer,
+                                    self.jfa_pipe.pipe_layout,
+                                    vk::ShaderStageFlags::COMPUTE,
+                                    0,
+                                    std::slice::from_raw_parts(
+                                        &push as *const JFAPush as *const u8,
+                                        mem::size_of::<JFAPush>(),
+                                    ),
+                                );
+
+                                interface.device.cmd_dispatch(
+                                    cmd_buffer,
+                                    region.extent.width / dist_between,
+                                    region.extent.height / dist_between,
+                                    1,
+                                );
+                            }
+                        },
+                    );
                 },
             )
         }
     }
 
+    /// Run every jump flooding pass over the whole brick texture.
+    pub fn run_jfa(&self, interface: &Interface) {
+        self.run_jfa_over(
+            interface,
+            &[vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent: vk::Extent2D {
+                    width: BRICK_TEXTURE_LEN,
+                    height: BRICK_TEXTURE_LEN,
+                },
+            }],
+        );
+    }
+
+    /// Run every jump flooding pass over the regions of the brick texture,
+    /// from 8 px between the seeds down to 1 px. The comp scopes
+    /// only hold the passes of the last run.
+    fn run_jfa_over(&self, interface: &Interface, region_list: &[vk::Rect2D]) {
+        if let Some(timestamp_pool) = &interface.comp_timestamp_pool {
+            timestamp_pool.clear();
+        }
+
+        self.run_jfa_iteration(interface, region_list, 1);
+        for idx in 0..4 {
+            self.run_jfa_iteration(interface, region_list, 8 >> idx);
+        }
+        self.run_jfa_iteration(interface, region_list, 1);
+    }
+
     pub fn draw_graphic(
         &self,
         interface: &Interface,
         pref: &Pref,
         uniform: &Uniform,
     ) -> Result<bool, Box<dyn Error>> {
-        unsafe {
-            interface.swap_draw_next(|present_index| {
-                interface.record_submit_cmd(
-                    interface.draw_cmd_fence,
-                    interface.draw_cmd_buffer,
-                    &[interface.present_complete],
-                    &[interface.render_complete],
-                    |cmd_buffer| {
-                        self.pool_graphic.write_buffer_desc(
-                            &self.uniform_buffer,
-                            vk::WHOLE_SIZE,
-                            0,
-                            0,
-                            vk::DescriptorType::UNIFORM_BUFFER,
-                            &interface.device,
-                        );
-
-                        let color_attachment_info = vk::RenderingAttachmentInfoKHR::builder()
-                            .image_view(self.image_target_list[present_index as usize].view)
-                            .load_op(vk::AttachmentLoadOp::CLEAR)
-                            .store_op(vk::AttachmentStoreOp::STORE)
-                            .clear_value(vk::ClearValue {
-                                color: vk::ClearColorValue {
-                                    float32: [1.0, 1.0, 1.0, 0.0],
-                                },
-                            })
-                            .build();
-
-                        let color_attachment_list = [color_attachment_info];
-
-                        let depth_attachment_info = vk::RenderingAttachmentInfo::builder()
-                            .image_view(self.depth_image.view)
-                            .load_op(vk::AttachmentLoadOp::CLEAR)
-                            .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                            .resolve_image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                            .clear_value(vk::ClearValue {
-                                depth_stencil: vk::ClearDepthStencilValue {
-                                    depth: 1.0,
-                                    stencil: 0,
-                                },
-                            })
-                            .build();
-
-                        let rendering_info = vk::RenderingInfoKHR::builder()
-                            .render_area(vk::Rect2D {
-                                offset: vk::Offset2D { x: 0, y: 0 },
-                                extent: interface.surface.render_res,
-                            })
-                            .layer_count(1)
-                            .color_attachments(&color_attachment_list)
-                            .depth_attachment(&depth_attachment_info)
-                            .build();
-
-                        // Dispatch Compute Pipe
-                        interface
-                            .device
-                            .cmd_begin_rendering(cmd_buffer, &rendering_info);
-
-                        interface.device.cmd_bind_descriptor_sets(
-                            cmd_buffer,
-                            vk::PipelineBindPoint::GRAPHICS,
-                            self.pipe_graphic.pipe_layout,
-                            0,
-                            &self.pool_graphic.set_list[..],
-                            &[],
-                        );
-
-                        interface.device.cmd_bind_pipeline(
-                            cmd_buffer,
-                            vk::PipelineBindPoint::GRAPHICS,
-                            self.pipe_graphic.pipe,
-                        );
-                        interface.device.cmd_set_viewport(
-                            cmd_buffer,
-                            0,
-                            &self.pipe_graphic.viewport,
-                        );
-
-                        interface
-                            .device
-                            .cmd_set_scissor(cmd_buffer, 0, &self.pipe_graphic.scissor);
-
-                        interface.device.cmd_bind_vertex_buffers(
-                            cmd_buffer,
-                            0,
-                            &[self.vertex_buffer.buffer],
-                            &[0],
-                        );
-
-                        interface.device.cmd_bind_index_buffer(
-                            cmd_buffer,
-                            self.index_buffer.buffer,
-                            0,
-                            vk::IndexType::UINT32,
-                        );
-
-                        interface.device.cmd_draw_indexed(
-                            cmd_buffer,
-                            self.index_data.len() as u32,
-                            1,
-                            0,
-                            0,
-                            1,
-                        );
+        interface.swap_draw_next(|present_index| {
+            interface.record_submit_cmd(
+                interface.draw_cmd_fence,
+                interface.draw_cmd_buffer,
+                &[interface.present_complete],
+                &[interface.render_complete],
+                |cmd_buffer| {
+                    let timestamp_pool = &interface.draw_timestamp_pool;
+                    if let Some(timestamp_pool) = timestamp_pool {
+                        timestamp_pool.clear();
+                    }
 
-                        interface.device.cmd_end_rendering(cmd_buffer);
+                    interface.gpu_scope(timestamp_pool, cmd_buffer, "frame", || {
+                        interface.gpu_scope(timestamp_pool, cmd_buffer, "graphic", || {
+                            self.record_graphic(interface, cmd_buffer, present_index as usize)
+                        });
 
                         // First Image Barrier
                         /*
@@ -755,15 +785,17 @@ impl Engine {
                         );
                         */
                         // Copy image memory
-                        self.pipe_comp.copy_image(
-                            &interface.device,
-                            cmd_buffer,
-                            pref,
-                            self.image_target_list[present_index as usize].img,
-                            interface.swapchain.img_list[present_index as usize],
-                            interface.surface.render_res,
-                            interface.surface.surface_res,
-                        );
+                        interface.gpu_scope(timestamp_pool, cmd_buffer, "copy", || {
+                            self.pipe_comp.copy_image(
+                                &interface.device,
+                                cmd_buffer,
+                                pref,
+                                self.image_target_list[present_index as usize].img,
+                                interface.swapchain.img_list[present_index as usize],
+                                interface.surface.render_res,
+                                interface.surface.surface_res,
+                            )
+                        });
                         /*
                         self.pipe_comp.sec_img_barrier(
                             interface.swapchain.img_list[present_index as usize],
@@ -771,12 +803,547 @@ impl Engine {
                             cmd_buffer,
                         );
                         */
+                    });
+                },
+            );
+        })
+    }
+
+    /// Record draw of the octree mesh into image target with idx.
+    /// Used by draw graphic and draw offscreen.
+    pub fn record_graphic(
+        &self,
+        interface: &Interface,
+        cmd_buffer: vk::CommandBuffer,
+        target_idx: usize,
+    ) {
+        unsafe {
+            self.pool_graphic.write_buffer_desc(
+                &self.uniform_buffer,
+                vk::WHOLE_SIZE,
+                0,
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                &interface.device,
+            );
+
+            let color_attachment_info = vk::RenderingAttachmentInfoKHR::builder()
+                .image_view(self.image_target_list[target_idx].view)
+                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [1.0, 1.0, 1.0, 0.0],
                     },
+                })
+                .build();
+
+            let color_attachment_list = [color_attachment_info];
+
+            let depth_attachment_info = vk::RenderingAttachmentInfo::builder()
+                .image_view(self.depth_image.view)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .resolve_image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .clear_value(vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    },
+                })
+                .build();
+
+            let rendering_info = vk::RenderingInfoKHR::builder()
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: interface.surface.render_res,
+                })
+                .layer_count(1)
+                .color_attachments(&color_attachment_list)
+                .depth_attachment(&depth_attachment_info)
+                .build();
+
+            interface
+                .device
+                .cmd_begin_rendering(cmd_buffer, &rendering_info);
+
+            interface.device.cmd_bind_descriptor_sets(
+                cmd_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipe_graphic.pipe_layout,
+                0,
+                &self.pool_graphic.set_list[..],
+                &[],
+            );
+
+            interface.device.cmd_bind_pipeline(
+                cmd_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipe_graphic.pipe,
+            );
+            interface
+                .device
+                .cmd_set_viewport(cmd_buffer, 0, &self.pipe_graphic.viewport);
+
+            interface
+                .device
+                .cmd_set_scissor(cmd_buffer, 0, &self.pipe_graphic.scissor);
+
+            interface.device.cmd_bind_vertex_buffers(
+                cmd_buffer,
+                0,
+                &[self.vertex_buffer.buffer],
+                &[0],
+            );
+
+            interface.device.cmd_bind_index_buffer(
+                cmd_buffer,
+                self.index_buffer.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+
+            interface
+                .device
+                .cmd_draw_indexed(
+                    cmd_buffer,
+                    self.cube_layout.index_data.len() as u32,
+                    1,
+                    0,
+                    0,
+                    1,
                 );
-            })
+
+            interface.device.cmd_end_rendering(cmd_buffer);
+        }
+    }
+
+    /// Render a single frame without swapchain and read it back.
+    /// Image target and depth image are moved into the right layout first,
+    /// after drawing the target is copied into a host visible buffer.
+    pub fn draw_offscreen(&self, interface: &Interface) -> image::RgbaImage {
+        unsafe {
+            let res = interface.surface.render_res;
+            let size = (res.width * res.height * 4) as u64;
+
+            log::info!("Creating ReadbackBuffer ...");
+            let readback_buffer = BufferSet::new(
+                size,
+                vk::BufferUsageFlags::TRANSFER_DST,
+                vk::SharingMode::EXCLUSIVE,
+                &interface.device,
+            )
+            .create_memory(
+                &interface.device,
+                &interface.phy_device,
+                align_of::<u8>() as u64,
+                size,
+                &vec![0u8; size as usize],
+            );
+
+            let target = &self.image_target_list[0];
+
+            interface.record_submit_cmd(
+                interface.draw_cmd_fence,
+                interface.draw_cmd_buffer,
+                &[],
+                &[],
+                |cmd_buffer| {
+                    let target_barrier = vk::ImageMemoryBarrier::builder()
+                        .image(target.img)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .subresource_range(SUBRES_RANGE)
+                        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                        .build();
+
+                    let depth_barrier = vk::ImageMemoryBarrier::builder()
+                        .image(self.depth_image.img)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::DEPTH,
+                            ..SUBRES_RANGE
+                        })
+                        .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                        .build();
+
+                    interface.device.cmd_pipeline_barrier(
+                        cmd_buffer,
+                        vk::PipelineStageFlags::TOP_OF_PIPE,
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[target_barrier, depth_barrier],
+                    );
+
+                    self.record_graphic(interface, cmd_buffer, 0);
+
+                    let transfer_barrier = vk::ImageMemoryBarrier::builder()
+                        .image(target.img)
+                        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .subresource_range(SUBRES_RANGE)
+                        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                        .build();
+
+                    interface.device.cmd_pipeline_barrier(
+                        cmd_buffer,
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &[transfer_barrier],
+                    );
+
+                    let buffer_copy = vk::BufferImageCopy::builder()
+                        .image_subresource(
+                            vk::ImageSubresourceLayers::builder()
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .layer_count(1)
+                                .build(),
+                        )
+                        .image_extent(res.into())
+                        .build();
+
+                    interface.device.cmd_copy_image_to_buffer(
+                        cmd_buffer,
+                        target.img,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        readback_buffer.buffer,
+                        &[buffer_copy],
+                    );
+                },
+            );
+
+            interface
+                .device
+                .wait_for_fences(&[interface.draw_cmd_fence], true, u64::MAX)
+                .expect("DEVICE_LOST");
+
+            let data = readback_buffer.read_mem::<u8>(interface, size as usize);
+            readback_buffer.destroy(&interface.device);
+
+            image::RgbaImage::from_raw(res.width, res.height, data).expect("ERR_READBACK_SIZE")
         }
     }
 
+    /// Upload the octant ranges changed since the last call through staging
+    /// buffers, the materials of the same ranges are copied along. After that
+    /// the cubes over the ranges are rewritten, so new nodes get their cube.
+    /// Buffers the octree outgrew are replaced by bigger ones.
+    /// Return false if nothing was dirty.
+    pub fn upload_dirty(&mut self, interface: &Interface, octree: &mut Octree) -> bool {
+        let dirty_list = octree.take_dirty();
+        if dirty_list.is_empty() {
+            return false;
+        }
+
+        unsafe {
+            // Last frame may still read the buffers
+            interface
+                .device
+                .wait_for_fences(&[interface.draw_cmd_fence], true, u64::MAX)
+                .expect("DEVICE_LOST");
+        }
+
+        // Edits can grow past the buffers, those are moved into bigger ones
+        if mem::size_of_val(&octree.octant_data[..]) as u64 > self.octree_buffer.mem_req.size {
+            self.octree_buffer =
+                self.grow_storage_buffer(interface, &self.octree_buffer, 1, &octree.octant_data);
+        }
+        if mem::size_of_val(&octree.material_data[..]) as u64 > self.material_buffer.mem_req.size {
+            self.material_buffer = self.grow_storage_buffer(
+                interface,
+                &self.material_buffer,
+                4,
+                &octree.material_data,
+            );
+        }
+
+        let octant_list: Vec<u32> = dirty_list
+            .iter()
+            .flat_map(|range| octree.octant_data[range.clone()].iter().copied())
+            .collect();
+        let material_list: Vec<Material> = dirty_list
+            .iter()
+            .flat_map(|range| octree.material_data[range.clone()].iter().copied())
+            .collect();
+
+        // Same region list for both buffers, only the element size differs
+        let region_list = |size: usize| {
+            let mut src_offset = 0;
+
+            dirty_list
+                .iter()
+                .map(|range| {
+                    let region = vk::BufferCopy {
+                        src_offset,
+                        dst_offset: (range.start * size) as u64,
+                        size: (range.len() * size) as u64,
+                    };
+                    src_offset += region.size;
+
+                    region
+                })
+                .collect::<Vec<vk::BufferCopy>>()
+        };
+        let octant_region_list = region_list(mem::size_of::<u32>());
+        let material_region_list = region_list(mem::size_of::<Material>());
+
+        unsafe {
+            log::info!(
+                "Uploading {} dirty octants in {} ranges ...",
+                octant_list.len(),
+                dirty_list.len()
+            );
+
+            let octant_staging = BufferSet::new(
+                mem::size_of_val(&octant_list[..]) as u64,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                vk::SharingMode::EXCLUSIVE,
+                &interface.device,
+            )
+            .create_memory(
+                &interface.device,
+                &interface.phy_device,
+                align_of::<u32>() as u64,
+                mem::size_of_val(&octant_list[..]) as u64,
+                &octant_list,
+            );
+
+            let material_staging = BufferSet::new(
+                mem::size_of_val(&material_list[..]) as u64,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                vk::SharingMode::EXCLUSIVE,
+                &interface.device,
+            )
+            .create_memory(
+                &interface.device,
+                &interface.phy_device,
+                align_of::<Material>() as u64,
+                mem::size_of_val(&material_list[..]) as u64,
+                &material_list,
+            );
+
+            interface.record_submit_cmd(
+                interface.setup_cmd_fence,
+                interface.setup_cmd_buffer,
+                &[],
+                &[],
+                |cmd_buffer| {
+                    interface.device.cmd_copy_buffer(
+                        cmd_buffer,
+                        octant_staging.buffer,
+                        self.octree_buffer.buffer,
+                        &octant_region_list,
+                    );
+                    interface.device.cmd_copy_buffer(
+                        cmd_buffer,
+                        material_staging.buffer,
+                        self.material_buffer.buffer,
+                        &material_region_list,
+                    );
+
+                    let barrier = vk::MemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                        .build();
+
+                    interface.device.cmd_pipeline_barrier(
+                        cmd_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::FRAGMENT_SHADER
+                            | vk::PipelineStageFlags::COMPUTE_SHADER,
+                        vk::DependencyFlags::empty(),
+                        &[barrier],
+                        &[],
+                        &[],
+                    );
+                },
+            );
+
+            interface
+                .device
+                .wait_for_fences(&[interface.setup_cmd_fence], true, u64::MAX)
+                .expect("DEVICE_LOST");
+
+            octant_staging.destroy(&interface.device);
+            material_staging.destroy(&interface.device);
+        }
+
+        self.rewrite_vert_data(interface, octree, &dirty_list);
+
+        true
+    }
+
+    /// Move a storage buffer of the graphic pipe into a new one with room
+    /// for data to grow, data is written and the descriptor of set points
+    /// to the new buffer. The old buffer must not be in use anymore.
+    fn grow_storage_buffer<Type: Copy>(
+        &self,
+        interface: &Interface,
+        buffer: &BufferSet,
+        set: usize,
+        data: &[Type],
+    ) -> BufferSet {
+        log::info!(
+            "Growing storage buffer of set {} to {} elements ...",
+            set,
+            data.len() * 2
+        );
+
+        let grown = BufferSet::new(
+            storage_capacity::<Type>(data.len()),
+            buffer.usage,
+            buffer.sharing_mode,
+            &interface.device,
+        )
+        .create_memory(
+            &interface.device,
+            &interface.phy_device,
+            align_of::<Type>() as u64,
+            mem::size_of_val(data) as u64,
+            data,
+        );
+        buffer.destroy(&interface.device);
+
+        self.pool_graphic.write_buffer_desc(
+            &grown,
+            vk::WHOLE_SIZE,
+            set,
+            0,
+            vk::DescriptorType::STORAGE_BUFFER,
+            &interface.device,
+        );
+
+        grown
+    }
+
+    /// Update the cube layout over the sorted dirty ranges and write the
+    /// slots it changed into the vertex buffers, which have room for every
+    /// cube. Only the changed bricks are uploaded and flooded again.
+    fn rewrite_vert_data(
+        &mut self,
+        interface: &Interface,
+        octree: &Octree,
+        dirty_list: &[Range<usize>],
+    ) {
+        let change = self
+            .cube_layout
+            .update(octree, dirty_list, Some(&mut self.img_buffer));
+
+        rewrite_slot_list(
+            interface,
+            &self.vertex_buffer,
+            &self.cube_layout.vertex_data,
+            BASE_CUBE_VERT.len(),
+            &change.slot_list,
+        );
+        rewrite_slot_list(
+            interface,
+            &self.index_buffer,
+            &self.cube_layout.index_data,
+            BASE_CUBE_IDX.len(),
+            &change.slot_list,
+        );
+        rewrite_slot_list(
+            interface,
+            &self.loc_info_buffer,
+            &self.cube_layout.loc_data,
+            1,
+            &change.loc_list,
+        );
+
+        if change.brick_list.is_empty() {
+            return;
+        }
+
+        unsafe {
+            // Jump flooding of the last edit may still use the texture
+            interface
+                .device
+                .wait_for_fences(&[interface.comp_cmd_fence], true, u64::MAX)
+                .expect("DEVICE_LOST");
+        }
+
+        // Bricks are packed one after the other into the staging buffer
+        let brick_size = (BRICK_WIDTH * BRICK_HEIGHT) as usize * mem::size_of::<image::Rgba<u8>>();
+        let mut brick_data: Vec<u8> = Vec::with_capacity(brick_size * change.brick_list.len());
+        let mut region_list = vec![];
+        let mut rect_list = vec![];
+
+        for &brick in &change.brick_list {
+            let px = brick_px(brick);
+            let offset = vk::Offset2D {
+                x: px.x as i32,
+                y: px.y as i32,
+            };
+
+            region_list.push(
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(brick_data.len() as u64)
+                    .image_subresource(BRICK_SUBRES_LAYERS)
+                    .image_offset(vk::Offset3D {
+                        x: offset.x,
+                        y: offset.y,
+                        z: 0,
+                    })
+                    .image_extent(vk::Extent3D {
+                        width: BRICK_WIDTH,
+                        height: BRICK_HEIGHT,
+                        depth: 1,
+                    })
+                    .build(),
+            );
+            rect_list.push(vk::Rect2D {
+                offset,
+                extent: vk::Extent2D {
+                    width: BRICK_WIDTH,
+                    height: BRICK_HEIGHT,
+                },
+            });
+
+            let row_size = BRICK_WIDTH as usize * mem::size_of::<image::Rgba<u8>>();
+            for y in px.y as u32..px.y as u32 + BRICK_HEIGHT {
+                let start = (y * BRICK_TEXTURE_LEN + px.x as u32) as usize
+                    * mem::size_of::<image::Rgba<u8>>();
+                brick_data.extend_from_slice(&self.img_buffer.as_raw()[start..start + row_size]);
+            }
+        }
+
+        log::info!("Uploading {} changed bricks ...", change.brick_list.len());
+
+        let brick_staging = BufferSet::new(
+            brick_data.len() as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::SharingMode::EXCLUSIVE,
+            &interface.device,
+        )
+        .create_memory(
+            &interface.device,
+            &interface.phy_device,
+            align_of::<u8>() as u64,
+            brick_data.len() as u64,
+            &brick_data,
+        );
+
+        self.upload_brick_texture(
+            interface,
+            &brick_staging,
+            &region_list,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        brick_staging.destroy(&interface.device);
+
+        self.run_jfa_over(interface, &rect_list);
+    }
+
     /// This function is called when the swapchain is outdated
     /// or has the wrong size basically whenever you change the window
     /// size or just minimize the window.
@@ -801,7 +1368,7 @@ impl Engine {
         interface.surface =
             interface
                 .surface
-                .get_surface_info(&interface.phy_device, &interface.window, pref);
+                .get_surface_info(&interface.phy_device, interface.window(), pref);
 
         uniform.apply_resolution(interface.surface.render_res);
 
@@ -860,6 +1427,7 @@ impl Engine {
             self.uniform_buffer.destroy(&interface.device);
 
             self.octree_buffer.destroy(&interface.device);
+            self.material_buffer.destroy(&interface.device);
             self.loc_info_buffer.destroy(&interface.device);
 
             self.pipe_graphic.drop(&interface.device);
@@ -867,6 +1435,30 @@ impl Engine {
     }
 }
 
+/// Byte size of a storage buffer for len values, with room for edits to grow.
+fn storage_capacity<Type>(len: usize) -> u64 {
+    (mem::size_of::<Type>() * (len * 2).max(MIN_STORAGE_LEN)) as u64
+}
+
+/// Write the slots of data into the host visible buffer, a slot is
+/// stride values long.
+fn rewrite_slot_list<Type: Copy>(
+    interface: &Interface,
+    buffer: &BufferSet,
+    data: &[Type],
+    stride: usize,
+    slot_list: &[usize],
+) {
+    for range in slot_ranges(slot_list) {
+        buffer.rewrite_mem_at(
+            interface,
+            align_of::<Type>() as u64,
+            (range.start * stride * mem::size_of::<Type>()) as u64,
+            &data[range.start * stride..range.end * stride],
+        );
+    }
+}
+
 impl Default for Engine {
     fn default() -> Self {
         Self {
@@ -875,11 +1467,12 @@ impl Default for Engine {
             img_buffer: Default::default(),
             vk_img_buffer: Default::default(),
             brick_texture: Default::default(),
-            index_data: Default::default(),
+            cube_layout: Default::default(),
             index_buffer: Default::default(),
             vertex_buffer: Default::default(),
             uniform_buffer: Default::default(),
             octree_buffer: Default::default(),
+            material_buffer: Default::default(),
             loc_info_buffer: Default::default(),
             pool_comp: Default::default(),
             pipe_comp: Default::default(),
@@ -0,0 +1,557 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use nalgebra_glm::{Vec2, Vec3, Vec4};
+
+use crate::{
+    pipe::{
+        obj::{BASE_CUBE_IDX, BASE_CUBE_UV, BASE_CUBE_VERT},
+        pipe::{LocInfo, Vertex, MAX_CUBE_COUNT, VERT_DATA_DEPTH},
+    },
+    tree::{
+        octant::Octant,
+        octree::{Octree, MAX_DEPTH, MAX_DEPTH_LIMIT, TEXTURE_ALIGN},
+        trace::{BranchInfo, PosInfo},
+    },
+    vector::Vector,
+};
+
+// Side length of the brick texture in px
+pub const BRICK_TEXTURE_LEN: u32 = 4096;
+// A brick is TEXTURE_ALIGN px wide and TEXTURE_ALIGN^2 px high
+pub const BRICK_WIDTH: u32 = TEXTURE_ALIGN as u32;
+pub const BRICK_HEIGHT: u32 = BRICK_WIDTH * BRICK_WIDTH;
+// Most bricks the brick texture has room for
+pub const MAX_BRICK_COUNT: usize =
+    ((BRICK_TEXTURE_LEN / BRICK_WIDTH) * (BRICK_TEXTURE_LEN / BRICK_HEIGHT)) as usize;
+
+// Freed bricks are reused first, so every cube there can be has room for its brick
+const _: () = assert!(MAX_BRICK_COUNT >= MAX_CUBE_COUNT);
+
+// Pixel of an empty brick, the jump flooding treats it as undefined
+const EMPTY_PX: image::Rgba<u8> = image::Rgba([0, 0, 0, 255]);
+
+// Bits of the pos on edge and of the span of a cube
+type CubeKey = ([u32; 3], u32);
+
+#[derive(Clone, Copy, Debug)]
+pub struct CubeSlot {
+    pub slot: usize,
+    // Only subdivided cubes have a brick
+    pub brick: Option<usize>,
+}
+
+/// Slots that an update of the cube layout wrote.
+#[derive(Clone, Debug, Default)]
+pub struct LayoutChange {
+    // Slots with new vertices and indices
+    pub slot_list: Vec<usize>,
+    // Slots with new loc info
+    pub loc_list: Vec<usize>,
+    // Bricks that were written or cleared
+    pub brick_list: Vec<usize>,
+}
+
+/// Vertex data of the cubes at vert data depth. Every cube keeps its slot
+/// in the vertex data and its brick over edits, so an update only writes the
+/// cubes that are new, gone or above dirty octants. Slots of gone cubes get
+/// empty triangles and are reused by the next new cube.
+#[derive(Clone, Debug, Default)]
+pub struct CubeLayout {
+    pub slot_map: HashMap<CubeKey, CubeSlot>,
+    pub free_slot_list: Vec<usize>,
+    pub free_brick_list: Vec<usize>,
+    pub brick_count: usize,
+
+    pub vertex_data: Vec<Vertex>,
+    pub index_data: Vec<u32>,
+    pub loc_data: Vec<LocInfo>,
+}
+
+/// Top left px of a brick in the brick texture, bricks fill the texture
+/// column by column.
+pub fn brick_px(brick: usize) -> Vec2 {
+    let length = (BRICK_HEIGHT as usize * brick) as f32;
+
+    Vec2::new(
+        (length / BRICK_TEXTURE_LEN as f32).floor() * TEXTURE_ALIGN,
+        length % BRICK_TEXTURE_LEN as f32,
+    )
+}
+
+/// Sorted slots merged into ranges of consecutive slots.
+pub fn slot_ranges(slot_list: &[usize]) -> Vec<Range<usize>> {
+    let mut slot_list = slot_list.to_vec();
+    slot_list.sort_unstable();
+    slot_list.dedup();
+
+    let mut range_list: Vec<Range<usize>> = vec![];
+    for slot in slot_list {
+        match range_list.last_mut() {
+            Some(last) if last.end == slot => last.end += 1,
+            _ => range_list.push(slot..slot + 1),
+        }
+    }
+
+    range_list
+}
+
+/// Every node at vert data depth, and the root if it is a single leaf.
+pub fn collect_cube_list(octree: &Octree) -> Vec<(PosInfo, [BranchInfo; MAX_DEPTH])> {
+    let (branch_data, pos_info) = octree.get_new_root_info(Vec4::default());
+    let mut cube_list = vec![];
+
+    if octree.octant_data[0].is_subdiv() {
+        octree.collect_branch(&branch_data, &pos_info, &mut cube_list, VERT_DATA_DEPTH);
+    } else if octree.octant_data[0].is_leaf() {
+        cube_list.push((pos_info, branch_data));
+    }
+
+    cube_list
+}
+
+/// True if the node at idx or a filled node below it is in one of the
+/// sorted dirty ranges.
+fn subtree_dirty(octree: &Octree, idx: usize, dirty_list: &[Range<usize>]) -> bool {
+    let range_idx = dirty_list.partition_point(|range| range.end <= idx);
+    if dirty_list
+        .get(range_idx)
+        .is_some_and(|range| range.contains(&idx))
+    {
+        return true;
+    }
+
+    let node = octree.octant_data[idx];
+    if !node.is_subdiv() {
+        return false;
+    }
+
+    let first_child_idx = node.get_first_child_idx() as usize;
+    (0..8).any(|mask| {
+        node.check_child_filled(mask)
+            && subtree_dirty(octree, first_child_idx + mask as usize, dirty_list)
+    })
+}
+
+/// Write value at slot, the data grows by one slot if slot is past its end.
+fn write_slot<Type: Copy>(data: &mut Vec<Type>, slot: usize, value: &[Type]) {
+    let start = slot * value.len();
+
+    if start == data.len() {
+        data.extend_from_slice(value);
+    } else {
+        data[start..start + value.len()].copy_from_slice(value);
+    }
+}
+
+fn cube_loc_info(pos_info: &PosInfo, branch_data: &[BranchInfo; MAX_DEPTH]) -> LocInfo {
+    let branch_info = branch_data[pos_info.depth_idx()];
+    let mut parent_list = [0; MAX_DEPTH_LIMIT];
+
+    // set to something that is not an actual index to indicate
+    // wether there is an active index in use or not
+    let mut last_hit_idx = [8; MAX_DEPTH_LIMIT];
+
+    branch_data
+        .iter()
+        .enumerate()
+        .for_each(|(idx, branch_info)| {
+            parent_list[idx] = branch_info.node;
+            last_hit_idx[idx] = branch_info.mask;
+        });
+
+    LocInfo {
+        parent_list,
+        last_hit_idx,
+        node_idx: branch_info.idx,
+        depth: pos_info.depth,
+        span: branch_info.span,
+
+        ..Default::default()
+    }
+}
+
+impl CubeLayout {
+    /// Layout of every cube of octree, with their bricks written into img.
+    pub fn new(octree: &Octree, img: Option<&mut image::RgbaImage>) -> Self {
+        let mut layout = Self::default();
+        layout.update(
+            octree,
+            std::slice::from_ref(&(0..octree.octant_data.len())),
+            img,
+        );
+
+        layout
+    }
+
+    /// Place the cubes of octree, cubes that are gone free their slot and brick.
+    /// Bricks of new cubes and of cubes above the sorted dirty ranges are
+    /// written into img again. Without img only the slots are kept.
+    pub fn update(
+        &mut self,
+        octree: &Octree,
+        dirty_list: &[Range<usize>],
+        mut img: Option<&mut image::RgbaImage>,
+    ) -> LayoutChange {
+        let mut change = LayoutChange::default();
+        let cube_list = collect_cube_list(octree);
+
+        let key_list: Vec<CubeKey> = cube_list
+            .iter()
+            .map(|(pos_info, branch_data)| {
+                let pos = pos_info.pos_on_edge;
+                let span = branch_data[pos_info.depth_idx()].span;
+
+                (
+                    [pos.x.to_bits(), pos.y.to_bits(), pos.z.to_bits()],
+                    span.to_bits(),
+                )
+            })
+            .collect();
+
+        // Gone cubes are freed first, so new cubes reuse their slots
+        let key_set: HashSet<&CubeKey> = key_list.iter().collect();
+        let mut gone_list: Vec<CubeKey> = self
+            .slot_map
+            .keys()
+            .filter(|key| !key_set.contains(key))
+            .copied()
+            .collect();
+        gone_list.sort_by_key(|key| std::cmp::Reverse(self.slot_map[key].slot));
+
+        for key in gone_list {
+            let cube = self.slot_map.remove(&key).unwrap();
+
+            write_slot(&mut self.index_data, cube.slot, &[0; BASE_CUBE_IDX.len()]);
+            change.slot_list.push(cube.slot);
+            self.free_slot_list.push(cube.slot);
+
+            if let Some(brick) = cube.brick {
+                self.free_brick(brick, img.as_deref_mut(), &mut change);
+            }
+        }
+
+        for ((pos_info, branch_data), key) in cube_list.iter().zip(key_list) {
+            let branch_info = branch_data[pos_info.depth_idx()];
+            let loc_info = cube_loc_info(pos_info, branch_data);
+
+            let mut cube = match self.slot_map.get(&key) {
+                Some(&cube) => {
+                    if self.loc_data[cube.slot] != loc_info {
+                        self.loc_data[cube.slot] = loc_info;
+                        change.loc_list.push(cube.slot);
+                    }
+
+                    cube
+                }
+                None => {
+                    let slot = self.free_slot_list.pop().unwrap_or(self.loc_data.len());
+                    self.write_cube(slot, pos_info, &branch_info, loc_info);
+                    change.slot_list.push(slot);
+                    change.loc_list.push(slot);
+
+                    CubeSlot { slot, brick: None }
+                }
+            };
+
+            match (cube.brick, branch_info.node.is_subdiv()) {
+                (Some(brick), false) => {
+                    self.free_brick(brick, img.as_deref_mut(), &mut change);
+                    cube.brick = None;
+                }
+                (Some(brick), true) => {
+                    if subtree_dirty(octree, branch_info.idx(), dirty_list) {
+                        self.write_brick(octree, brick, pos_info, branch_data, img.as_deref_mut());
+                        change.brick_list.push(brick);
+                    }
+                }
+                (None, true) => {
+                    let brick = self.alloc_brick();
+                    self.write_brick(octree, brick, pos_info, branch_data, img.as_deref_mut());
+                    change.brick_list.push(brick);
+                    cube.brick = Some(brick);
+                }
+                (None, false) => {}
+            }
+
+            self.slot_map.insert(key, cube);
+        }
+
+        change
+    }
+
+    fn write_cube(
+        &mut self,
+        slot: usize,
+        pos_info: &PosInfo,
+        branch_info: &BranchInfo,
+        loc_info: LocInfo,
+    ) {
+        let center = pos_info.pos_on_edge.xyz() + Vec3::ftv(branch_info.span / 2.0);
+
+        let vertex_list: Vec<Vertex> = BASE_CUBE_VERT
+            .iter()
+            .enumerate()
+            .map(|(vert_idx, coord)| Vertex {
+                pos: [
+                    coord.0 * branch_info.span + center.x,
+                    coord.1 * branch_info.span + center.y,
+                    coord.2 * branch_info.span + center.z,
+                    1.0,
+                ],
+                pos_on_edge: [
+                    pos_info.pos_on_edge.x,
+                    pos_info.pos_on_edge.y,
+                    pos_info.pos_on_edge.z,
+                    0.0,
+                ],
+                uv: [
+                    BASE_CUBE_UV[vert_idx].0 as f32,
+                    BASE_CUBE_UV[vert_idx].1 as f32,
+                ],
+                loc_idx: slot as u32,
+            })
+            .collect();
+
+        let index_list: Vec<u32> = BASE_CUBE_IDX
+            .iter()
+            .map(|idx| (idx + (slot * BASE_CUBE_VERT.len()) as i32) as u32)
+            .collect();
+
+        write_slot(&mut self.vertex_data, slot, &vertex_list);
+        write_slot(&mut self.index_data, slot, &index_list);
+        write_slot(&mut self.loc_data, slot, &[loc_info]);
+    }
+
+    fn alloc_brick(&mut self) -> usize {
+        self.free_brick_list.pop().unwrap_or_else(|| {
+            self.brick_count += 1;
+            self.brick_count - 1
+        })
+    }
+
+    fn free_brick(
+        &mut self,
+        brick: usize,
+        img: Option<&mut image::RgbaImage>,
+        change: &mut LayoutChange,
+    ) {
+        if let Some(img) = img {
+            clear_brick(img, brick);
+        }
+
+        self.free_brick_list.push(brick);
+        change.brick_list.push(brick);
+    }
+
+    fn write_brick(
+        &self,
+        octree: &Octree,
+        brick: usize,
+        pos_info: &PosInfo,
+        branch_data: &[BranchInfo; MAX_DEPTH],
+        img: Option<&mut image::RgbaImage>,
+    ) {
+        let Some(img) = img else {
+            return;
+        };
+
+        clear_brick(img, brick);
+        octree.write_branch_to_texture(
+            branch_data,
+            pos_info,
+            img,
+            brick_px(brick),
+            pos_info.pos_on_edge,
+            TEXTURE_ALIGN,
+            MAX_DEPTH as u32,
+        );
+    }
+}
+
+fn clear_brick(img: &mut image::RgbaImage, brick: usize) {
+    let px = brick_px(brick);
+    let width = img.width();
+
+    for y in px.y as u32..px.y as u32 + BRICK_HEIGHT {
+        let start = (y * width + px.x as u32) as usize * EMPTY_PX.0.len();
+        let row = &mut img.as_mut()[start..start + BRICK_WIDTH as usize * EMPTY_PX.0.len()];
+
+        row.chunks_exact_mut(EMPTY_PX.0.len())
+            .for_each(|px| px.copy_from_slice(&EMPTY_PX.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+
+    use super::{brick_px, slot_ranges, CubeLayout, BRICK_TEXTURE_LEN, MAX_BRICK_COUNT};
+    use crate::{
+        pipe::pipe::MAX_CUBE_COUNT,
+        tree::{
+            gen::{self, SceneKind},
+            material::Material,
+            octant::Octant,
+            octree::Octree,
+        },
+    };
+
+    #[test]
+    fn root_leaf_is_one_cube() {
+        let octree = Octree::filled(256.0, Material::default());
+        let layout = CubeLayout::new(&octree, None);
+
+        assert_eq!(layout.loc_data.len(), 1);
+        assert_eq!(
+            (layout.loc_data[0].depth, layout.loc_data[0].span),
+            (0, 256.0)
+        );
+        assert_eq!(layout.loc_data[0].node_idx, 0);
+        assert_eq!(layout.index_data.len(), 36);
+        assert!(layout.vertex_data.iter().all(|vertex| vertex.pos[..3]
+            .iter()
+            .all(|&value| value == 0.0 || value == 256.0)));
+
+        assert!(CubeLayout::new(&Octree::default(), None)
+            .vertex_data
+            .is_empty());
+    }
+
+    #[test]
+    fn loc_points_at_cube_node() {
+        let mut octree = Octree::default();
+        for pos in [[0.0, 0.0, 0.0], [100.0, 4.0, 30.0], [250.0, 250.0, 250.0]] {
+            octree.insert_node(Vec4::new(pos[0], pos[1], pos[2], 0.0), Material::default());
+        }
+        octree.insert_node_at_depth(Vec4::new(10.0, 200.0, 10.0, 0.0), Material::default(), 3);
+
+        let layout = CubeLayout::new(&octree, None);
+
+        assert_eq!(layout.loc_data.len(), 4);
+        for loc in layout.loc_data {
+            let node = octree.octant_data[loc.node_idx as usize];
+
+            assert_eq!(node, loc.parent_list[loc.depth as usize]);
+            assert!(node.is_leaf() || node.is_subdiv());
+        }
+    }
+
+    #[test]
+    fn slot_ranges_merge() {
+        assert!(slot_ranges(&[]).is_empty());
+        assert_eq!(slot_ranges(&[7, 1, 2, 2, 3, 9, 8]), vec![1..4, 7..10]);
+    }
+
+    #[test]
+    fn bricks_fit_texture() {
+        let last = brick_px(MAX_BRICK_COUNT - 1);
+
+        assert_eq!(brick_px(0), brick_px(0) * 0.0);
+        assert!(last.x < BRICK_TEXTURE_LEN as f32 && last.y < BRICK_TEXTURE_LEN as f32);
+        assert_eq!(brick_px(MAX_BRICK_COUNT).x, BRICK_TEXTURE_LEN as f32);
+    }
+
+    #[test]
+    fn every_scene_fits_texture() {
+        for kind in [
+            SceneKind::TEST,
+            SceneKind::TERRAIN,
+            SceneKind::CAVES,
+            SceneKind::PRIMITIVES,
+        ] {
+            let octree = gen::generate(kind, 0);
+            let mut img = image::RgbaImage::new(BRICK_TEXTURE_LEN, BRICK_TEXTURE_LEN);
+            let layout = CubeLayout::new(&octree, Some(&mut img));
+
+            assert!(!layout.loc_data.is_empty(), "{:?}", kind);
+            assert!(layout.brick_count <= MAX_BRICK_COUNT, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn full_tree_fits_texture() {
+        // One voxel in every node at vert data depth - 1, so every possible cube is there
+        let mut octree = Octree::default();
+        for x in 0..32 {
+            for y in 0..32 {
+                for z in 0..32 {
+                    let pos = Vec4::new(x as f32, y as f32, z as f32, 0.0) * 8.0;
+                    octree.insert_node(pos, Material::default());
+                }
+            }
+        }
+
+        let layout = CubeLayout::new(&octree, None);
+
+        assert_eq!(layout.loc_data.len(), MAX_CUBE_COUNT);
+        assert_eq!(layout.brick_count, MAX_BRICK_COUNT);
+        assert_eq!(
+            layout
+                .slot_map
+                .values()
+                .filter(|cube| cube.brick.is_some())
+                .count(),
+            MAX_BRICK_COUNT
+        );
+    }
+
+    #[test]
+    fn edit_writes_only_its_cube() {
+        let mut octree = Octree::default();
+        for pos in [[0.0, 0.0, 0.0], [100.0, 4.0, 30.0], [250.0, 250.0, 250.0]] {
+            octree.insert_node(Vec4::new(pos[0], pos[1], pos[2], 0.0), Material::default());
+        }
+        octree.take_dirty();
+
+        let mut img = image::RgbaImage::new(BRICK_TEXTURE_LEN, BRICK_TEXTURE_LEN);
+        let mut layout = CubeLayout::new(&octree, Some(&mut img));
+        let vertex_data = layout.vertex_data.clone();
+
+        // Next to the first leaf, same cube and brick
+        octree.insert_node(Vec4::new(2.0, 0.0, 0.0, 0.0), Material::default());
+        let dirty_list = octree.take_dirty();
+        let change = layout.update(&octree, &dirty_list, Some(&mut img));
+
+        assert!(change.slot_list.is_empty());
+        assert_eq!(change.brick_list, vec![0]);
+        assert_eq!(layout.vertex_data, vertex_data);
+
+        // New cube gets the next slot
+        octree.insert_node(Vec4::new(128.0, 128.0, 128.0, 0.0), Material::default());
+        let dirty_list = octree.take_dirty();
+        let change = layout.update(&octree, &dirty_list, Some(&mut img));
+
+        assert_eq!(change.slot_list, vec![3]);
+        assert_eq!(change.brick_list, vec![3]);
+
+        // Gone cube frees its slot and brick, the next new cube reuses them
+        octree.remove_node(Vec4::new(100.0, 4.0, 30.0, 0.0));
+        let dirty_list = octree.take_dirty();
+        let change = layout.update(&octree, &dirty_list, Some(&mut img));
+
+        assert_eq!(change.slot_list, vec![1]);
+        assert_eq!(change.brick_list, vec![1]);
+        assert_eq!(layout.index_data[36..72], [0; 36]);
+
+        octree.insert_node(Vec4::new(60.0, 60.0, 60.0, 0.0), Material::default());
+        let dirty_list = octree.take_dirty();
+        let change = layout.update(&octree, &dirty_list, Some(&mut img));
+
+        assert_eq!(change.slot_list, vec![1]);
+        assert_eq!(change.brick_list, vec![1]);
+
+        let node_idx_list = |layout: &CubeLayout| {
+            let mut node_idx_list: Vec<u32> =
+                layout.loc_data.iter().map(|loc| loc.node_idx).collect();
+            node_idx_list.sort_unstable();
+            node_idx_list
+        };
+        assert_eq!(
+            node_idx_list(&layout),
+            node_idx_list(&CubeLayout::new(&octree, None))
+        );
+    }
+}
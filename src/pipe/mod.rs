@@ -1,6 +1,8 @@
 pub mod buffer;
+pub mod cpu;
 pub mod descriptor;
 pub mod engine;
 pub mod image;
+pub mod layout;
 pub mod pipe;
 pub mod obj;
\ No newline at end of file
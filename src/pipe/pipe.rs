@@ -1,24 +1,17 @@
 use std::{ffi::CString, io::Cursor, mem};
 
 use ash::{util::read_spv, vk::{self, PushConstantRange}, Device};
-use cgmath::num_traits::Pow;
-use nalgebra_glm::{Vec2, Vec3, Vec4};
 
 use crate::{
-    interface::{interface::Interface, surface::SurfaceGroup},
+    interface::surface::SurfaceGroup,
     offset_of,
-    pipe::obj::{BASE_CUBE_IDX, BASE_CUBE_UV, BASE_CUBE_VERT},
-    tree::{
-        octant::Octant,
-        octree::{Octree, MAX_DEPTH, MAX_DEPTH_LIMIT, TEXTURE_ALIGN},
-    },
-    vector::Vector,
+    tree::octree::MAX_DEPTH_LIMIT,
     Pref,
 };
 
 use super::{descriptor::DescriptorPool, image::ImageTarget};
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct Vertex {
     pub pos: [f32; 4],
     pub pos_on_edge: [f32; 4],
@@ -26,14 +19,19 @@ pub struct Vertex {
     pub loc_idx: u32,
 }
 
-#[derive(Clone, Debug, Copy)]
+// Same layout as LocInfo in the fragment shaders
+#[repr(C)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub struct LocInfo {
     pub parent_list: [u32; MAX_DEPTH_LIMIT],
     pub last_hit_idx: [u32; MAX_DEPTH_LIMIT],
+
+    // Idx of the cube node in octant data
+    pub node_idx: u32,
+    pub padding: u32,
+
     pub depth: u32,
     pub span: f32,
-
-    padding: [u32; 2],
 }
 
 #[derive(Clone)]
@@ -54,11 +52,21 @@ pub struct Pipe {
     pub pipe: vk::Pipeline,
 }
 
+// Same layout as the push constant of the jump flooding
+#[repr(C)]
 #[derive(Clone, Debug, Copy)]
 pub struct JFAPush {
-    pub px_per_group: Vec2,
+    // Top left px of the dispatched region
+    pub offset: [u32; 2],
+    pub px_per_group: u32,
 }
 
+// Octree is drawn as one cube per node above this depth
+pub const VERT_DATA_DEPTH: u32 = 6;
+// Most cubes there can be, one per node at VERT_DATA_DEPTH - 2,
+// the vertex buffers are created for this count
+pub const MAX_CUBE_COUNT: usize = 1 << (3 * (VERT_DATA_DEPTH as usize - 2));
+
 // "../../shader/comp.spv"
 // include_bytes!("../../shader/comp.spv")
 
@@ -117,99 +125,6 @@ impl Pipe {
         }
     }
 
-    pub fn get_octree_vert_data(
-        octree: &Octree,
-        img: &mut image::ImageBuffer<image::Rgba<u8>, Vec<u8>>,
-    ) -> (Vec<Vertex>, Vec<u32>, Vec<LocInfo>) {
-        let mut vertex_data = vec![];
-        let mut index_data = vec![];
-        let mut loc_data = vec![];
-
-        let (branch_data, pos_info) = octree.get_new_root_info(Vec4::default());
-        let mut leaf_data = vec![];
-        octree.collect_branch(&branch_data, &pos_info, &mut leaf_data, 6);
-
-        // log::info!("{:#034b}", leaf_data[0].1.node.get_child_bitmask());
-
-        leaf_data
-            .iter()
-            .enumerate()
-            .for_each(|(leaf_idx, (pos_info, loc_branch_data))| {
-                let branch_info = loc_branch_data[pos_info.depth_idx()];
-                let center = pos_info.local_pos.xyz() * 2.0 + Vec3::ftv(branch_info.span / 2.0);
-
-                let length = TEXTURE_ALIGN.pow(2) as f32 * leaf_idx as f32;
-                let base_px = Vec2::new(
-                    (length / img.height() as f32).floor() * TEXTURE_ALIGN,
-                    length % img.height() as f32,
-                );
-
-                octree.write_branch_to_texture(
-                    loc_branch_data,
-                    pos_info,
-                    img,
-                    base_px,
-                    pos_info.local_pos,
-                    TEXTURE_ALIGN,
-                    MAX_DEPTH as u32,
-                );
-
-                BASE_CUBE_VERT
-                    .iter()
-                    .enumerate()
-                    .for_each(|(vert_idx, coord)| {
-                        vertex_data.push(Vertex {
-                            pos: [
-                                coord.0 * branch_info.span + center.x,
-                                coord.1 * branch_info.span + center.y,
-                                coord.2 * branch_info.span + center.z,
-                                1.0,
-                            ],
-                            pos_on_edge: [
-                                pos_info.local_pos.x,
-                                pos_info.local_pos.y,
-                                pos_info.local_pos.z,
-                                0.0,
-                            ],
-                            uv: [
-                                BASE_CUBE_UV[vert_idx].0 as f32,
-                                BASE_CUBE_UV[vert_idx].1 as f32,
-                            ],
-                            loc_idx: loc_data.len() as u32,
-                        });
-                    });
-
-                BASE_CUBE_IDX
-                    .iter()
-                    .for_each(|idx| index_data.push((idx + (leaf_idx as i32) * 24) as u32));
-
-                let mut parent_list = [0; MAX_DEPTH_LIMIT];
-
-                // set to something that is not an actual index to indicate
-                // wether there is an active index in use or not
-                let mut last_hit_idx = [8; MAX_DEPTH_LIMIT];
-
-                loc_branch_data
-                    .iter()
-                    .enumerate()
-                    .for_each(|(idx, branch_info)| {
-                        parent_list[idx] = branch_info.node;
-                        last_hit_idx[idx] = branch_info.mask;
-                    });
-
-                loc_data.push(LocInfo {
-                    parent_list,
-                    last_hit_idx,
-                    depth: pos_info.depth,
-                    span: branch_info.span,
-
-                    ..Default::default()
-                });
-            });
-
-        (vertex_data, index_data, loc_data)
-    }
-
     pub fn create_graphic_pipe(
         device: &Device,
         surface: &SurfaceGroup,
@@ -573,9 +488,10 @@ impl Default for LocInfo {
         Self {
             parent_list: Default::default(),
             last_hit_idx: Default::default(),
+            node_idx: Default::default(),
+            padding: Default::default(),
             depth: Default::default(),
             span: Default::default(),
-            padding: Default::default(),
         }
     }
 }
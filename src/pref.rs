@@ -0,0 +1,539 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use ash::vk;
+
+use crate::tree::gen::SceneKind;
+
+/// Read at startup if no settings file is given with --pref.
+pub const DEFAULT_PREF_FILE: &str = "pref.toml";
+
+// General Setting
+pub struct Pref {
+    pub pref_present_mode: vk::PresentModeKHR,
+    pub img_filter: vk::Filter,
+    pub img_scale: f32,
+
+    pub name: String,
+    pub engine_name: String,
+
+    pub start_window_size: vk::Extent2D,
+
+    pub use_render_res: bool,
+    pub render_res: vk::Extent2D,
+
+    pub mov_speed: f32,
+    // Key bindings, see Input::parse_bindings
+    pub binding_file: Option<PathBuf>,
+
+    pub scene: SceneKind,
+    pub seed: u32,
+    // Load scene from file instead of generating it
+    pub scene_file: Option<PathBuf>,
+    // Upload far nodes at lower resolution, see Octree::lod_by_distance
+    pub lod_detail: Option<f32>,
+    // Upload only this many depth levels, see Octree::truncate
+    pub lod_depth: Option<usize>,
+    // Upload shared subtrees only once, see Octree::to_dag
+    pub use_dag: bool,
+
+    // Load chunks around the cam instead of a single root, a vox scene
+    // file bigger than the root is split over the chunks, see World
+    pub world_radius: Option<[i32; 3]>,
+
+    // Render one image on the cpu and exit, no window is created
+    pub cpu_render_file: Option<PathBuf>,
+    // Render one image with vulkan but without window and exit
+    pub offscreen_file: Option<PathBuf>,
+
+    // Save the cam state of every frame on exit, see CamPath
+    pub record_file: Option<PathBuf>,
+    // Drive the cam by a recorded path instead of the input and exit at its end
+    pub replay_file: Option<PathBuf>,
+    // Time between replayed frames in seconds, independent of the frame time
+    pub replay_step: f32,
+
+    // Draw bench frames along the replay file or an orbit, write the stats and exit
+    pub bench_file: Option<PathBuf>,
+    pub bench_frames: u32,
+
+    // Log the gpu time of every labelled pass, see TimestampPool
+    pub profile_gpu: bool,
+}
+
+impl Default for Pref {
+    fn default() -> Self {
+        Self {
+            pref_present_mode: vk::PresentModeKHR::IMMEDIATE,
+            img_filter: vk::Filter::LINEAR,
+            img_scale: 1.0,
+
+            name: env!("CARGO_PKG_NAME").to_string(),
+            engine_name: "Engine".to_string(),
+
+            start_window_size: vk::Extent2D {
+                width: 800,
+                height: 600,
+            },
+
+            use_render_res: true,
+            render_res: vk::Extent2D {
+                width: 1920,
+                height: 1080,
+            },
+
+            mov_speed: 0.05,
+            binding_file: None,
+
+            scene: SceneKind::TEST,
+            seed: 0,
+            scene_file: None,
+            lod_detail: None,
+            lod_depth: None,
+            use_dag: false,
+
+            world_radius: None,
+
+            cpu_render_file: None,
+            offscreen_file: None,
+
+            record_file: None,
+            replay_file: None,
+            replay_step: 1.0 / 60.0,
+
+            bench_file: None,
+            bench_frames: 1000,
+
+            profile_gpu: false,
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, Box<dyn Error>> {
+    unquote(value)
+        .parse::<T>()
+        .map_err(|_| invalid_data(format!("ERR_PREF_VALUE -> {} = {}", key, value)))
+}
+
+/// List like [1, 2, 3], the brackets are optional.
+fn parse_list<T: FromStr + Copy + Default, const N: usize>(
+    key: &str,
+    value: &str,
+) -> Result<[T; N], Box<dyn Error>> {
+    let value_list: Vec<&str> = unquote(value)
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .collect();
+
+    if value_list.len() != N {
+        return Err(invalid_data(format!(
+            "ERR_PREF_LIST_LEN -> {} = {}",
+            key, value
+        )));
+    }
+
+    let mut list = [T::default(); N];
+    for (idx, item) in value_list.iter().enumerate() {
+        list[idx] = parse_value(key, item)?;
+    }
+
+    Ok(list)
+}
+
+fn parse_extent(key: &str, value: &str) -> Result<vk::Extent2D, Box<dyn Error>> {
+    let [width, height] = parse_list(key, value)?;
+
+    Ok(vk::Extent2D { width, height })
+}
+
+/// Optional values are cleared with none.
+fn parse_option<T, Function: Fn(&str) -> Result<T, Box<dyn Error>>>(
+    value: &str,
+    parse: Function,
+) -> Result<Option<T>, Box<dyn Error>> {
+    match unquote(value) {
+        "none" => Ok(None),
+        value => parse(value).map(Some),
+    }
+}
+
+fn parse_path(value: &str) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(PathBuf::from(unquote(value)))
+}
+
+impl Pref {
+    /// Set a single value by name, used for the file and the command line.
+    /// Names are the field names, except present_mode and window_size.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        match key {
+            "present_mode" => {
+                self.pref_present_mode = match unquote(value) {
+                    "IMMEDIATE" => vk::PresentModeKHR::IMMEDIATE,
+                    "MAILBOX" => vk::PresentModeKHR::MAILBOX,
+                    "FIFO" => vk::PresentModeKHR::FIFO,
+                    "FIFO_RELAXED" => vk::PresentModeKHR::FIFO_RELAXED,
+                    _ => {
+                        return Err(invalid_data(format!(
+                            "ERR_PREF_VALUE -> {} = {}",
+                            key, value
+                        )))
+                    }
+                }
+            }
+            "img_filter" => {
+                self.img_filter = match unquote(value) {
+                    "NEAREST" => vk::Filter::NEAREST,
+                    "LINEAR" => vk::Filter::LINEAR,
+                    _ => {
+                        return Err(invalid_data(format!(
+                            "ERR_PREF_VALUE -> {} = {}",
+                            key, value
+                        )))
+                    }
+                }
+            }
+            "img_scale" => self.img_scale = parse_value(key, value)?,
+
+            "name" => self.name = unquote(value).to_string(),
+
+            "window_size" => self.start_window_size = parse_extent(key, value)?,
+            "use_render_res" => self.use_render_res = parse_value(key, value)?,
+            "render_res" => self.render_res = parse_extent(key, value)?,
+
+            "mov_speed" => self.mov_speed = parse_value(key, value)?,
+            "binding_file" => self.binding_file = parse_option(value, parse_path)?,
+
+            "scene" => self.scene = parse_value(key, value)?,
+            "seed" => self.seed = parse_value(key, value)?,
+            "scene_file" => self.scene_file = parse_option(value, parse_path)?,
+            "lod_detail" => self.lod_detail = parse_option(value, |value| parse_value(key, value))?,
+            "lod_depth" => self.lod_depth = parse_option(value, |value| parse_value(key, value))?,
+            "use_dag" => self.use_dag = parse_value(key, value)?,
+            "world_radius" => {
+                self.world_radius = parse_option(value, |value| parse_list(key, value))?
+            }
+
+            "cpu_render_file" => self.cpu_render_file = parse_option(value, parse_path)?,
+            "offscreen_file" => self.offscreen_file = parse_option(value, parse_path)?,
+
+            "record_file" => self.record_file = parse_option(value, parse_path)?,
+            "replay_file" => self.replay_file = parse_option(value, parse_path)?,
+            "replay_step" => self.replay_step = parse_value(key, value)?,
+
+            "bench_file" => self.bench_file = parse_option(value, parse_path)?,
+            "bench_frames" => self.bench_frames = parse_value(key, value)?,
+
+            "profile_gpu" => self.profile_gpu = parse_value(key, value)?,
+
+            _ => return Err(invalid_data(format!("ERR_PREF_KEY -> {}", key))),
+        }
+
+        Ok(())
+    }
+
+    /// TOML file with the keys of set at the top level. Options are cleared
+    /// with "none" or keep their default, if the key is missing.
+    pub fn parse(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        let table: toml::Table = text.parse()?;
+
+        for (key, value) in table {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Array(item_list) => item_list
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                toml::Value::Table(_) | toml::Value::Datetime(_) => {
+                    return Err(invalid_data(format!(
+                        "ERR_PREF_VALUE -> {} = {}",
+                        key, value
+                    )))
+                }
+                value => value.to_string(),
+            };
+
+            self.set(&key, &value)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        log::info!("Reading pref file {} ...", path.display());
+
+        self.parse(&fs::read_to_string(path)?)
+    }
+
+    /// Defaults, then the settings file, then the command line. Arguments
+    /// are --key value or --key=value with the keys of set, a flag without
+    /// value is set to true. Values may start with a single -, like -1. The file is given with --pref, otherwise
+    /// the default file is read if it exists.
+    pub fn from_args<Args: IntoIterator<Item = String>>(
+        arg_list: Args,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut pref_file = None;
+        let mut override_list = vec![];
+
+        let mut arg_list = arg_list.into_iter().peekable();
+        while let Some(arg) = arg_list.next() {
+            let arg = arg
+                .strip_prefix("--")
+                .ok_or_else(|| invalid_data(format!("ERR_PREF_ARG -> {}", arg)))?;
+
+            let (key, value) = match arg.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => {
+                    let value = match arg_list.peek() {
+                        Some(next) if !next.starts_with("--") => {
+                            arg_list.next().unwrap_or_default()
+                        }
+                        _ => "true".to_string(),
+                    };
+
+                    (arg.to_string(), value)
+                }
+            };
+            let key = key.replace('-', "_");
+
+            if key == "pref" {
+                pref_file = Some(PathBuf::from(value));
+            } else {
+                override_list.push((key, value));
+            }
+        }
+
+        let mut pref = Pref::default();
+
+        match pref_file {
+            Some(path) => pref.load(&path)?,
+            None if Path::new(DEFAULT_PREF_FILE).exists() => {
+                pref.load(Path::new(DEFAULT_PREF_FILE))?
+            }
+            None => (),
+        }
+
+        for (key, value) in override_list {
+            pref.set(&key, &value)?;
+        }
+
+        pref.validate()?;
+
+        Ok(pref)
+    }
+
+    /// Lod or dag upload, it is built from the octree and can not be patched
+    /// with the dirty ranges of the octree.
+    pub fn reduced_upload(&self) -> bool {
+        self.lod_detail.is_some() || self.lod_depth.is_some() || self.use_dag
+    }
+
+    /// Reject values that can not work. The present mode is checked when the
+    /// surface is created, as the supported modes depend on the device.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let invalid = |msg: &str| Err(invalid_data(format!("ERR_PREF_INVALID -> {}", msg)));
+
+        if !(self.img_scale > 0.0 && self.img_scale.is_finite()) {
+            return invalid("img_scale must be positive");
+        }
+        if self.mov_speed <= 0.0 || !self.mov_speed.is_finite() {
+            return invalid("mov_speed must be positive");
+        }
+
+        for (name, extent) in [
+            ("window_size", self.start_window_size),
+            ("render_res", self.render_res),
+        ] {
+            if extent.width == 0 || extent.height == 0 {
+                return invalid(&format!("{} must not be empty", name));
+            }
+        }
+
+        if self
+            .lod_detail
+            .is_some_and(|detail| detail <= 0.0 || !detail.is_finite())
+        {
+            return invalid("lod_detail must be positive");
+        }
+        if self.lod_depth == Some(0) {
+            return invalid("lod_depth must not be 0");
+        }
+        if self
+            .world_radius
+            .is_some_and(|radius| radius.iter().any(|&value| value < 0))
+        {
+            return invalid("world_radius must not be negative");
+        }
+
+        if self.replay_step <= 0.0 || !self.replay_step.is_finite() {
+            return invalid("replay_step must be positive");
+        }
+
+        if self.bench_frames == 0 {
+            return invalid("bench_frames must not be 0");
+        }
+
+        for path in [&self.scene_file, &self.binding_file, &self.replay_file].into_iter().flatten() {
+            if !path.exists() {
+                return invalid(&format!("{} does not exist", path.display()));
+            }
+        }
+
+        if self.cpu_render_file.is_some() && self.offscreen_file.is_some() {
+            log::info!(
+                "Both cpu_render_file and offscreen_file are set, only the cpu render runs ..."
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use ash::vk;
+
+    use super::Pref;
+    use crate::tree::gen::SceneKind;
+
+    fn arg_list(arg_list: &[&str]) -> Vec<String> {
+        arg_list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_pref_file() {
+        let mut pref = Pref::default();
+
+        pref.parse(
+            "# Laptop without immediate mode\n\
+             present_mode = \"MAILBOX\"\n\
+             render_res = [1280, 720]   # half of 1440p\n\
+             \n\
+             scene = \"TERRAIN\"\n\
+             seed = 7\n\
+             world_radius = [2, 1, 2]\n\
+             offscreen_file = \"out.png\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(pref.pref_present_mode, vk::PresentModeKHR::MAILBOX);
+        assert_eq!(
+            pref.render_res,
+            vk::Extent2D {
+                width: 1280,
+                height: 720
+            }
+        );
+        assert_eq!(pref.scene, SceneKind::TERRAIN);
+        assert_eq!(pref.seed, 7);
+        assert_eq!(pref.world_radius, Some([2, 1, 2]));
+        assert_eq!(pref.offscreen_file, Some(PathBuf::from("out.png")));
+
+        pref.parse("world_radius = \"none\"\nlod_detail = 1\nlod_depth = 5\nuse_dag = true")
+            .unwrap();
+        assert_eq!(pref.world_radius, None);
+        assert_eq!(pref.lod_detail, Some(1.0));
+        assert_eq!(pref.lod_depth, Some(5));
+        assert!(pref.use_dag);
+
+        assert!(pref.parse("render_res = [1280]").is_err());
+        assert!(pref.parse("seed = -1").is_err());
+        assert!(pref.parse("speed = 1.0").is_err());
+        assert!(pref.parse("use_dag").is_err());
+        assert!(pref.parse("name = unquoted").is_err());
+        assert!(pref.parse("[window]\nsize = [800, 600]").is_err());
+    }
+
+    #[test]
+    fn parse_example_file() {
+        let mut pref = Pref::default();
+        pref.parse(include_str!("../pref.example.toml")).unwrap();
+
+        assert!(pref.validate().is_ok());
+    }
+
+    #[test]
+    fn args_override_defaults() {
+        let pref = Pref::from_args(arg_list(&[
+            "--mov-speed",
+            "0.2",
+            "--use_dag",
+            "--render_res=640,480",
+            "--scene",
+            "CAVES",
+        ]))
+        .unwrap();
+
+        assert_eq!(pref.mov_speed, 0.2);
+        assert!(pref.use_dag);
+        assert_eq!(
+            pref.render_res,
+            vk::Extent2D {
+                width: 640,
+                height: 480
+            }
+        );
+        assert_eq!(pref.scene, SceneKind::CAVES);
+
+        // Negative value is taken as value and not as the next flag
+        for (arg_list, msg) in [
+            (
+                arg_list(&["--mov_speed", "-0.2"]),
+                "mov_speed must be positive",
+            ),
+            (
+                arg_list(&["--world-radius", "-1,0,1"]),
+                "world_radius must not be negative",
+            ),
+        ] {
+            let err = Pref::from_args(arg_list).err().unwrap();
+            assert!(err.to_string().contains(msg), "{}", err);
+        }
+
+        assert!(Pref::from_args(arg_list(&["mov_speed", "0.2"])).is_err());
+        assert!(Pref::from_args(arg_list(&["--pref", "missing.toml"])).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_bad_values() {
+        assert!(Pref::default().validate().is_ok());
+
+        for (key, value) in [
+            ("img_scale", "0"),
+            ("mov_speed", "-1"),
+            ("window_size", "[0, 600]"),
+            ("lod_detail", "NaN"),
+            ("lod_depth", "0"),
+            ("world_radius", "[1, -1, 1]"),
+            ("scene_file", "missing.oct"),
+            ("replay_step", "0"),
+            ("bench_frames", "0"),
+        ] {
+            let mut pref = Pref::default();
+            pref.set(key, value).unwrap();
+
+            assert!(pref.validate().is_err(), "{} = {}", key, value);
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    error::Error,
+    fmt::Write,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    time::Duration,
+};
+
+use nalgebra_glm::{Vec2, Vec4};
+
+use crate::uniform::Uniform;
+
+/// Cam state of one frame, time is in seconds since the start. Pos and
+/// cam pos are in world space, so a path stays valid over chunk changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CamFrame {
+    pub time: f32,
+
+    pub pos: Vec4,
+    pub cam_pos: Vec4,
+    pub look_dir: Vec4,
+    pub mouse_rot: Vec2,
+}
+
+impl CamFrame {
+    /// Cam of uniform, which is relative to the chunk with its origin at origin.
+    pub fn capture(uniform: &Uniform, origin: Vec4, time: Duration) -> Self {
+        Self {
+            time: time.as_secs_f32(),
+
+            pos: uniform.pos + origin,
+            cam_pos: uniform.cam_pos + origin,
+            look_dir: uniform.look_dir,
+            mouse_rot: uniform.mouse_rot,
+        }
+    }
+
+    /// Move the cam of uniform relative to the chunk with its origin at origin,
+    /// World::follow moves it into the right chunk afterwards.
+    pub fn apply(&self, uniform: &mut Uniform, origin: Vec4) {
+        uniform.pos = self.pos - origin;
+        uniform.cam_pos = self.cam_pos - origin;
+        uniform.look_dir = self.look_dir;
+        uniform.mouse_rot = self.mouse_rot;
+    }
+
+    /// Blend towards other, look dir is normalized again.
+    pub fn lerp(&self, other: &CamFrame, factor: f32) -> CamFrame {
+        let look_dir = self.look_dir + (other.look_dir - self.look_dir) * factor;
+
+        CamFrame {
+            time: self.time + (other.time - self.time) * factor,
+
+            pos: self.pos + (other.pos - self.pos) * factor,
+            cam_pos: self.cam_pos + (other.cam_pos - self.cam_pos) * factor,
+            look_dir: if look_dir == Vec4::zeros() {
+                self.look_dir
+            } else {
+                look_dir.normalize()
+            },
+            mouse_rot: self.mouse_rot + (other.mouse_rot - self.mouse_rot) * factor,
+        }
+    }
+}
+
+// Key frames of CamPath::orbit, the cam is interpolated in between
+const ORBIT_FRAME_COUNT: usize = 64;
+
+/// Recorded cam frames, sorted by time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CamPath {
+    pub frame_list: Vec<CamFrame>,
+}
+
+fn invalid_data(msg: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+impl CamPath {
+    pub fn push(&mut self, frame: CamFrame) {
+        self.frame_list.push(frame);
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frame_list.last().map_or(0.0, |frame| frame.time)
+    }
+
+    /// Cam state at time, between two frames it is interpolated.
+    /// None after the last frame.
+    pub fn sample(&self, time: f32) -> Option<CamFrame> {
+        let next_idx = self.frame_list.partition_point(|frame| frame.time <= time);
+        if next_idx == self.frame_list.len() {
+            return self
+                .frame_list
+                .last()
+                .filter(|frame| frame.time == time)
+                .copied();
+        }
+
+        let next = &self.frame_list[next_idx];
+        let Some(prev) = next_idx.checked_sub(1).map(|idx| &self.frame_list[idx]) else {
+            return Some(*next);
+        };
+
+        let factor = (time - prev.time) / (next.time - prev.time);
+        Some(prev.lerp(next, factor))
+    }
+
+    /// One frame per line, time | pos | cam pos | look dir | mouse rot.
+    /// Floats are written with all digits, so a replay matches exactly.
+    pub fn to_text(&self) -> String {
+        let mut text = "# time pos.xyz cam_pos.xyz look_dir.xyz mouse_rot.xy\n".to_string();
+
+        for frame in &self.frame_list {
+            let value_list = [
+                frame.time,
+                frame.pos.x,
+                frame.pos.y,
+                frame.pos.z,
+                frame.cam_pos.x,
+                frame.cam_pos.y,
+                frame.cam_pos.z,
+                frame.look_dir.x,
+                frame.look_dir.y,
+                frame.look_dir.z,
+                frame.mouse_rot.x,
+                frame.mouse_rot.y,
+            ];
+
+            let line = value_list.map(|value| value.to_string()).join(" ");
+            writeln!(text, "{}", line).expect("ERR_WRITE_CAM_PATH");
+        }
+
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut path = CamPath::default();
+
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let value_list = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|_| invalid_data(format!("ERR_CAM_PATH_VALUE -> {}", line_idx + 1)))?;
+
+            let [time, pos_x, pos_y, pos_z, cam_x, cam_y, cam_z, dir_x, dir_y, dir_z, rot_x, rot_y] =
+                value_list[..]
+            else {
+                return Err(invalid_data(format!(
+                    "ERR_CAM_PATH_LINE -> {}",
+                    line_idx + 1
+                )));
+            };
+
+            if time < path.duration() {
+                return Err(invalid_data(format!(
+                    "ERR_CAM_PATH_TIME -> {}",
+                    line_idx + 1
+                )));
+            }
+
+            path.push(CamFrame {
+                time,
+
+                pos: Vec4::new(pos_x, pos_y, pos_z, 0.0),
+                cam_pos: Vec4::new(cam_x, cam_y, cam_z, 0.0),
+                look_dir: Vec4::new(dir_x, dir_y, dir_z, 0.0),
+                mouse_rot: Vec2::new(rot_x, rot_y),
+            });
+        }
+
+        Ok(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        log::info!(
+            "Saving cam path with {} frames to {} ...",
+            self.frame_list.len(),
+            path.display()
+        );
+
+        Ok(fs::write(path, self.to_text())?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        log::info!("Loading cam path from {} ...", path.display());
+
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// One circle around the root in duration seconds, the cam looks
+    /// at the same point as in Uniform::overview.
+    pub fn orbit(root_span: f32, duration: f32) -> Self {
+        let mut path = CamPath::default();
+        let mut uniform = Uniform::new(root_span);
+        let center = Vec4::new(0.5, 0.3, 0.5, 0.0) * root_span;
+
+        for idx in 0..=ORBIT_FRAME_COUNT {
+            let angle = idx as f32 / ORBIT_FRAME_COUNT as f32 * std::f32::consts::TAU;
+
+            uniform.cam_pos =
+                Vec4::new(0.5 + 0.8 * angle.cos(), 1.1, 0.5 + 0.8 * angle.sin(), 0.0) * root_span;
+            uniform.pos = uniform.cam_pos;
+            uniform.look_at(center);
+
+            let time = idx as f32 / ORBIT_FRAME_COUNT as f32 * duration;
+            path.push(CamFrame::capture(
+                &uniform,
+                Vec4::zeros(),
+                Duration::from_secs_f32(time),
+            ));
+        }
+
+        path
+    }
+}
+
+/// Plays a cam path with a fixed time step per frame instead of the
+/// real frame time, so every run draws the same frames.
+pub struct Replay {
+    pub path: CamPath,
+    pub step: f32,
+    pub frame_idx: u32,
+}
+
+impl Replay {
+    pub fn new(path: CamPath, step: f32) -> Self {
+        Self {
+            path,
+            step,
+            frame_idx: 0,
+        }
+    }
+
+    /// Frames until next frame returns none.
+    pub fn frame_count(&self) -> usize {
+        (0..)
+            .take_while(|&idx| self.path.sample(idx as f32 * self.step).is_some())
+            .count()
+    }
+
+    /// Move the cam to the next frame, relative to the chunk at origin.
+    /// Return the time of the frame, None once the path is over.
+    pub fn next_frame(&mut self, uniform: &mut Uniform, origin: Vec4) -> Option<Duration> {
+        let time = self.frame_idx as f32 * self.step;
+        let frame = self.path.sample(time)?;
+
+        frame.apply(uniform, origin);
+        self.frame_idx += 1;
+
+        Some(Duration::from_secs_f32(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nalgebra_glm::{Vec2, Vec4};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{CamFrame, CamPath, Replay};
+    use crate::uniform::Uniform;
+
+    fn random_path(frame_count: usize) -> CamPath {
+        let mut rng = StdRng::seed_from_u64(23);
+        let mut path = CamPath::default();
+        let mut uniform = Uniform::new(256.0);
+
+        for idx in 0..frame_count {
+            uniform.cam_pos = Vec4::new(rng.gen(), rng.gen(), rng.gen(), 0.0) * 256.0;
+            uniform.pos = uniform.cam_pos;
+            uniform.move_mouse(Vec2::new(
+                rng.gen_range(-50.0..50.0),
+                rng.gen_range(-50.0..50.0),
+            ));
+
+            path.push(CamFrame::capture(
+                &uniform,
+                Vec4::zeros(),
+                Duration::from_millis(idx as u64 * 17),
+            ));
+        }
+
+        path
+    }
+
+    #[test]
+    fn cam_path_text_round_trip() {
+        let path = random_path(50);
+
+        assert_eq!(CamPath::parse(&path.to_text()).unwrap(), path);
+
+        assert!(CamPath::parse("0.0 1.0 2.0").is_err());
+        assert!(CamPath::parse(&"x ".repeat(12)).is_err());
+        assert!(CamPath::parse(&format!("{}\n{}", "1.0 ".repeat(12), "0.5 ".repeat(12))).is_err());
+    }
+
+    #[test]
+    fn sample_between_frames() {
+        let mut path = CamPath::default();
+        let mut frame = CamFrame {
+            time: 1.0,
+
+            pos: Vec4::zeros(),
+            cam_pos: Vec4::zeros(),
+            look_dir: Vec4::new(1.0, 0.0, 0.0, 0.0),
+            mouse_rot: Vec2::zeros(),
+        };
+        path.push(frame);
+
+        frame.time = 2.0;
+        frame.cam_pos = Vec4::new(10.0, 0.0, 0.0, 0.0);
+        frame.look_dir = Vec4::new(0.0, 0.0, 1.0, 0.0);
+        path.push(frame);
+
+        // Before the first frame the cam waits at the start
+        assert_eq!(path.sample(0.0).unwrap().cam_pos, Vec4::zeros());
+
+        let half = path.sample(1.5).unwrap();
+        assert_eq!(half.cam_pos, Vec4::new(5.0, 0.0, 0.0, 0.0));
+        assert!((half.look_dir.norm() - 1.0).abs() < 1e-6);
+
+        assert_eq!(path.sample(2.0).unwrap().cam_pos, frame.cam_pos);
+        assert!(path.sample(2.1).is_none());
+    }
+
+    #[test]
+    fn orbit_closes_circle() {
+        let path = CamPath::orbit(256.0, 10.0);
+        let start = path.frame_list[0];
+
+        assert_eq!(path.duration(), 10.0);
+        assert!((path.sample(10.0).unwrap().cam_pos - start.cam_pos).norm() < 1e-3);
+
+        let half = path.sample(5.0).unwrap();
+        assert!((half.cam_pos.x - (0.5 - 0.8) * 256.0).abs() < 1e-3);
+        assert!((half.look_dir.norm() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn orbit_covers_bench_frames() {
+        for frame_count in [1, 7, 1010, 100_010] {
+            let path = CamPath::orbit(256.0, frame_count as f32 / 60.0);
+
+            assert!(Replay::new(path, 1.0 / 60.0).frame_count() >= frame_count);
+        }
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        let path = random_path(30);
+        let duration = path.duration();
+
+        let run = || {
+            let mut replay = Replay::new(path.clone(), 1.0 / 60.0);
+            let mut uniform = Uniform::new(256.0);
+            let mut frame_list = vec![];
+
+            while let Some(time) = replay.next_frame(&mut uniform, Vec4::zeros()) {
+                frame_list.push(CamFrame::capture(&uniform, Vec4::zeros(), time));
+            }
+
+            frame_list
+        };
+
+        let frame_list = run();
+        assert_eq!(frame_list, run());
+        assert_eq!(frame_list.len(), (duration * 60.0) as usize + 1);
+        assert_eq!(
+            Replay::new(path.clone(), 1.0 / 60.0).frame_count(),
+            frame_list.len()
+        );
+        assert_eq!(frame_list[0].cam_pos, path.frame_list[0].cam_pos);
+    }
+
+    #[test]
+    fn frames_are_in_world_space() {
+        let mut uniform = Uniform::new(256.0);
+        uniform.cam_pos = Vec4::new(10.0, 20.0, 30.0, 0.0);
+        uniform.pos = uniform.cam_pos;
+
+        // Cam in chunk (1, 0, -1) is stored at its world pos
+        let origin = Vec4::new(256.0, 0.0, -256.0, 0.0);
+        let frame = CamFrame::capture(&uniform, origin, Duration::ZERO);
+        assert_eq!(frame.cam_pos, Vec4::new(266.0, 20.0, -226.0, 0.0));
+
+        // Replay from chunk (0, 0, 0) puts the cam outside of it, for World::follow
+        let mut replayed = Uniform::new(256.0);
+        frame.apply(&mut replayed, Vec4::zeros());
+        assert_eq!(replayed.cam_pos, frame.cam_pos);
+
+        frame.apply(&mut replayed, origin);
+        assert_eq!(replayed.cam_pos, uniform.cam_pos);
+        assert_eq!(replayed.pos, uniform.pos);
+    }
+}
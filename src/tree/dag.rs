@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use super::{material::Material, octant::Octant, octree::Octree};
+
+// Child nodes and materials of a block, materials as raw bits
+type BlockKey = ([u32; 8], [[u32; 12]; 8]);
+
+fn material_key(material: &Material) -> [u32; 12] {
+    [
+        material.albedo.x.to_bits(),
+        material.albedo.y.to_bits(),
+        material.albedo.z.to_bits(),
+        material.albedo.w.to_bits(),
+        material.emission.x.to_bits(),
+        material.emission.y.to_bits(),
+        material.emission.z.to_bits(),
+        material.emission.w.to_bits(),
+        material.roughness.to_bits(),
+        material.material_id,
+        material.occupancy.to_bits(),
+        material.padding,
+    ]
+}
+
+impl Octree {
+    /// Convert into a directed acyclic graph, identical blocks of eight
+    /// children are only stored once and shared by all parents.
+    /// The node layout does not change, a parent still points to the first
+    /// child of its block, so every traversal works on the result as well.
+    /// Materials are part of a block, so only subtrees with the same
+    /// materials are merged. The result is read only, insert and remove
+    /// would write through a shared block.
+    pub fn to_dag(&self) -> Octree {
+        let mut dag = Octree {
+            root_span: self.root_span,
+
+            ..Default::default()
+        };
+        let mut block_map: HashMap<BlockKey, u32> = HashMap::new();
+
+        dag.octant_data[0] = self.dedup_node(&mut dag, &mut block_map, 0);
+        dag.material_data[0] = self.material_data[0];
+
+        log::info!(
+            "Dag has {} of {} nodes, {} shared blocks ...",
+            dag.octant_data.len(),
+            self.octant_data.len(),
+            block_map.len()
+        );
+
+        dag
+    }
+
+    /// Return the node for idx with the first child idx pointing into the
+    /// dag. Children are added bottom up, so a block is only hashed once
+    /// all of its subtrees are merged.
+    fn dedup_node(
+        &self,
+        dag: &mut Octree,
+        block_map: &mut HashMap<BlockKey, u32>,
+        idx: usize,
+    ) -> u32 {
+        let node = self.octant_data[idx];
+        if !node.is_subdiv() {
+            return node;
+        }
+
+        let first_child_idx = node.get_first_child_idx() as usize;
+        let mut node_list = [0u32; 8];
+        let mut material_list = [Material::default(); 8];
+
+        for mask in 0..8 {
+            node_list[mask] = self.dedup_node(dag, block_map, first_child_idx + mask);
+
+            // Empty slots may hold stale materials, they must not prevent sharing
+            if node_list[mask] != 0 {
+                material_list[mask] = self.material_data[first_child_idx + mask];
+            }
+        }
+
+        let key = (
+            node_list,
+            material_list.map(|material| material_key(&material)),
+        );
+        let dag_first_child_idx = *block_map.entry(key).or_insert_with(|| {
+            let dag_first_child_idx = dag.octant_data.len() as u32;
+            dag.octant_data.extend_from_slice(&node_list);
+            dag.material_data.extend_from_slice(&material_list);
+
+            dag_first_child_idx
+        });
+
+        node.set_first_child_idx(dag_first_child_idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{
+        pipe::layout::CubeLayout,
+        tree::{material::Material, octant::Octant, octree::Octree, trace::Ray},
+    };
+
+    /// Repeated pillars with two colors.
+    fn pillars() -> Octree {
+        let mut octree = Octree::default();
+        let leaf_span = octree.leaf_span();
+
+        for x in 0..128u32 {
+            for z in 0..128u32 {
+                if x % 8 > 1 || z % 8 > 1 {
+                    continue;
+                }
+
+                let color = if (x / 32 + z / 32) % 2 == 0 {
+                    Vec4::new(1.0, 0.0, 0.0, 1.0)
+                } else {
+                    Vec4::new(0.0, 0.0, 1.0, 1.0)
+                };
+
+                for y in 0..16 {
+                    let pos = Vec4::new(x as f32, y as f32, z as f32, 0.0) * leaf_span;
+                    octree.insert_node(pos, Material::from_color(color));
+                }
+            }
+        }
+
+        octree
+    }
+
+    #[test]
+    fn dag_is_smaller() {
+        let octree = pillars();
+        let dag = octree.to_dag();
+
+        assert!(dag.octant_data.len() * 10 < octree.octant_data.len());
+        assert_eq!(dag.octant_data.len(), dag.material_data.len());
+        assert_eq!(dag.to_dag().octant_data, dag.octant_data);
+    }
+
+    #[test]
+    fn dag_traces_like_octree() {
+        let octree = pillars();
+        let dag = octree.to_dag();
+        let mut rng = StdRng::seed_from_u64(16);
+
+        for _ in 0..500 {
+            let origin = Vec4::new(
+                rng.gen_range(-50.0..300.0),
+                rng.gen_range(40.0..300.0),
+                rng.gen_range(-50.0..300.0),
+                0.0,
+            );
+            let target = Vec4::new(
+                rng.gen_range(0.0..256.0),
+                rng.gen_range(0.0..32.0),
+                rng.gen_range(0.0..256.0),
+                0.0,
+            );
+            let ray = Ray {
+                origin,
+                dir: (target - origin).normalize(),
+            };
+
+            let hit = octree.cast_ray(&ray, f32::MAX);
+            let dag_hit = dag.cast_ray(&ray, f32::MAX);
+
+            assert_eq!(hit.map(|hit| hit.pos), dag_hit.map(|hit| hit.pos));
+            if let (Some(hit), Some(dag_hit)) = (hit, dag_hit) {
+                assert_eq!(
+                    octree.material_data[hit.idx as usize],
+                    dag.material_data[dag_hit.idx as usize]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dag_uploads_same_cubes() {
+        let octree = pillars();
+        let dag = octree.to_dag();
+
+        let layout = CubeLayout::new(&octree, None);
+        let dag_layout = CubeLayout::new(&dag, None);
+
+        assert_eq!(layout.index_data, dag_layout.index_data);
+        assert_eq!(layout.vertex_data.len(), dag_layout.vertex_data.len());
+        for (vertex, dag_vertex) in layout.vertex_data.iter().zip(&dag_layout.vertex_data) {
+            assert_eq!(vertex.pos, dag_vertex.pos);
+            assert_eq!(vertex.pos_on_edge, dag_vertex.pos_on_edge);
+        }
+
+        // Only the first child idx of the cube nodes points somewhere else
+        for (loc, dag_loc) in layout.loc_data.iter().zip(&dag_layout.loc_data) {
+            assert_eq!((loc.depth, loc.span), (dag_loc.depth, dag_loc.span));
+            assert_eq!(
+                loc.parent_list.map(|node| node.get_child_bitmask()),
+                dag_loc.parent_list.map(|node| node.get_child_bitmask())
+            );
+        }
+    }
+}
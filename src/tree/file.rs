@@ -0,0 +1,503 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::Path,
+};
+
+use nalgebra_glm::Vec4;
+
+use super::{
+    material::Material,
+    octant::Octant,
+    octree::{Octree, MAX_DEPTH},
+    vox, voxelize,
+    world::World,
+};
+
+/// Native octree file, all values are little endian.
+///
+/// Magic | "PTOC"
+/// Version | u32
+/// Depth | u32, has to match MAX_DEPTH
+/// Octant format | u32, always 1 = 22 bit first child idx
+/// Root span | f32
+/// Node count | u32
+/// Node list | u32 * node count
+/// Block count | u32
+/// Block list | tag [u8; 4], byte len u32, data
+/// Checksum | u32, crc32 of everything before
+///
+/// Version 1 has no occupancy in the material block, it is read as 1.0.
+/// Older files can have octant format 0 (16 bit first child idx), their
+/// upper bits are never set, so they are read the same way.
+pub const FILE_MAGIC: &[u8; 4] = b"PTOC";
+pub const FILE_VERSION: u32 = 2;
+
+// Block with one material per node
+pub const MATERIAL_TAG: &[u8; 4] = b"MATL";
+const MATERIAL_SIZE: usize = 44;
+const MATERIAL_SIZE_V1: usize = 40;
+
+const OCTANT_FORMAT: u32 = 1;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
+pub enum OctreeFileError {
+    IO(io::Error),
+    MAGIC,
+    VERSION(u32),
+    DEPTH(u32),
+    TRUNCATED,
+    CHECKSUM { expected: u32, found: u32 },
+    CORRUPT(&'static str),
+}
+
+impl fmt::Display for OctreeFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OctreeFileError::IO(error) => write!(f, "ERR_OCTREE_FILE_IO -> {}", error),
+            OctreeFileError::MAGIC => write!(f, "ERR_OCTREE_FILE_MAGIC"),
+            OctreeFileError::VERSION(version) => {
+                write!(f, "ERR_OCTREE_FILE_VERSION -> {}", version)
+            }
+            OctreeFileError::DEPTH(depth) => write!(f, "ERR_OCTREE_FILE_DEPTH -> {}", depth),
+            OctreeFileError::TRUNCATED => write!(f, "ERR_OCTREE_FILE_TRUNCATED"),
+            OctreeFileError::CHECKSUM { expected, found } => write!(
+                f,
+                "ERR_OCTREE_FILE_CHECKSUM -> expected {:#010x} found {:#010x}",
+                expected, found
+            ),
+            OctreeFileError::CORRUPT(msg) => write!(f, "ERR_OCTREE_FILE_CORRUPT -> {}", msg),
+        }
+    }
+}
+
+impl Error for OctreeFileError {}
+
+impl From<io::Error> for OctreeFileError {
+    fn from(error: io::Error) -> Self {
+        OctreeFileError::IO(error)
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            // Polynomial of crc32 (IEEE) in reversed bit order
+            crc = (crc >> 1) ^ (0xEDB88320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+
+    !crc
+}
+
+struct FileReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> FileReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], OctreeFileError> {
+        let end = self.pos.checked_add(len).ok_or(OctreeFileError::TRUNCATED)?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(OctreeFileError::TRUNCATED)?;
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, OctreeFileError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, OctreeFileError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+}
+
+impl Octree {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![];
+
+        data.extend_from_slice(FILE_MAGIC);
+        data.extend_from_slice(&FILE_VERSION.to_le_bytes());
+        data.extend_from_slice(&(MAX_DEPTH as u32).to_le_bytes());
+        data.extend_from_slice(&OCTANT_FORMAT.to_le_bytes());
+        data.extend_from_slice(&self.root_span.to_le_bytes());
+
+        data.extend_from_slice(&(self.octant_data.len() as u32).to_le_bytes());
+        self.octant_data
+            .iter()
+            .for_each(|node| data.extend_from_slice(&node.to_le_bytes()));
+
+        // Material block
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(MATERIAL_TAG);
+        data.extend_from_slice(&((self.material_data.len() * MATERIAL_SIZE) as u32).to_le_bytes());
+        self.material_data.iter().for_each(|material| {
+            material
+                .albedo
+                .iter()
+                .chain(material.emission.iter())
+                .chain([material.roughness].iter())
+                .for_each(|value| data.extend_from_slice(&value.to_le_bytes()));
+            data.extend_from_slice(&material.material_id.to_le_bytes());
+            data.extend_from_slice(&material.occupancy.to_le_bytes());
+        });
+
+        data.extend_from_slice(&crc32(&data).to_le_bytes());
+
+        data
+    }
+
+    /// Read octree from bytes, the checksum is tested before anything else.
+    /// Unknown blocks are skipped, so newer attributes do not break old builds.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, OctreeFileError> {
+        if data.len() < FILE_MAGIC.len() + 4 {
+            return Err(OctreeFileError::TRUNCATED);
+        }
+        if &data[0..4] != FILE_MAGIC {
+            return Err(OctreeFileError::MAGIC);
+        }
+
+        let (content, checksum) = data.split_at(data.len() - 4);
+        let expected = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+        let found = crc32(content);
+        if expected != found {
+            return Err(OctreeFileError::CHECKSUM { expected, found });
+        }
+
+        let mut reader = FileReader {
+            data: content,
+            pos: 4,
+        };
+
+        let version = reader.u32()?;
+        if !(1..=FILE_VERSION).contains(&version) {
+            return Err(OctreeFileError::VERSION(version));
+        }
+
+        let depth = reader.u32()?;
+        if depth != MAX_DEPTH as u32 {
+            return Err(OctreeFileError::DEPTH(depth));
+        }
+
+        if reader.u32()? > OCTANT_FORMAT {
+            return Err(OctreeFileError::CORRUPT("octant format"));
+        }
+
+        let root_span = reader.f32()?;
+        if root_span.is_nan() || root_span <= 0.0 {
+            return Err(OctreeFileError::CORRUPT("root span"));
+        }
+
+        let node_count = reader.u32()? as usize;
+        if node_count == 0 {
+            return Err(OctreeFileError::CORRUPT("empty node list"));
+        }
+
+        let octant_data: Vec<u32> = reader
+            .bytes(node_count.checked_mul(4).ok_or(OctreeFileError::TRUNCATED)?)?
+            .chunks_exact(4)
+            .map(|node| u32::from_le_bytes([node[0], node[1], node[2], node[3]]))
+            .collect();
+
+        let child_out_of_range = octant_data.iter().any(|node| {
+            node.is_subdiv() && node.get_first_child_idx() as usize + 8 > node_count
+        });
+        if child_out_of_range {
+            return Err(OctreeFileError::CORRUPT("first child idx out of range"));
+        }
+
+        let mut material_data = vec![Material::default(); node_count];
+
+        let block_count = reader.u32()?;
+        for _ in 0..block_count {
+            let tag = reader.bytes(4)?;
+            let len = reader.u32()? as usize;
+            let mut block = FileReader {
+                data: reader.bytes(len)?,
+                pos: 0,
+            };
+
+            if tag == MATERIAL_TAG {
+                let material_size = match version {
+                    1 => MATERIAL_SIZE_V1,
+                    _ => MATERIAL_SIZE,
+                };
+                if len != node_count * material_size {
+                    return Err(OctreeFileError::CORRUPT("material block size"));
+                }
+
+                for material in material_data.iter_mut() {
+                    material.albedo = Vec4::new(block.f32()?, block.f32()?, block.f32()?, block.f32()?);
+                    material.emission = Vec4::new(block.f32()?, block.f32()?, block.f32()?, block.f32()?);
+                    material.roughness = block.f32()?;
+                    material.material_id = block.u32()?;
+                    if version > 1 {
+                        material.occupancy = block.f32()?;
+                    }
+                }
+            } else {
+                log::info!(
+                    "Skipping unknown block {} ...",
+                    String::from_utf8_lossy(tag)
+                );
+            }
+        }
+
+        if reader.pos != content.len() {
+            return Err(OctreeFileError::CORRUPT("trailing data"));
+        }
+
+        Ok(Self {
+            octant_data,
+            material_data,
+            root_span,
+
+            ..Default::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), OctreeFileError> {
+        log::info!("Saving octree to {} ...", path.display());
+        Ok(fs::write(path, self.to_bytes())?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, OctreeFileError> {
+        log::info!("Loading octree from {} ...", path.display());
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+/// Load scene file, the loader is selected by file extension.
+pub fn load_scene(path: &Path) -> Result<Octree, Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    match extension.as_str() {
+        "vox" => vox::load_vox(path),
+        "obj" | "stl" => voxelize::load_mesh(path, MAX_DEPTH, true),
+        _ => Ok(Octree::load(path)?),
+    }
+}
+
+/// Load scene file into the chunks of world. Vox scenes bigger than one
+/// root are split over several chunks, other scenes fill chunk (0, 0, 0).
+pub fn load_scene_world(path: &Path, world: &mut World) -> Result<(), Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    match extension.as_str() {
+        "vox" => vox::load_vox_world(path, world),
+        _ => {
+            let octree = load_scene(path)?;
+            world.chunk_map.insert(
+                [0; 3],
+                Octree {
+                    root_span: world.root_span,
+
+                    ..octree
+                },
+            );
+
+            Ok(())
+        }
+    }
+}
+
+/// Save scene file as octree file or vox, meshes can not be written.
+pub fn save_scene(octree: &Octree, path: &Path) -> Result<(), Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    match extension.as_str() {
+        "vox" => vox::save_vox(octree, path),
+        "obj" | "stl" => Err(Box::new(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("ERR_SAVE_SCENE_FORMAT -> {}", extension),
+        ))),
+        _ => Ok(octree.save(path)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{crc32, OctreeFileError, MATERIAL_SIZE, MATERIAL_SIZE_V1};
+    use crate::tree::{material::Material, octant::Octant, octree::Octree};
+
+    // Magic, version, depth, format, root span and node count
+    const NODE_LIST_START: usize = 24;
+
+    fn random_octree() -> Octree {
+        let mut rng = StdRng::seed_from_u64(8);
+        let mut octree = Octree::default();
+
+        for _ in 0..200 {
+            let pos = Vec4::new(
+                rng.gen_range(0.0..256.0),
+                rng.gen_range(0.0..256.0),
+                rng.gen_range(0.0..256.0),
+                0.0,
+            );
+            let color = Vec4::new(rng.gen(), rng.gen(), rng.gen(), 1.0);
+            octree.insert_node(pos, Material::from_color(color));
+        }
+
+        octree
+    }
+
+    /// Replace the checksum, so the reader gets past it to the broken part.
+    fn with_checksum(mut data: Vec<u8>) -> Vec<u8> {
+        let content_len = data.len() - 4;
+        let checksum = crc32(&data[..content_len]);
+        data[content_len..].copy_from_slice(&checksum.to_le_bytes());
+
+        data
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let octree = random_octree();
+        let read = Octree::from_bytes(&octree.to_bytes()).unwrap();
+
+        assert_eq!(read.octant_data, octree.octant_data);
+        assert_eq!(read.material_data, octree.material_data);
+        assert_eq!(read.root_span, octree.root_span);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut data = random_octree().to_bytes();
+        data[0] = b'X';
+
+        assert!(matches!(
+            Octree::from_bytes(&with_checksum(data)),
+            Err(OctreeFileError::MAGIC)
+        ));
+    }
+
+    #[test]
+    fn bad_version() {
+        let mut data = random_octree().to_bytes();
+        data[4..8].copy_from_slice(&99u32.to_le_bytes());
+
+        assert!(matches!(
+            Octree::from_bytes(&with_checksum(data)),
+            Err(OctreeFileError::VERSION(99))
+        ));
+    }
+
+    #[test]
+    fn octant_format() {
+        let octree = random_octree();
+
+        // Format 0 of older files is read like the current one
+        let mut data = octree.to_bytes();
+        data[12..16].copy_from_slice(&0u32.to_le_bytes());
+        let read = Octree::from_bytes(&with_checksum(data)).unwrap();
+        assert_eq!(read.octant_data, octree.octant_data);
+
+        let mut data = octree.to_bytes();
+        data[12..16].copy_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(
+            Octree::from_bytes(&with_checksum(data)),
+            Err(OctreeFileError::CORRUPT(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_body() {
+        let data = random_octree().to_bytes();
+
+        // Without a fitting checksum the cut is found before parsing
+        let mut cut = data[..data.len() / 2].to_vec();
+        assert!(matches!(
+            Octree::from_bytes(&cut),
+            Err(OctreeFileError::CHECKSUM { .. })
+        ));
+
+        cut.extend_from_slice(&[0; 4]);
+        assert!(matches!(
+            Octree::from_bytes(&with_checksum(cut)),
+            Err(OctreeFileError::TRUNCATED)
+        ));
+        assert!(matches!(
+            Octree::from_bytes(&data[..6]),
+            Err(OctreeFileError::TRUNCATED)
+        ));
+    }
+
+    #[test]
+    fn child_idx_out_of_range() {
+        let octree = random_octree();
+        let mut data = octree.to_bytes();
+
+        let node = octree.octant_data[0].set_first_child_idx(octree.octant_data.len() as u32 - 4);
+        data[NODE_LIST_START..NODE_LIST_START + 4].copy_from_slice(&node.to_le_bytes());
+
+        assert!(matches!(
+            Octree::from_bytes(&with_checksum(data)),
+            Err(OctreeFileError::CORRUPT(_))
+        ));
+    }
+
+    #[test]
+    fn occupancy_round_trip() {
+        let mut octree = random_octree();
+        octree.material_data[1].occupancy = 0.25;
+        octree.material_data[2].occupancy = 0.0;
+
+        let read = Octree::from_bytes(&octree.to_bytes()).unwrap();
+        assert_eq!(read.material_data, octree.material_data);
+    }
+
+    #[test]
+    fn version_one_without_occupancy() {
+        let mut octree = random_octree();
+        octree.material_data[1].occupancy = 0.25;
+        let data = octree.to_bytes();
+
+        // Rewrite as version 1, the material block has no occupancy
+        let node_count = octree.octant_data.len();
+        let block_start = NODE_LIST_START + node_count * 4 + 12;
+        let mut old_data = data[..block_start].to_vec();
+        old_data[4..8].copy_from_slice(&1u32.to_le_bytes());
+        old_data[block_start - 4..]
+            .copy_from_slice(&((node_count * MATERIAL_SIZE_V1) as u32).to_le_bytes());
+        data[block_start..data.len() - 4]
+            .chunks_exact(MATERIAL_SIZE)
+            .for_each(|material| old_data.extend_from_slice(&material[..MATERIAL_SIZE_V1]));
+        old_data.extend_from_slice(&[0; 4]);
+
+        let read = Octree::from_bytes(&with_checksum(old_data)).unwrap();
+        assert_eq!(read.octant_data, octree.octant_data);
+        assert!(read
+            .material_data
+            .iter()
+            .all(|material| material.occupancy == 1.0));
+    }
+}
@@ -0,0 +1,337 @@
+use nalgebra_glm::{Vec3, Vec4};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use strum_macros::EnumString;
+
+use super::{
+    material::Material,
+    octree::{Octree, GRID_LEN},
+    world::{ChunkGenerator, ChunkPos},
+};
+
+/// Scene that is generated at startup,
+/// selected with scene and seed in pref.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, EnumString)]
+pub enum SceneKind {
+    TEST,
+    TERRAIN,
+    CAVES,
+    PRIMITIVES,
+}
+
+/// Simple shapes, position and size are in voxel.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug)]
+pub enum Primitive {
+    SPHERE { center: Vec3, radius: f32 },
+    CUBOID { min: Vec3, max: Vec3 },
+}
+
+impl Primitive {
+    /// Check if center of voxel is inside of primitive.
+    pub fn contains(&self, voxel: Vec3) -> bool {
+        let pos = voxel.add_scalar(0.5);
+
+        match self {
+            Primitive::SPHERE { center, radius } => (pos - center).norm() <= *radius,
+            Primitive::CUBOID { min, max } => (0..3).all(|idx| min[idx] <= pos[idx] && pos[idx] <= max[idx]),
+        }
+    }
+
+    /// Get min and max voxel of bounding box.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        match self {
+            Primitive::SPHERE { center, radius } => (center.add_scalar(-radius), center.add_scalar(*radius)),
+            Primitive::CUBOID { min, max } => (*min, *max),
+        }
+    }
+}
+
+pub fn generate(kind: SceneKind, seed: u32) -> Octree {
+    log::info!("Generating scene {:?} with seed {} ...", kind, seed);
+
+    match kind {
+        SceneKind::TEST => {
+            let mut octree = Octree::default();
+            octree.test_scene();
+
+            octree
+        }
+        SceneKind::TERRAIN => terrain(seed),
+        SceneKind::CAVES => caves(seed),
+        SceneKind::PRIMITIVES => primitives(seed, 16),
+    }
+}
+
+/// Material of terrain, based on distance to surface.
+pub fn terrain_material(depth: u32) -> Material {
+    match depth {
+        0 => Material::from_color(Vec4::new(0.3, 0.6, 0.2, 1.0)),
+        1..=3 => Material::from_color(Vec4::new(0.45, 0.3, 0.2, 1.0)),
+        _ => Material::from_color(Vec4::new(0.5, 0.5, 0.5, 1.0)),
+    }
+}
+
+/// Surface height for each column of a chunk, indexed with z * GRID_LEN + x.
+/// Height is in voxel above the bottom of chunk row y = 0.
+pub fn height_map(seed: u32, chunk_pos: ChunkPos) -> Vec<u32> {
+    let fbm = Fbm::<Perlin>::new(seed)
+        .set_octaves(5)
+        .set_frequency(1.0 / 64.0);
+
+    let offset_x = chunk_pos[0] as f64 * GRID_LEN as f64;
+    let offset_z = chunk_pos[2] as f64 * GRID_LEN as f64;
+
+    (0..GRID_LEN * GRID_LEN)
+        .map(|idx| {
+            let noise = fbm.get([
+                offset_x + (idx % GRID_LEN) as f64,
+                offset_z + (idx / GRID_LEN) as f64,
+            ]);
+            let height = GRID_LEN as f64 * (0.3 + noise * 0.2);
+
+            height.clamp(1.0, (GRID_LEN - 1) as f64) as u32
+        })
+        .collect()
+}
+
+pub fn terrain(seed: u32) -> Octree {
+    terrain_chunk(seed, [0; 3])
+}
+
+/// Chunk of endless terrain, chunks below row y = 0 are solid
+/// and chunks above are empty.
+pub fn terrain_chunk(seed: u32, chunk_pos: ChunkPos) -> Octree {
+    if chunk_pos[1] > 0 {
+        return Octree::default();
+    }
+
+    let height_map = height_map(seed, chunk_pos);
+    let base_y = chunk_pos[1] * GRID_LEN as i32;
+
+    // Deep below the surface every voxel is stone, no need to sample
+    let min_height = height_map.iter().min().copied().unwrap_or_default() as i32;
+    if base_y + GRID_LEN as i32 - 1 + 3 < min_height {
+        return Octree::filled(Octree::default().root_span, terrain_material(u32::MAX));
+    }
+
+    Octree::from_dense([GRID_LEN; 3], |x, y, z| {
+        let height = height_map[(z * GRID_LEN + x) as usize] as i32;
+        let y = base_y + y as i32;
+
+        (y <= height).then(|| terrain_material((height - y) as u32))
+    })
+}
+
+pub fn caves(seed: u32) -> Octree {
+    caves_chunk(seed, [0; 3])
+}
+
+/// Terrain with caves carved out by 3D fbm noise.
+/// The top layers are not carved, so the surface stays closed.
+pub fn caves_chunk(seed: u32, chunk_pos: ChunkPos) -> Octree {
+    if chunk_pos[1] > 0 {
+        return Octree::default();
+    }
+
+    let height_map = height_map(seed, chunk_pos);
+    let fbm = Fbm::<Perlin>::new(seed.wrapping_add(1))
+        .set_octaves(3)
+        .set_frequency(1.0 / 24.0);
+    let base = chunk_pos.map(|value| value * GRID_LEN as i32);
+
+    Octree::from_dense([GRID_LEN; 3], |x, y, z| {
+        let height = height_map[(z * GRID_LEN + x) as usize] as i32;
+        let pos = [base[0] + x as i32, base[1] + y as i32, base[2] + z as i32];
+
+        if pos[1] > height {
+            return None;
+        }
+
+        if pos[1] + 4 < height && fbm.get(pos.map(|value| value as f64)) > 0.2 {
+            return None;
+        }
+
+        Some(terrain_material((height - pos[1]) as u32))
+    })
+}
+
+/// Generator for a world, terrain and caves continue over all chunks,
+/// the other scenes are only placed into chunk (0, 0, 0).
+pub fn chunk_generator(kind: SceneKind, seed: u32) -> ChunkGenerator {
+    Box::new(move |chunk_pos| match kind {
+        SceneKind::TERRAIN => terrain_chunk(seed, chunk_pos),
+        SceneKind::CAVES => caves_chunk(seed, chunk_pos),
+        _ if chunk_pos == [0; 3] => generate(kind, seed),
+        _ => Octree::default(),
+    })
+}
+
+/// Random spheres and cuboids with random color,
+/// the same seed always creates the same scene.
+pub fn primitives(seed: u32, count: usize) -> Octree {
+    let mut rng = StdRng::seed_from_u64(seed as u64);
+    let grid_len = GRID_LEN as f32;
+
+    let primitive_list: Vec<(Primitive, Material)> = (0..count)
+        .map(|_| {
+            let center = Vec3::new(
+                rng.gen_range(0.0..grid_len),
+                rng.gen_range(0.0..grid_len),
+                rng.gen_range(0.0..grid_len),
+            );
+            let size = rng.gen_range(2.0..grid_len / 8.0);
+
+            let primitive = if rng.gen_bool(0.5) {
+                Primitive::SPHERE {
+                    center,
+                    radius: size,
+                }
+            } else {
+                Primitive::CUBOID {
+                    min: center.add_scalar(-size),
+                    max: center.add_scalar(size),
+                }
+            };
+
+            let color = Vec4::new(rng.gen(), rng.gen(), rng.gen(), 1.0);
+
+            (primitive, Material::from_color(color))
+        })
+        .collect();
+
+    // Idx of the first primitive that contains the voxel, only the
+    // bounding box of each primitive is tested
+    let grid_idx = |x: u32, y: u32, z: u32| ((z * GRID_LEN + y) * GRID_LEN + x) as usize;
+    let mut grid: Vec<Option<u16>> = vec![None; grid_idx(0, 0, GRID_LEN)];
+
+    for (primitive_idx, (primitive, _)) in primitive_list.iter().enumerate() {
+        let (min, max) = primitive.bounds();
+        let range = |axis: usize| {
+            min[axis].floor().clamp(0.0, grid_len) as u32..max[axis].ceil().clamp(0.0, grid_len) as u32
+        };
+
+        for z in range(2) {
+            for y in range(1) {
+                for x in range(0) {
+                    let cell = &mut grid[grid_idx(x, y, z)];
+                    if cell.is_none() && primitive.contains(Vec3::new(x as f32, y as f32, z as f32)) {
+                        *cell = Some(primitive_idx as u16);
+                    }
+                }
+            }
+        }
+    }
+
+    Octree::from_dense([GRID_LEN; 3], |x, y, z| {
+        grid[grid_idx(x, y, z)].map(|primitive_idx| primitive_list[primitive_idx as usize].1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{caves_chunk, chunk_generator, generate, terrain_chunk, SceneKind};
+    use crate::tree::{
+        material::Material,
+        octant::Octant,
+        octree::{Octree, GRID_LEN, MAX_DEPTH},
+        world::ChunkPos,
+    };
+
+    const KIND_LIST: [SceneKind; 4] = [
+        SceneKind::TEST,
+        SceneKind::TERRAIN,
+        SceneKind::CAVES,
+        SceneKind::PRIMITIVES,
+    ];
+
+    /// Material of the leaf that contains voxel, None if it is empty.
+    fn voxel_material(octree: &Octree, voxel: [u32; 3]) -> Option<Material> {
+        let mut idx = 0;
+        let mut span = GRID_LEN;
+
+        for _ in 0..MAX_DEPTH {
+            let node = octree.octant_data[idx];
+            if node.is_leaf() {
+                return Some(octree.material_data[idx]);
+            }
+            if !node.is_subdiv() {
+                return None;
+            }
+
+            span /= 2;
+            let mask = (0..3).fold(0, |mask, axis| mask | ((voxel[axis] & span > 0) as u32) << axis);
+            idx = node.get_first_child_idx() as usize + mask as usize;
+        }
+
+        None
+    }
+
+    /// Highest filled voxel of the column, None if the column is empty.
+    fn surface(octree: &Octree, x: u32, z: u32) -> Option<u32> {
+        (0..GRID_LEN).rev().find(|&y| voxel_material(octree, [x, y, z]).is_some())
+    }
+
+    #[test]
+    fn every_scene_builds() {
+        for kind in KIND_LIST {
+            let octree = generate(kind, 1);
+            assert!(octree.octant_data[0].is_subdiv(), "{:?}", kind);
+
+            let generator = chunk_generator(kind, 1);
+            for chunk_pos in [[1, 0, -1], [0, 1, 0]] {
+                let chunk = generator(chunk_pos);
+                assert_eq!(chunk.root_span, octree.root_span, "{:?} {:?}", kind, chunk_pos);
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_octree() {
+        for kind in KIND_LIST {
+            let octree = generate(kind, 3);
+            let again = generate(kind, 3);
+
+            assert_eq!(octree.octant_data, again.octant_data, "{:?}", kind);
+            assert_eq!(octree.material_data, again.material_data, "{:?}", kind);
+        }
+
+        for kind in [SceneKind::TERRAIN, SceneKind::PRIMITIVES] {
+            assert_ne!(generate(kind, 3).octant_data, generate(kind, 4).octant_data);
+        }
+    }
+
+    #[test]
+    fn chunk_borders_match() {
+        let chunk_list: [fn(u32, ChunkPos) -> Octree; 2] = [terrain_chunk, caves_chunk];
+
+        for chunk in chunk_list {
+            let octree = chunk(5, [0; 3]);
+            let right = chunk(5, [1, 0, 0]);
+
+            // The slope stays below a few voxel, a seam between the
+            // chunks would show up as a big step in the surface
+            for z in 0..GRID_LEN {
+                let left_height = surface(&octree, GRID_LEN - 1, z).unwrap();
+                let right_height = surface(&right, 0, z).unwrap();
+
+                assert!(left_height.abs_diff(right_height) <= 4, "{} {}", left_height, right_height);
+                assert_eq!(
+                    voxel_material(&octree, [GRID_LEN - 1, left_height, z]),
+                    voxel_material(&right, [0, right_height, z])
+                );
+            }
+        }
+
+        // Terrain continues below the chunk without holes
+        let octree = terrain_chunk(5, [0; 3]);
+        let below = terrain_chunk(5, [0, -1, 0]);
+        for x in 0..GRID_LEN {
+            for z in 0..GRID_LEN {
+                assert!(voxel_material(&below, [x, GRID_LEN - 1, z]).is_some());
+                assert!(voxel_material(&octree, [x, 0, z]).is_some());
+            }
+        }
+    }
+}
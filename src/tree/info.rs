@@ -0,0 +1,126 @@
+use std::{fmt, mem};
+
+use super::{
+    material::Material,
+    octant::Octant,
+    octree::{Octree, MAX_DEPTH},
+};
+
+/// Node counts of the reachable part of an octree, slots of removed
+/// blocks are only part of the node count.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OctreeInfo {
+    pub node_count: usize,
+    pub subdiv_count: usize,
+    pub leaf_count: usize,
+    pub empty_count: usize,
+    pub free_block_count: usize,
+
+    // Leaves per depth, leaf at depth 0 is a filled root
+    pub leaf_depth_list: [usize; MAX_DEPTH],
+    pub max_depth: usize,
+
+    pub byte_size: usize,
+    pub root_span: f32,
+}
+
+impl Octree {
+    pub fn info(&self) -> OctreeInfo {
+        let mut info = OctreeInfo {
+            node_count: self.octant_data.len(),
+            subdiv_count: 0,
+            leaf_count: 0,
+            empty_count: 0,
+            free_block_count: self.free_list.len(),
+
+            leaf_depth_list: [0; MAX_DEPTH],
+            max_depth: 0,
+
+            byte_size: mem::size_of_val(&self.octant_data[..])
+                + mem::size_of::<Material>() * self.material_data.len(),
+            root_span: self.root_span,
+        };
+
+        // (Idx, depth) of nodes that still need to be counted
+        let mut node_list = vec![(0usize, 0usize)];
+
+        while let Some((idx, depth)) = node_list.pop() {
+            let node = self.octant_data[idx];
+
+            if node.is_subdiv() {
+                info.subdiv_count += 1;
+
+                let first_child_idx = node.get_first_child_idx() as usize;
+                node_list.extend((0..8).map(|mask| (first_child_idx + mask, depth + 1)));
+            } else if node.is_leaf() {
+                info.leaf_count += 1;
+                info.leaf_depth_list[depth] += 1;
+                info.max_depth = info.max_depth.max(depth);
+            } else {
+                info.empty_count += 1;
+            }
+        }
+
+        info
+    }
+}
+
+impl fmt::Display for OctreeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Nodes        {}", self.node_count)?;
+        writeln!(f, "Subdiv       {}", self.subdiv_count)?;
+        writeln!(f, "Leaves       {}", self.leaf_count)?;
+        writeln!(f, "Empty        {}", self.empty_count)?;
+        writeln!(f, "Free blocks  {}", self.free_block_count)?;
+        writeln!(f, "Max depth    {}", self.max_depth)?;
+        writeln!(f, "Size         {:.2} MiB", self.byte_size as f64 / (1024.0 * 1024.0))?;
+        writeln!(f, "Root span    {}", self.root_span)?;
+
+        write!(f, "Leaves per depth")?;
+        for (depth, leaf_count) in self.leaf_depth_list.iter().enumerate() {
+            write!(f, "\n  {}  {}", depth, leaf_count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+
+    use crate::tree::{
+        material::Material,
+        octree::{Octree, MAX_DEPTH},
+    };
+
+    #[test]
+    fn info_counts_nodes_per_depth() {
+        let mut octree = Octree::default();
+        let leaf_span = octree.leaf_span();
+
+        octree.insert_node(Vec4::zeros(), Material::default());
+        octree.insert_node(Vec4::new(1.0, 0.0, 0.0, 0.0) * leaf_span, Material::default());
+        octree.insert_node_at_depth(Vec4::new(200.0, 200.0, 200.0, 0.0), Material::default(), 2);
+
+        let info = octree.info();
+        assert_eq!(info.leaf_count, 3);
+        assert_eq!(info.leaf_depth_list[MAX_DEPTH - 1], 2);
+        assert_eq!(info.leaf_depth_list[1], 1);
+        assert_eq!(info.max_depth, MAX_DEPTH - 1);
+
+        // Root plus one subdiv per depth down to the two leaves
+        assert_eq!(info.subdiv_count, MAX_DEPTH - 1);
+        assert_eq!(
+            info.subdiv_count + info.leaf_count + info.empty_count,
+            1 + 8 * info.subdiv_count
+        );
+
+        octree.remove_node(Vec4::zeros());
+        octree.remove_node(Vec4::new(1.0, 0.0, 0.0, 0.0) * leaf_span);
+        let info = octree.info();
+        assert_eq!(info.leaf_count, 1);
+        assert_eq!(info.subdiv_count, 1);
+        assert!(info.free_block_count > 0);
+    }
+}
@@ -0,0 +1,291 @@
+use nalgebra_glm::Vec4;
+
+use crate::mask_to_vec;
+
+use super::{
+    material::Material,
+    octant::Octant,
+    octree::{Octree, MAX_DEPTH},
+};
+
+/// Merge the materials of the filled children of a node. Colors are weighted
+/// with the occupancy, so a mostly empty child does not tint the parent as
+/// much as a filled one. Missing children count as empty.
+pub fn aggregate(child_list: &[Material]) -> Material {
+    let occupancy: f32 = child_list.iter().map(|child| child.occupancy).sum();
+    if occupancy <= 0.0 {
+        return Material {
+            occupancy: 0.0,
+
+            ..Default::default()
+        };
+    }
+
+    let weighted = |value: fn(&Material) -> Vec4| {
+        child_list.iter().fold(Vec4::zeros(), |sum, child| {
+            sum + value(child) * child.occupancy
+        }) / occupancy
+    };
+
+    // Id of the child that fills most of the node
+    let material_id = child_list
+        .iter()
+        .max_by(|a, b| a.occupancy.total_cmp(&b.occupancy))
+        .map_or(0, |child| child.material_id);
+
+    Material {
+        albedo: weighted(|child| child.albedo),
+        emission: weighted(|child| child.emission),
+        roughness: child_list
+            .iter()
+            .map(|child| child.roughness * child.occupancy)
+            .sum::<f32>()
+            / occupancy,
+        material_id,
+        occupancy: occupancy / 8.0,
+
+        ..Default::default()
+    }
+}
+
+impl Octree {
+    /// Material data where every interior node holds the aggregate of its
+    /// children (mip chain), leaves keep their material.
+    pub fn mip_data(&self) -> Vec<Material> {
+        let mut mip_data = self.material_data.clone();
+        self.fill_mip(&mut mip_data, 0);
+
+        mip_data
+    }
+
+    fn fill_mip(&self, mip_data: &mut Vec<Material>, idx: usize) -> Option<Material> {
+        let node = self.octant_data[idx];
+
+        if node.is_subdiv() {
+            let first_child_idx = node.get_first_child_idx() as usize;
+            let child_list: Vec<Material> = (0..8)
+                .filter_map(|mask| self.fill_mip(mip_data, first_child_idx + mask))
+                .collect();
+
+            mip_data[idx] = aggregate(&child_list);
+            Some(mip_data[idx])
+        } else if node.is_leaf() {
+            Some(mip_data[idx])
+        } else {
+            None
+        }
+    }
+
+    /// Copy of the octree with reduced resolution. A node is only split if
+    /// subdivide(pos_on_edge, span, depth) is true, otherwise it becomes a
+    /// leaf with the aggregate of its children as material.
+    pub fn lod<Function: Fn(Vec4, f32, usize) -> bool>(&self, subdivide: Function) -> Octree {
+        let mip_data = self.mip_data();
+        let mut octree = Octree {
+            root_span: self.root_span,
+
+            ..Default::default()
+        };
+
+        octree.octant_data[0] = self.copy_lod(
+            &mip_data,
+            &mut octree,
+            &subdivide,
+            0,
+            Vec4::zeros(),
+            self.root_span,
+            0,
+        );
+        octree.material_data[0] = mip_data[0];
+
+        log::info!(
+            "Lod octree has {} of {} nodes ...",
+            octree.octant_data.len(),
+            self.octant_data.len()
+        );
+
+        octree
+    }
+
+    /// Return the new node for idx, all kept children are already copied.
+    /// Blocks are written depth first like in compact.
+    #[allow(clippy::too_many_arguments)]
+    fn copy_lod<Function: Fn(Vec4, f32, usize) -> bool>(
+        &self,
+        mip_data: &[Material],
+        octree: &mut Octree,
+        subdivide: &Function,
+        idx: usize,
+        pos_on_edge: Vec4,
+        span: f32,
+        depth: usize,
+    ) -> u32 {
+        let node = self.octant_data[idx];
+
+        if !node.is_subdiv() {
+            return node;
+        }
+        if !subdivide(pos_on_edge, span, depth) {
+            return 0u32.set_leaf(true);
+        }
+
+        let old_first = node.get_first_child_idx() as usize;
+        let new_first = octree.octant_data.len();
+        octree.octant_data.extend_from_slice(&[0; 8]);
+        octree
+            .material_data
+            .extend_from_slice(&mip_data[old_first..old_first + 8]);
+
+        for mask in 0..8u32 {
+            let child_pos = pos_on_edge + mask_to_vec!(mask) * span * 0.5;
+
+            octree.octant_data[new_first + mask as usize] = self.copy_lod(
+                mip_data,
+                octree,
+                subdivide,
+                old_first + mask as usize,
+                child_pos,
+                span * 0.5,
+                depth + 1,
+            );
+        }
+
+        node.set_first_child_idx(new_first as u32)
+    }
+
+    /// Keep depth levels, leaves are at depth - 1 like in insert node at depth.
+    pub fn truncate(&self, depth: usize) -> Octree {
+        let depth = depth.clamp(1, MAX_DEPTH);
+        self.lod(|_, _, node_depth| node_depth + 1 < depth)
+    }
+
+    /// Split a node while its span is bigger than the distance to the cam
+    /// divided by detail, so the resolution drops with the distance.
+    /// Nodes that contain the cam are always split.
+    pub fn lod_by_distance(&self, cam_pos: Vec4, detail: f32) -> Octree {
+        self.lod(|pos_on_edge, span, _| {
+            let closest = Vec4::new(
+                cam_pos.x.clamp(pos_on_edge.x, pos_on_edge.x + span),
+                cam_pos.y.clamp(pos_on_edge.y, pos_on_edge.y + span),
+                cam_pos.z.clamp(pos_on_edge.z, pos_on_edge.z + span),
+                0.0,
+            );
+
+            span * detail > (cam_pos.xyz() - closest.xyz()).norm()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+
+    use super::aggregate;
+    use crate::tree::{
+        material::Material,
+        octant::Octant,
+        octree::{Octree, MAX_DEPTH},
+    };
+
+    fn red() -> Material {
+        Material::from_color(Vec4::new(1.0, 0.0, 0.0, 1.0))
+    }
+
+    fn blue() -> Material {
+        Material::from_color(Vec4::new(0.0, 0.0, 1.0, 1.0))
+    }
+
+    fn leaf_count(octree: &Octree) -> usize {
+        octree
+            .octant_data
+            .iter()
+            .filter(|node| node.is_leaf())
+            .count()
+    }
+
+    #[test]
+    fn aggregate_weights_by_occupancy() {
+        let half = Material {
+            occupancy: 0.5,
+
+            ..blue()
+        };
+
+        let material = aggregate(&[red(), half]);
+        assert_eq!(material.occupancy, 1.5 / 8.0);
+        assert!((material.albedo - Vec4::new(1.0, 0.0, 0.5, 1.5) / 1.5).norm() < 1e-6);
+
+        assert_eq!(aggregate(&[]).occupancy, 0.0);
+    }
+
+    #[test]
+    fn truncate_full_depth_keeps_leaves() {
+        let mut octree = Octree::default();
+        let leaf_span = octree.leaf_span();
+        for pos in [[0, 0, 0], [1, 0, 0], [5, 9, 3], [127, 127, 127]] {
+            let pos = Vec4::new(pos[0] as f32, pos[1] as f32, pos[2] as f32, 0.0);
+            octree.insert_node(pos * leaf_span, red());
+        }
+
+        let lod = octree.truncate(MAX_DEPTH);
+        assert_eq!(lod.octant_data.len(), octree.octant_data.len());
+        assert_eq!(leaf_count(&lod), 4);
+        assert_eq!(
+            lod.node_at_pos(Vec4::new(10.0, 18.0, 6.0, 0.0)).depth,
+            MAX_DEPTH as u32 - 1
+        );
+    }
+
+    #[test]
+    fn truncate_merges_children() {
+        let mut octree = Octree::default();
+        let leaf_span = octree.leaf_span();
+
+        // Two red and two blue voxel in the first node of depth 2
+        for (x, material) in [(0, red()), (1, red()), (2, blue()), (3, blue())] {
+            octree.insert_node(Vec4::new(x as f32, 0.0, 0.0, 0.0) * leaf_span, material);
+        }
+
+        let lod = octree.truncate(3);
+        assert_eq!(leaf_count(&lod), 1);
+
+        let pos_info = lod.node_at_pos(Vec4::zeros());
+        assert_eq!(pos_info.depth, 2);
+
+        // Leaf at depth 2 covers 32 ^ 3 voxel
+        let material = lod
+            .material_data
+            .iter()
+            .zip(&lod.octant_data)
+            .find(|(_, node)| node.is_leaf())
+            .unwrap()
+            .0;
+        assert!((material.occupancy - 4.0 / 32768.0).abs() < 1e-9);
+        assert!((material.albedo - Vec4::new(0.5, 0.0, 0.5, 1.0)).norm() < 1e-6);
+
+        // Root aggregate matches the merged leaf
+        assert_eq!(lod.material_data[0].albedo, material.albedo);
+        assert_eq!(
+            octree.mip_data()[0].occupancy,
+            lod.material_data[0].occupancy
+        );
+    }
+
+    #[test]
+    fn lod_by_distance_keeps_near_detail() {
+        let mut octree = Octree::default();
+        let leaf_span = octree.leaf_span();
+        for x in 0..128 {
+            octree.insert_node(Vec4::new(x as f32, 0.0, 0.0, 0.0) * leaf_span, red());
+        }
+
+        let lod = octree.lod_by_distance(Vec4::new(0.0, 0.0, 0.0, 0.0), 2.0);
+
+        assert!(lod.octant_data.len() < octree.octant_data.len());
+        assert_eq!(
+            lod.node_at_pos(Vec4::new(1.0, 1.0, 1.0, 0.0)).depth,
+            MAX_DEPTH as u32 - 1
+        );
+        assert!(lod.node_at_pos(Vec4::new(250.0, 1.0, 1.0, 0.0)).depth < MAX_DEPTH as u32 - 2);
+    }
+}
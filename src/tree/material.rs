@@ -0,0 +1,52 @@
+use nalgebra_glm::Vec4;
+
+/// Attributes of a single leaf, stored parallel to the octant data.
+/// The layout matches the std430 struct in the fragment shader.
+#[repr(C)]
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct Material {
+    pub albedo: Vec4,
+    pub emission: Vec4,
+
+    pub roughness: f32,
+    pub material_id: u32,
+
+    // Filled part of the node, 1.0 for leaves and the average
+    // of the children for the aggregate of an interior node
+    pub occupancy: f32,
+    pub padding: u32,
+}
+
+impl Material {
+    pub fn new(albedo: Vec4, emission: Vec4, roughness: f32, material_id: u32) -> Self {
+        Self {
+            albedo,
+            emission,
+            roughness,
+            material_id,
+
+            ..Default::default()
+        }
+    }
+
+    pub fn from_color(albedo: Vec4) -> Self {
+        Self {
+            albedo,
+
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            albedo: Vec4::new(1.0, 1.0, 1.0, 1.0),
+            emission: Default::default(),
+            roughness: 1.0,
+            material_id: Default::default(),
+            occupancy: 1.0,
+            padding: Default::default(),
+        }
+    }
+}
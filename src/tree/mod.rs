@@ -1,3 +1,12 @@
+pub mod dag;
+pub mod file;
+pub mod gen;
+pub mod info;
+pub mod lod;
+pub mod material;
 pub mod octant;
 pub mod octree;
-pub mod trace;
\ No newline at end of file
+pub mod trace;
+pub mod vox;
+pub mod voxelize;
+pub mod world;
\ No newline at end of file
@@ -1,10 +1,13 @@
-use crate::{read_bitrange, set_bit, bitcheck, write_bitrange};
+use crate::{bitcheck, read_bitrange, set_bit, write_bitrange};
 
-/// Bit 0 - 15 | first_child_idx
+/// Bit 0 - 15 | first_child_idx (lower part)
 /// Bit 16 - 23 | Child bitmask
 /// Bit 24 | Leaf?
 /// Bit 25 | Subdivide?
-
+/// Bit 26 - 31 | first_child_idx (upper part)
+///
+/// first_child_idx always uses all 22 bits, the GLSL child_idx macro
+/// decodes it the same way.
 pub trait Octant {
     fn set_subdiv(&self, subdiv: bool) -> Self;
     fn set_leaf(&self, leaf: bool) -> Self;
@@ -54,10 +57,130 @@ impl Octant for u32 {
     }
 
     fn get_first_child_idx(&self) -> Self {
-        read_bitrange!(self, 0, 15)
+        read_bitrange!(self, 0, 15) | (read_bitrange!(self, 26, 31) << 16)
     }
 
     fn set_first_child_idx(&self, child_offset: u32) -> Self {
-        write_bitrange!(self, child_offset, 0, 15)
+        let lower = write_bitrange!(self, child_offset, 0, 15);
+        write_bitrange!(lower, child_offset >> 16, 26, 31)
+    }
+}
+
+// Highest first child idx of 22 bits, up to 4 194 304 slots
+pub const MAX_FIRST_CHILD_IDX: u32 = (1 << 22) - 8;
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{Octant, MAX_FIRST_CHILD_IDX};
+
+    const CHILD_MASK: u32 = 0x00FF_0000;
+    const LEAF_BIT: u32 = 1 << 24;
+    const SUBDIV_BIT: u32 = 1 << 25;
+    const FIRST_CHILD_IDX_MASK: u32 = !(CHILD_MASK | LEAF_BIT | SUBDIV_BIT);
+
+    #[test]
+    fn empty_octant() {
+        let node = 0u32;
+
+        assert!(!node.is_leaf());
+        assert!(!node.is_subdiv());
+        assert!(!node.has_children());
+        assert_eq!(node.get_child_bitmask(), 0);
+        assert_eq!(node.get_first_child_idx(), 0);
+    }
+
+    #[test]
+    fn flag_round_trip() {
+        let mut rng = StdRng::seed_from_u64(24);
+
+        for _ in 0..1000 {
+            let node: u32 = rng.gen();
+
+            for flag in [true, false] {
+                let leaf = node.set_leaf(flag);
+                assert_eq!(leaf.is_leaf(), flag);
+                assert_eq!(leaf & !LEAF_BIT, node & !LEAF_BIT);
+
+                let subdiv = node.set_subdiv(flag);
+                assert_eq!(subdiv.is_subdiv(), flag);
+                assert_eq!(subdiv & !SUBDIV_BIT, node & !SUBDIV_BIT);
+            }
+        }
+    }
+
+    #[test]
+    fn child_mask_round_trip() {
+        let mut rng = StdRng::seed_from_u64(16);
+
+        for _ in 0..1000 {
+            let node: u32 = rng.gen();
+            let child_mask: u32 = rng.gen_range(0..256);
+
+            let mut filled = node;
+            for child_idx in 0..8 {
+                filled = filled.set_child_filled(child_idx, child_mask >> child_idx & 1 == 1);
+            }
+
+            assert_eq!(filled.get_child_bitmask(), child_mask);
+            assert_eq!(filled.has_children(), child_mask != 0);
+            assert_eq!(filled & !CHILD_MASK, node & !CHILD_MASK);
+            (0..8).for_each(|child_idx| {
+                assert_eq!(
+                    filled.check_child_filled(child_idx),
+                    child_mask >> child_idx & 1 == 1
+                )
+            });
+        }
+    }
+
+    #[test]
+    fn first_child_idx_round_trip() {
+        let mut rng = StdRng::seed_from_u64(26);
+
+        let max = MAX_FIRST_CHILD_IDX;
+        let mut idx_list = vec![0, 1, 8, (1 << 16) - 8, max - 1, max];
+        idx_list.extend((0..1000).map(|_| rng.gen_range(0..=max)));
+
+        for first_child_idx in idx_list {
+            let node: u32 = rng.gen();
+            let written = node.set_first_child_idx(first_child_idx);
+
+            assert_eq!(written.get_first_child_idx(), first_child_idx);
+            assert_eq!(
+                written & !FIRST_CHILD_IDX_MASK,
+                node & !FIRST_CHILD_IDX_MASK
+            );
+        }
+
+        // Idx below 65 536 only uses the lower part
+        assert_eq!(0u32.set_first_child_idx(0xFFFF) & !0xFFFF, 0);
+        assert_eq!(0u32.set_first_child_idx(1 << 16), 1 << 26);
+    }
+
+    #[test]
+    fn fields_are_independent() {
+        let mut rng = StdRng::seed_from_u64(31);
+
+        for _ in 0..1000 {
+            let leaf = rng.gen();
+            let subdiv = rng.gen();
+            let child_mask: u32 = rng.gen_range(0..256);
+            let first_child_idx = rng.gen_range(0..=MAX_FIRST_CHILD_IDX);
+
+            let mut node = 0u32
+                .set_first_child_idx(first_child_idx)
+                .set_leaf(leaf)
+                .set_subdiv(subdiv);
+            for child_idx in 0..8 {
+                node = node.set_child_filled(child_idx, child_mask >> child_idx & 1 == 1);
+            }
+
+            assert_eq!(node.is_leaf(), leaf);
+            assert_eq!(node.is_subdiv(), subdiv);
+            assert_eq!(node.get_child_bitmask(), child_mask);
+            assert_eq!(node.get_first_child_idx(), first_child_idx);
+        }
     }
 }
@@ -1,20 +1,33 @@
+use std::ops::Range;
+
 use nalgebra_glm::{Vec2, Vec3, Vec4};
 
 use crate::{mask_to_vec, vector::Vector};
 
 use super::{
-    octant::Octant,
+    material::Material,
+    octant::{Octant, MAX_FIRST_CHILD_IDX},
     trace::{BranchInfo, PosInfo},
 };
 
 pub const MAX_DEPTH: usize = 8;
 pub const MAX_DEPTH_LIMIT: usize = 16;
+// Leaf count along one axis of the root
+pub const GRID_LEN: u32 = 1 << (MAX_DEPTH - 1);
 pub const TEXTURE_ALIGN: f32 = 16.0;
 
+#[derive(Clone)]
 pub struct Octree {
     // RootIndex = 0
     pub octant_data: Vec<u32>,
+    // Material of each octant, same idx as in octant data
+    pub material_data: Vec<Material>,
     pub root_span: f32,
+
+    // First child idx of unused blocks of eight children
+    pub free_list: Vec<u32>,
+    // Ranges of octant data changed since the last upload
+    pub dirty_list: Vec<Range<usize>>,
 }
 
 impl Octree {
@@ -65,10 +78,21 @@ impl Octree {
         pos_info
     }
 
-    pub fn insert_node(&mut self, insert_pos: Vec4) -> PosInfo {
+    pub fn insert_node(&mut self, insert_pos: Vec4, material: Material) -> PosInfo {
+        self.insert_node_at_depth(insert_pos, material, MAX_DEPTH)
+    }
+
+    /// Insert leaf at depth - 1 instead of MAX_DEPTH - 1, so with
+    /// a smaller depth the leaf covers a bigger cube.
+    pub fn insert_node_at_depth(
+        &mut self,
+        insert_pos: Vec4,
+        material: Material,
+        depth: usize,
+    ) -> PosInfo {
         let (mut branch_data, mut pos_info) = self.get_new_root_info(insert_pos);
 
-        for _ in 1..MAX_DEPTH {
+        for _ in 1..depth.clamp(1, MAX_DEPTH) {
             pos_info.move_into_child(&mut branch_data, |branch| {
                 let mut branch = branch.clone();
 
@@ -78,17 +102,13 @@ impl Octree {
                         // Set Nodetype to be subdivide
                         .set_subdiv(true)
                         // Set child offset, offset is index of first child
-                        .set_first_child_idx(self.octant_data.len() as u32);
-
-                    // Add new child to octant data
-                    for _ in 0..8 {
-                        self.octant_data.push(0);
-                    }
+                        .set_first_child_idx(self.alloc_child_block());
                 }
 
                 // Set child filled and update parent in octant data
                 self.octant_data[branch.parent_idx()] =
                     branch.parent.set_child_filled(branch.mask, true);
+                self.mark_dirty(branch.parent_idx()..branch.parent_idx() + 1);
 
                 (branch.idx, branch.node) = branch.get_child(&self.octant_data, branch.mask);
 
@@ -96,12 +116,304 @@ impl Octree {
             });
         }
 
-        self.octant_data[pos_info.branch(&branch_data).idx()] =
-            self.octant_data[pos_info.branch(&branch_data).idx()].set_leaf(true);
+        let leaf_idx = pos_info.branch(&branch_data).idx();
+        self.octant_data[leaf_idx] = self.octant_data[leaf_idx].set_leaf(true);
+        self.material_data[leaf_idx] = material;
+        self.mark_dirty(leaf_idx..leaf_idx + 1);
 
         pos_info
     }
 
+    /// Clear the leaf at remove pos. A coarser leaf on the way down is
+    /// subdivided first, so only the cube at MAX_DEPTH - 1 is removed.
+    /// After that the child bit is cleared in every parent on the way up,
+    /// a parent with an empty bitmask is collapsed, so the tree only
+    /// contains filled branches. Return None if there is no leaf at remove pos.
+    pub fn remove_node(&mut self, remove_pos: Vec4) -> Option<PosInfo> {
+        let (mut branch_data, mut pos_info) = self.get_new_root_info(remove_pos);
+
+        for _ in 1..MAX_DEPTH {
+            let branch = pos_info.branch(&branch_data);
+
+            if branch.node.is_leaf() && !branch.node.is_subdiv() {
+                branch_data[pos_info.depth_idx()].node = self.subdivide_leaf(branch.idx());
+            } else if !branch.node.is_subdiv() {
+                return None;
+            }
+
+            pos_info.move_into_child(&mut branch_data, |mut branch| {
+                (branch.idx, branch.node) = branch.get_child(&self.octant_data, branch.mask);
+
+                branch
+            });
+        }
+
+        let leaf = pos_info.branch(&branch_data);
+        if !leaf.node.is_leaf() {
+            return None;
+        }
+
+        self.octant_data[leaf.idx()] = leaf.node.set_leaf(false);
+        self.material_data[leaf.idx()] = Material::default();
+        self.mark_dirty(leaf.idx()..leaf.idx() + 1);
+
+        // Move up and clear child bit, stop at first parent with children left
+        for depth in (1..MAX_DEPTH).rev() {
+            let branch = branch_data[depth];
+            let parent = self.octant_data[branch.parent_idx()].set_child_filled(branch.mask, false);
+            self.mark_dirty(branch.parent_idx()..branch.parent_idx() + 1);
+
+            if parent.has_children() {
+                self.octant_data[branch.parent_idx()] = parent;
+                break;
+            }
+
+            // Collapse parent, children are no longer referenced
+            self.free_list.push(parent.get_first_child_idx());
+            self.octant_data[branch.parent_idx()] =
+                parent.set_subdiv(false).set_first_child_idx(0);
+        }
+
+        Some(pos_info)
+    }
+
+    /// Split the leaf at idx into eight leaves with its material,
+    /// return the new subdivided node.
+    fn subdivide_leaf(&mut self, idx: usize) -> u32 {
+        let material = self.material_data[idx];
+        let first_child_idx = self.alloc_child_block();
+        let first = first_child_idx as usize;
+
+        self.octant_data[first..first + 8].fill(0u32.set_leaf(true));
+        self.material_data[first..first + 8].fill(material);
+
+        let node = (0..8).fold(
+            0u32.set_subdiv(true).set_first_child_idx(first_child_idx),
+            |node, mask| node.set_child_filled(mask, true),
+        );
+        self.octant_data[idx] = node;
+        self.material_data[idx] = Material::default();
+        self.mark_dirty(idx..idx + 1);
+
+        node
+    }
+
+    /// Build octree from dense voxel grid in one pass. The grid is walked
+    /// bottom up, a block of children is only written once all of its
+    /// subtrees are finished. Voxel (x, y, z) is the leaf at
+    /// pos (x, y, z) * leaf span, so dims can be up to GRID_LEN.
+    /// Bigger grids are split into chunks by World::insert_dense.
+    pub fn from_dense<Function: Fn(u32, u32, u32) -> Option<Material>>(
+        dims: [u32; 3],
+        sample: Function,
+    ) -> Self {
+        let mut octree = Self::default();
+
+        assert!(
+            dims.iter().all(|&dim| dim <= GRID_LEN),
+            "ERR_DENSE_DIMS_TOO_LARGE"
+        );
+
+        (octree.octant_data[0], octree.material_data[0]) =
+            octree.build_dense(&dims, &sample, [0; 3], 0);
+
+        octree
+    }
+
+    /// Return node and material for the cube at origin (in voxel) and depth,
+    /// all children of the cube are already added to octant data.
+    fn build_dense<Function: Fn(u32, u32, u32) -> Option<Material>>(
+        &mut self,
+        dims: &[u32; 3],
+        sample: &Function,
+        origin: [u32; 3],
+        depth: usize,
+    ) -> (u32, Material) {
+        if origin.iter().zip(dims).any(|(pos, dim)| pos >= dim) {
+            return (0, Material::default());
+        }
+
+        if depth == MAX_DEPTH - 1 {
+            return match sample(origin[0], origin[1], origin[2]) {
+                Some(material) => (0u32.set_leaf(true), material),
+                None => (0, Material::default()),
+            };
+        }
+
+        // Size of a child in voxel
+        let child_len = 1 << (MAX_DEPTH - 2 - depth);
+
+        let mut node_list = [0u32; 8];
+        let mut material_list = [Material::default(); 8];
+        let mut node = 0u32;
+
+        for mask in 0..8u32 {
+            let child_origin = [
+                origin[0] + (mask & 1) * child_len,
+                origin[1] + ((mask >> 1) & 1) * child_len,
+                origin[2] + ((mask >> 2) & 1) * child_len,
+            ];
+
+            let (child, material) = self.build_dense(dims, sample, child_origin, depth + 1);
+            if child != 0 {
+                node = node.set_child_filled(mask, true);
+            }
+
+            node_list[mask as usize] = child;
+            material_list[mask as usize] = material;
+        }
+
+        if !node.has_children() {
+            return (0, Material::default());
+        }
+
+        let first_child_idx = self.octant_data.len() as u32;
+        self.octant_data.extend_from_slice(&node_list);
+        self.material_data.extend_from_slice(&material_list);
+
+        (
+            node.set_subdiv(true).set_first_child_idx(first_child_idx),
+            Material::default(),
+        )
+    }
+
+    /// Root that is one leaf, filled completely with material.
+    pub fn filled(root_span: f32, material: Material) -> Self {
+        Self {
+            octant_data: vec![0u32.set_leaf(true)],
+            material_data: vec![material],
+            root_span,
+
+            ..Default::default()
+        }
+    }
+
+    /// Replace a root that is completely filled with one material by a
+    /// single root leaf, like the solid chunks below the terrain.
+    /// Return true if the octree was collapsed.
+    pub fn collapse_uniform(&mut self) -> bool {
+        if self.octant_data.len() == 1 {
+            return false;
+        }
+
+        match self.uniform_material(0) {
+            Some(material) => {
+                *self = Self::filled(self.root_span, material);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Material of the node at idx, if all of its cube has this material.
+    fn uniform_material(&self, idx: usize) -> Option<Material> {
+        let node = self.octant_data[idx];
+        if node.is_leaf() && !node.is_subdiv() {
+            return Some(self.material_data[idx]);
+        }
+        if !node.is_subdiv() || node.get_child_bitmask() != 0xFF {
+            return None;
+        }
+
+        let first_child_idx = node.get_first_child_idx() as usize;
+        let material = self.uniform_material(first_child_idx)?;
+
+        (1..8)
+            .all(|mask| self.uniform_material(first_child_idx + mask) == Some(material))
+            .then_some(material)
+    }
+
+    pub fn leaf_span(&self) -> f32 {
+        self.root_span / GRID_LEN as f32
+    }
+
+    /// Get first child idx of an empty block of eight children. Reuse
+    /// block from free list if possible, else add new block to octant data.
+    fn alloc_child_block(&mut self) -> u32 {
+        match self.free_list.pop() {
+            Some(first_child_idx) => {
+                let first = first_child_idx as usize;
+                self.octant_data[first..first + 8].fill(0);
+                self.material_data[first..first + 8].fill(Material::default());
+                self.mark_dirty(first..first + 8);
+
+                first_child_idx
+            }
+            None => {
+                let first_child_idx = self.octant_data.len() as u32;
+                assert!(first_child_idx <= MAX_FIRST_CHILD_IDX, "ERR_OCTANT_DATA_FULL");
+
+                self.octant_data.extend_from_slice(&[0; 8]);
+                self.material_data.extend_from_slice(&[Material::default(); 8]);
+                self.mark_dirty(first_child_idx as usize..first_child_idx as usize + 8);
+
+                first_child_idx
+            }
+        }
+    }
+
+    /// Rewrite octant data, so that only reachable blocks are left.
+    /// Children are copied depth first and the first child idx of
+    /// each parent is updated. Return the number of reclaimed slots.
+    pub fn compact(&mut self) -> usize {
+        let mut octant_data = vec![self.octant_data[0]];
+        let mut material_data = vec![self.material_data[0]];
+        // (Old idx, new idx) of parents that still need their children copied
+        let mut parent_list = vec![(0usize, 0usize)];
+
+        while let Some((old_idx, new_idx)) = parent_list.pop() {
+            let node = self.octant_data[old_idx];
+            if !node.is_subdiv() {
+                continue;
+            }
+
+            let old_first = node.get_first_child_idx() as usize;
+            let new_first = octant_data.len();
+
+            octant_data.extend_from_slice(&self.octant_data[old_first..old_first + 8]);
+            material_data.extend_from_slice(&self.material_data[old_first..old_first + 8]);
+            octant_data[new_idx] = node.set_first_child_idx(new_first as u32);
+
+            for idx in 0..8 {
+                parent_list.push((old_first + idx, new_first + idx));
+            }
+        }
+
+        let reclaimed = self.octant_data.len() - octant_data.len();
+        log::info!("Compacted octree, reclaimed {} slots ...", reclaimed);
+
+        self.octant_data = octant_data;
+        self.material_data = material_data;
+        self.free_list.clear();
+        self.mark_dirty(0..self.octant_data.len());
+
+        reclaimed
+    }
+
+    pub fn mark_dirty(&mut self, idx_range: Range<usize>) {
+        self.dirty_list.push(idx_range);
+    }
+
+    /// Return the changed ranges sorted by idx, overlapping and
+    /// adjacent ranges are merged. Afterwards nothing is dirty.
+    pub fn take_dirty(&mut self) -> Vec<Range<usize>> {
+        let mut dirty_list = std::mem::take(&mut self.dirty_list);
+        dirty_list.sort_by_key(|range| range.start);
+
+        let mut merged_list: Vec<Range<usize>> = vec![];
+        for range in dirty_list {
+            match merged_list.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged_list.push(range),
+            }
+        }
+
+        merged_list
+    }
+
+    /// Push every node with a leaf child or a child at max depth - 1 into
+    /// leaf data, each node only once. Subdivided children above max
+    /// depth - 1 are collected the same way.
     pub fn collect_branch(
         &self,
         branch_data: &[BranchInfo; MAX_DEPTH],
@@ -110,6 +422,7 @@ impl Octree {
         max_depth: u32,
     ) -> [BranchInfo; MAX_DEPTH] {
         let mut branch_data = branch_data.clone();
+        let mut is_pushed = false;
 
         for idx in 0..8 {
             let mut pos_info = pos_info.clone();
@@ -126,7 +439,8 @@ impl Octree {
 
             if branch.node.is_subdiv() && pos_info.depth < max_depth - 1 {
                 branch_data = self.collect_branch(&branch_data, &pos_info, leaf_data, max_depth);
-            } else if branch.node.is_leaf() || branch.node.is_subdiv() {
+            } else if (branch.node.is_leaf() || branch.node.is_subdiv()) && !is_pushed {
+                is_pushed = true;
                 pos_info.move_up(&mut branch_data);
 
                 leaf_data.push((pos_info, branch_data.clone()));
@@ -174,8 +488,8 @@ impl Octree {
             } else if branch.node.is_leaf() || branch.node.is_subdiv() {
                 pos_info.move_up(&mut branch_data);
 
-                let local_pos = pos_info.local_pos - pos_on_edge;
-                let mut pos = base_px + Vec2::new(local_pos.x, local_pos.y + (local_pos.z * base_span));
+                let local_pos = pos_info.pos_on_edge - pos_on_edge;
+                let pos = base_px + Vec2::new(local_pos.x, local_pos.y + (local_pos.z * base_span));
 
                 img.put_pixel(pos.x as u32, pos.y as u32, image::Rgba([255, 255, 255, 0]));
                 // *img.get_pixel_mut(5, 5) = image::Rgb([255, 255, 255]);
@@ -189,15 +503,15 @@ impl Octree {
         // let fbm = Fbm::<Perlin>::new(0);
         // let mut rng = rand::thread_rng();
 
-        self.insert_node(Vec4::ftv(0.0));
+        self.insert_node(Vec4::ftv(0.0), Material::default());
 
-        self.insert_node(Vec4::ftv(8.0));
+        self.insert_node(Vec4::ftv(8.0), Material::from_color(Vec4::new(1.0, 0.0, 0.0, 1.0)));
 
-        //self.insert_node(Vec4::ftv(4.0));
+        //self.insert_node(Vec4::ftv(4.0), Material::default());
 
-        self.insert_node(Vec4::ftv(17.0));
+        self.insert_node(Vec4::ftv(17.0), Material::from_color(Vec4::new(0.0, 1.0, 0.0, 1.0)));
 
-        self.insert_node(Vec4::ftv(78.0));
+        self.insert_node(Vec4::ftv(78.0), Material::from_color(Vec4::new(0.0, 0.0, 1.0, 1.0)));
 
         for nude in self.octant_data.clone() {
             log::info!(
@@ -215,7 +529,305 @@ impl Default for Octree {
     fn default() -> Self {
         Self {
             octant_data: vec![0],
+            material_data: vec![Material::default()],
             root_span: (1 << MAX_DEPTH) as f32,
+            free_list: vec![],
+            dirty_list: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{Octree, GRID_LEN, MAX_DEPTH};
+    use crate::tree::{
+        material::Material,
+        octant::Octant,
+        trace::Ray,
+    };
+
+    /// Depth and idx of the leaf that contains pos.
+    fn leaf_at(octree: &Octree, pos: Vec4) -> Option<(u32, usize)> {
+        let (mut branch_data, mut pos_info) = octree.get_new_root_info(pos);
+
+        while pos_info.branch(&branch_data).node.is_subdiv() {
+            pos_info.move_into_child(&mut branch_data, |mut branch| {
+                (branch.idx, branch.node) = branch.get_child(&octree.octant_data, branch.mask);
+
+                branch
+            });
+        }
+
+        let branch = pos_info.branch(&branch_data);
+        branch.node.is_leaf().then_some((pos_info.depth, branch.idx()))
+    }
+
+    #[test]
+    fn take_dirty_merges_ranges() {
+        let mut octree = Octree::default();
+        for range in [10..12, 0..1, 11..16, 16..20, 30..38, 1..2] {
+            octree.mark_dirty(range);
+        }
+
+        assert_eq!(octree.take_dirty(), vec![0..2, 10..20, 30..38]);
+        assert!(octree.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn edits_mark_dirty() {
+        let mut octree = Octree::default();
+        let pos = Vec4::new(4.0, 4.0, 4.0, 0.0);
+
+        octree.insert_node(pos, Material::default());
+        assert_eq!(octree.take_dirty(), vec![0..octree.octant_data.len()]);
+
+        // Second leaf in the same block only touches the parents and the leaf
+        let pos_info = octree.insert_node(pos + Vec4::new(2.0, 0.0, 0.0, 0.0), Material::default());
+        let dirty_list = octree.take_dirty();
+        assert_eq!(dirty_list.iter().map(|range| range.len()).sum::<usize>(), 8);
+        assert!(dirty_list.iter().any(|range| range.contains(&0)));
+
+        octree.remove_node(pos).unwrap();
+        assert!(!octree.take_dirty().is_empty());
+        assert_eq!(pos_info.depth, 7);
+    }
+
+    #[test]
+    fn remove_collapses_parents() {
+        let mut octree = Octree::default();
+        let pos = Vec4::new(100.0, 30.0, 200.0, 0.0);
+        let other_pos = Vec4::new(102.0, 30.0, 200.0, 0.0);
+
+        octree.insert_node(pos, Material::default());
+        octree.insert_node(other_pos, Material::default());
+        assert!(octree.remove_node(Vec4::new(10.0, 10.0, 10.0, 0.0)).is_none());
+
+        // Sibling is left, only the leaf is cleared
+        octree.remove_node(pos).unwrap();
+        assert!(octree.free_list.is_empty());
+        assert!(leaf_at(&octree, pos).is_none());
+        assert!(leaf_at(&octree, other_pos).is_some());
+        assert!(octree.remove_node(pos).is_none());
+
+        // Last leaf collapses every parent up to the root
+        octree.remove_node(other_pos).unwrap();
+        assert_eq!(octree.octant_data[0], 0);
+        assert_eq!(octree.free_list.len(), MAX_DEPTH - 1);
+    }
+
+    #[test]
+    fn remove_subdivides_coarse_leaf() {
+        let mut octree = Octree::default();
+        let material = Material::from_color(Vec4::new(1.0, 0.0, 0.0, 1.0));
+        let pos = Vec4::new(70.0, 70.0, 70.0, 0.0);
+
+        // Leaf at depth 2 covers the cube from 64 to 128
+        octree.insert_node_at_depth(pos, material, 3);
+        octree.remove_node(pos).unwrap();
+
+        assert!(leaf_at(&octree, pos).is_none());
+
+        // Leaves next to the removed cube are as small as it, others stay coarse
+        let (depth, idx) = leaf_at(&octree, pos - Vec4::new(2.0, 0.0, 0.0, 0.0)).unwrap();
+        assert_eq!(depth as usize, MAX_DEPTH - 1);
+        assert_eq!(octree.material_data[idx], material);
+        assert_eq!(leaf_at(&octree, Vec4::new(127.0, 64.0, 64.0, 0.0)).unwrap().0, 3);
+
+        // Seven leaves are left on each level from depth 3 to 7
+        let leaf_count = octree.octant_data.iter().filter(|node| node.is_leaf()).count();
+        assert_eq!(leaf_count, 7 * 5);
+    }
+
+    #[test]
+    fn free_blocks_are_reused() {
+        let mut octree = Octree::default();
+        let pos = Vec4::new(100.0, 30.0, 200.0, 0.0);
+
+        octree.insert_node(Vec4::new(4.0, 4.0, 4.0, 0.0), Material::default());
+        octree.insert_node(pos, Material::default());
+        let slot_count = octree.octant_data.len();
+
+        // Only the root block is shared, the six blocks below are freed
+        octree.remove_node(pos).unwrap();
+        assert_eq!(octree.free_list.len(), MAX_DEPTH - 2);
+
+        octree.insert_node(pos, Material::default());
+        assert!(octree.free_list.is_empty());
+        assert_eq!(octree.octant_data.len(), slot_count);
+        assert!(leaf_at(&octree, pos).is_some());
+    }
+
+    #[test]
+    fn alloc_past_short_child_idx() {
+        let mut octree = Octree::default();
+
+        // Every leaf gets its own parent block, 16 384 blocks need more than 16 bit
+        for x in 0..32 {
+            for y in 0..32 {
+                for z in 0..16 {
+                    let pos = Vec4::new(x as f32, y as f32, z as f32, 0.0) * 8.0;
+                    octree.insert_node(pos, Material::default());
+                }
+            }
         }
+
+        assert!(octree.octant_data.len() > 1 << 16);
+        assert!(leaf_at(&octree, Vec4::new(248.0, 248.0, 120.0, 0.0)).is_some());
+    }
+
+    #[test]
+    fn from_dense_matches_insert_node() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let dims = [20, 13, 33];
+        let color_list = [Vec4::new(1.0, 0.0, 0.0, 1.0), Vec4::new(0.0, 0.0, 1.0, 1.0)];
+
+        let grid: Vec<Option<Material>> = (0..dims.iter().product::<u32>())
+            .map(|_| {
+                rng.gen_bool(0.3)
+                    .then(|| Material::from_color(color_list[rng.gen_range(0..2)]))
+            })
+            .collect();
+        let sample = |x: u32, y: u32, z: u32| grid[(x + dims[0] * (y + dims[1] * z)) as usize];
+
+        let dense = Octree::from_dense(dims, sample);
+        let mut octree = Octree::default();
+        let leaf_span = octree.leaf_span();
+
+        for x in 0..dims[0] {
+            for y in 0..dims[1] {
+                for z in 0..dims[2] {
+                    if let Some(material) = sample(x, y, z) {
+                        let pos = Vec4::new(x as f32, y as f32, z as f32, 0.0) * leaf_span;
+                        octree.insert_node(pos, material);
+                    }
+                }
+            }
+        }
+
+        // Same leaves and the same number of slots, only the order differs
+        assert_eq!(dense.octant_data.len(), octree.octant_data.len());
+        for x in 0..dims[0] + 2 {
+            for y in 0..dims[1] + 2 {
+                for z in 0..dims[2] + 2 {
+                    let pos = Vec4::new(x as f32, y as f32, z as f32, 0.0) * leaf_span;
+                    let dense_leaf =
+                        leaf_at(&dense, pos).map(|(depth, idx)| (depth, dense.material_data[idx]));
+                    let leaf = leaf_at(&octree, pos)
+                        .map(|(depth, idx)| (depth, octree.material_data[idx]));
+
+                    assert_eq!(dense_leaf, leaf);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn from_dense_empty_grid() {
+        // Nothing sampled or nothing to sample leaves only the empty root
+        for dims in [[16, 16, 16], [0, 16, 16], [16, 0, 0]] {
+            let octree = Octree::from_dense(dims, |_, _, _| None);
+
+            assert_eq!(octree.octant_data.len(), 1);
+            assert_eq!(octree.octant_data[0].get_child_bitmask(), 0);
+            assert!(leaf_at(&octree, Vec4::zeros()).is_none());
+        }
+
+        let octree = Octree::from_dense([0; 3], |_, _, _| Some(Material::default()));
+        assert_eq!(octree.octant_data.len(), 1);
+    }
+
+    #[test]
+    fn collapse_uniform_root() {
+        let material = Material::from_color(Vec4::new(0.5, 0.5, 0.5, 1.0));
+        let solid = Octree::from_dense([GRID_LEN; 3], |_, _, _| Some(material));
+        let pos = Vec4::new(10.0, 10.0, 10.0, 0.0);
+
+        // One different leaf or a hole keeps the tree
+        let mut octree = solid.clone();
+        let (_, idx) = leaf_at(&octree, pos).unwrap();
+        octree.material_data[idx] = Material::default();
+        assert!(!octree.collapse_uniform());
+
+        let mut octree = solid.clone();
+        octree.remove_node(pos).unwrap();
+        assert!(!octree.collapse_uniform());
+
+        let mut octree = solid;
+        assert!(octree.collapse_uniform());
+        assert_eq!(octree.octant_data, vec![0u32.set_leaf(true)]);
+        assert_eq!(octree.material_data, vec![material]);
+        assert!(!octree.collapse_uniform());
+
+        // Root leaf is hit and can be carved like any coarse leaf
+        assert_eq!(leaf_at(&octree, pos), Some((0, 0)));
+        octree.remove_node(pos).unwrap();
+        assert!(leaf_at(&octree, pos).is_none());
+    }
+
+    #[test]
+    fn compact_keeps_hits() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut octree = Octree::default();
+        let random_pos = |rng: &mut StdRng| {
+            Vec4::new(
+                rng.gen_range(0.0..256.0),
+                rng.gen_range(0.0..256.0),
+                rng.gen_range(0.0..256.0),
+                0.0,
+            )
+        };
+
+        let pos_list: Vec<Vec4> = (0..400).map(|_| random_pos(&mut rng)).collect();
+        for (idx, pos) in pos_list.iter().enumerate() {
+            let color = Vec4::new(idx as f32 / 400.0, 0.0, 1.0, 1.0);
+            octree.insert_node(*pos, Material::from_color(color));
+        }
+        for pos in pos_list.iter().step_by(2) {
+            octree.remove_node(*pos);
+        }
+
+        // Rays aim at the leaves that are left, some are hidden by others
+        let ray_list: Vec<Ray> = pos_list
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(|pos| {
+                let origin = random_pos(&mut rng) * 1.5 - Vec4::new(64.0, 64.0, 64.0, 0.0);
+                Ray {
+                    origin,
+                    dir: pos - origin,
+                }
+            })
+            .collect();
+        let cast = |octree: &Octree| {
+            ray_list
+                .iter()
+                .map(|ray| {
+                    octree.cast_ray(ray, f32::MAX).map(|hit| {
+                        (
+                            hit.pos,
+                            hit.normal,
+                            hit.dist,
+                            hit.depth,
+                            octree.material_data[hit.idx as usize],
+                        )
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let hit_list = cast(&octree);
+        let slot_count = octree.octant_data.len();
+
+        let reclaimed = octree.compact();
+        assert!(reclaimed > 0);
+        assert_eq!(octree.octant_data.len(), slot_count - reclaimed);
+        assert!(octree.free_list.is_empty());
+
+        assert_eq!(cast(&octree), hit_list);
+        assert!(hit_list.iter().filter(|hit| hit.is_some()).count() > 150);
     }
 }
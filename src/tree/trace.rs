@@ -1,8 +1,11 @@
 use nalgebra_glm::Vec4;
 
-use crate::{mask_to_vec, read_bitrange, vec_to_mask, vector::Vector};
+use crate::{mask_to_vec, vec_to_mask, vector::Vector};
 
-use super::{octant::Octant, octree::MAX_DEPTH};
+use super::{
+    octant::Octant,
+    octree::{Octree, MAX_DEPTH},
+};
 
 #[repr(C)]
 #[derive(Clone, Debug, Copy)]
@@ -11,6 +14,19 @@ pub struct Ray {
     pub dir: Vec4,
 }
 
+/// First leaf along a ray, pos and normal are in world space.
+/// Dist is measured along the normalized ray dir.
+/// Normal is zero, if the ray starts inside of the leaf.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct Hit {
+    pub pos: Vec4,
+    pub normal: Vec4,
+    pub dist: f32,
+
+    pub depth: u32,
+    pub idx: u32,
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, Copy)]
 pub struct BranchInfo {
@@ -47,7 +63,7 @@ impl BranchInfo {
     }
 
     pub fn first_child_idx(&self) -> u32 {
-        read_bitrange!(self.parent, 0, 15)
+        self.parent.get_first_child_idx()
     }
 
     pub fn get_child(&self, octant_data: &Vec<u32>, child_mask: u32) -> (u32, u32) {
@@ -76,48 +92,64 @@ impl PosInfo {
         branch_data[self.depth_idx()]
     }
 
-    /// Not tested
-
+    /// Get face neighbor in the direction of dir mask (one axis bit),
+    /// inv moves in negative direction like in the fragment shader.
+    /// Moves up until the neighbor is a sibling and then down on the
+    /// mirrored path, but never deeper than self. The result is the
+    /// neighbor at the same depth or the coarser node that contains it.
+    /// Branch data is only updated, if there is a neighbor inside of the root.
     pub fn neighbor(
         &self,
         octant_data: &Vec<u32>,
         branch_data: &mut [BranchInfo; MAX_DEPTH],
-        max_depth: usize,
         dir_mask: u32,
+        inv: bool,
     ) -> Option<PosInfo> {
-        let mut pos_info = self.clone();
+        let mut pos_info = *self;
+        let mut neighbor_data = *branch_data;
+
+        // Move up, until the neighbor has the same parent
+        loop {
+            if pos_info.depth == 0 {
+                return None;
+            }
 
-        for _ in self.depth as usize..max_depth {
-            let mut branch = branch_data[self.depth_idx()];
-            let new_mask = branch.mask | dir_mask;
-            let move_up = branch.mask & dir_mask;
+            let mask = neighbor_data[pos_info.depth_idx()].mask;
+            let out_parent = if inv { !mask & dir_mask } else { mask & dir_mask };
 
-            // Check if move up
-            if move_up > 0 {
-                pos_info.move_up(&branch_data);
-            } else {
-                // Stop moving up and get next node
-                branch = self
-                    .branch(branch_data)
-                    .move_to_neighbor(octant_data, new_mask);
+            if out_parent == 0 {
+                break;
+            }
 
-                // Start moving down
-                while branch.node.is_subdiv() {
-                    pos_info.move_into_child(branch_data, |branch| {
-                        let mut branch = branch.clone();
+            pos_info.move_up(&neighbor_data);
+        }
+        pos_info.move_up(&neighbor_data);
 
-                        (branch.idx, branch.node) =
-                            branch.get_child(&octant_data, branch.mask);
+        // Move down, the neighbor path is the old path flipped on the dir axis
+        while pos_info.depth < self.depth && pos_info.branch(&neighbor_data).node.is_subdiv() {
+            let mask = branch_data[pos_info.depth_idx() + 1].mask ^ dir_mask;
 
-                        branch
-                    });
-                }
+            pos_info.update_branch_to_child(&mut neighbor_data, |branch| {
+                let mut branch = *branch;
 
-                return Some(pos_info);
-            }
+                branch.mask = mask;
+                (branch.idx, branch.node) = branch.get_child(octant_data, mask);
+
+                branch
+            });
+        }
+
+        // Same pos relative to the start node, but moved by one span
+        let span = branch_data[self.depth_idx()].span;
+        let mut dir = mask_to_vec!(dir_mask) * span;
+        if inv {
+            dir = -dir;
         }
+        pos_info.local_pos = self.pos_on_edge + self.local_pos + dir - pos_info.pos_on_edge;
+
+        *branch_data = neighbor_data;
 
-        None
+        Some(pos_info)
     }
 
     pub fn move_up(&mut self, branch_data: &[BranchInfo; MAX_DEPTH]) {
@@ -153,8 +185,8 @@ impl PosInfo {
         branch = update(&branch);
 
         let mask_vec = mask_to_vec!(branch.mask);
-        self.pos_on_edge -= mask_vec * branch.span;
-        self.local_pos += mask_vec * branch.span;
+        self.pos_on_edge += mask_vec * branch.span;
+        self.local_pos -= mask_vec * branch.span;
 
         branch_data[self.depth_idx()] = branch;
     }
@@ -178,6 +210,159 @@ impl PosInfo {
     }
 }
 
+/// Check if local pos is inside of a cube with span. A point on a face
+/// belongs to the cube the ray is moving into, so steps are never repeated.
+fn inside_towards(local_pos: Vec4, span: f32, dir: Vec4) -> bool {
+    (0..3).all(|axis| {
+        if dir[axis] < 0.0 {
+            0.0 < local_pos[axis] && local_pos[axis] <= span
+        } else {
+            0.0 <= local_pos[axis] && local_pos[axis] < span
+        }
+    })
+}
+
+/// Child mask of local pos, like in move into child, but with the same
+/// face rule as in inside towards.
+fn child_mask_towards(local_pos: Vec4, span: f32, dir: Vec4) -> u32 {
+    (0..3).fold(0, |mask, axis| {
+        let upper = if dir[axis] < 0.0 {
+            local_pos[axis] > span
+        } else {
+            local_pos[axis] >= span
+        };
+
+        mask | ((upper as u32) << axis)
+    })
+}
+
+impl Octree {
+    /// CPU version of the traversal in the fragment shader, the root is expected
+    /// at the origin. Empty nodes are skipped in one step, the walk moves up
+    /// until the new pos is inside of the node and then down to the next node.
+    pub fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<Hit> {
+        let dir = Vec4::new(ray.dir.x, ray.dir.y, ray.dir.z, 0.0);
+        if dir.norm() == 0.0 {
+            return None;
+        }
+        let dir = dir.normalize();
+        let origin = Vec4::new(ray.origin.x, ray.origin.y, ray.origin.z, 0.0);
+
+        // Move origin onto root, if it is outside
+        let mut dist = 0.0f32;
+        let mut exit_dist = f32::MAX;
+        let mut normal = Vec4::zeros();
+        let mut entry_axis = None;
+
+        for axis in 0..3 {
+            if dir[axis] == 0.0 {
+                if origin[axis] < 0.0 || origin[axis] > self.root_span {
+                    return None;
+                }
+                continue;
+            }
+
+            let near = if dir[axis] > 0.0 { 0.0 } else { self.root_span };
+            let near_dist = (near - origin[axis]) / dir[axis];
+            let far_dist = (self.root_span - near - origin[axis]) / dir[axis];
+
+            if near_dist > dist {
+                dist = near_dist;
+                entry_axis = Some((axis, near));
+            }
+            exit_dist = exit_dist.min(far_dist);
+        }
+
+        if dist > exit_dist || dist > max_dist {
+            return None;
+        }
+
+        let mut local_pos = origin + dir * dist;
+        if let Some((axis, near)) = entry_axis {
+            local_pos[axis] = near;
+            normal[axis] = -dir[axis].signum();
+        }
+
+        let (mut branch_data, mut pos_info) = self.get_new_root_info(Vec4::zeros());
+        pos_info.local_pos = local_pos;
+
+        loop {
+            // Move up, until pos is inside of the node
+            while !inside_towards(
+                pos_info.local_pos,
+                pos_info.branch(&branch_data).span,
+                dir,
+            ) {
+                if pos_info.depth == 0 {
+                    return None;
+                }
+                pos_info.move_up(&branch_data);
+            }
+
+            // Move down, until node is not subdivided
+            loop {
+                let branch = pos_info.branch(&branch_data);
+
+                if branch.node.is_leaf() {
+                    return Some(Hit {
+                        pos: pos_info.pos_on_edge + pos_info.local_pos,
+                        normal,
+                        dist,
+                        depth: pos_info.depth,
+                        idx: branch.idx,
+                    });
+                }
+
+                if !branch.node.is_subdiv() || pos_info.depth_idx() == MAX_DEPTH - 1 {
+                    break;
+                }
+
+                let local_pos = pos_info.local_pos;
+                pos_info.update_branch_to_child(&mut branch_data, |branch| {
+                    let mut branch = *branch;
+                    branch.mask = child_mask_towards(local_pos, branch.span, dir);
+                    (branch.idx, branch.node) = branch.get_child(&self.octant_data, branch.mask);
+
+                    branch
+                });
+            }
+
+            // Node is empty, move to the closest face in ray dir
+            let span = pos_info.branch(&branch_data).span;
+            let mut step = f32::MAX;
+            let mut step_axis = 0;
+            let mut face = 0.0;
+
+            for axis in 0..3 {
+                if dir[axis] == 0.0 {
+                    continue;
+                }
+
+                let axis_face = if dir[axis] > 0.0 { span } else { 0.0 };
+                let axis_step = (axis_face - pos_info.local_pos[axis]) / dir[axis];
+
+                if axis_step < step {
+                    step = axis_step;
+                    step_axis = axis;
+                    face = axis_face;
+                }
+            }
+
+            let step = step.max(0.0);
+            dist += step;
+            if dist > max_dist {
+                return None;
+            }
+
+            pos_info.local_pos += dir * step;
+            pos_info.local_pos[step_axis] = face;
+
+            normal = Vec4::zeros();
+            normal[step_axis] = -dir[step_axis].signum();
+        }
+    }
+}
+
 impl Default for Ray {
     fn default() -> Self {
         Self {
@@ -211,3 +396,345 @@ impl Default for PosInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{BranchInfo, PosInfo, Ray};
+    use crate::tree::{
+        material::Material,
+        octant::Octant,
+        octree::{Octree, GRID_LEN, MAX_DEPTH},
+    };
+
+    fn ray(origin: [f32; 3], dir: [f32; 3]) -> Ray {
+        Ray {
+            origin: Vec4::new(origin[0], origin[1], origin[2], 0.0),
+            dir: Vec4::new(dir[0], dir[1], dir[2], 0.0),
+        }
+    }
+
+    fn sphere(x: u32, y: u32, z: u32) -> bool {
+        let center = GRID_LEN as f32 * 0.5;
+        let pos = Vec4::new(x as f32, y as f32, z as f32, 0.0).add_scalar(0.5 - center);
+
+        pos.xyz().norm() < center * 0.6 || ((x ^ z) & 31) == 0 && y < 20
+    }
+
+    /// Voxel by voxel walk over the dense grid (Amanatides and Woo),
+    /// returns the first filled voxel and the dist to it.
+    fn reference_hit(
+        leaf_span: f32,
+        origin: Vec4,
+        dir: Vec4,
+        filled: impl Fn(u32, u32, u32) -> bool,
+    ) -> Option<([u32; 3], f32)> {
+        let dir = dir.normalize();
+        let root_span = leaf_span * GRID_LEN as f32;
+
+        // Entry into root
+        let mut dist = 0.0f32;
+        let mut exit_dist = f32::MAX;
+        for axis in 0..3 {
+            let near = if dir[axis] > 0.0 { 0.0 } else { root_span };
+            dist = dist.max((near - origin[axis]) / dir[axis]);
+            exit_dist = exit_dist.min((root_span - near - origin[axis]) / dir[axis]);
+        }
+        if dist > exit_dist {
+            return None;
+        }
+
+        let entry = origin + dir * dist;
+        let mut voxel = [0i32; 3];
+        let mut next = [0.0f32; 3];
+        let mut delta = [0.0f32; 3];
+        let mut step = [0i32; 3];
+
+        for axis in 0..3 {
+            let pos = entry[axis] / leaf_span;
+            voxel[axis] = (pos.floor() as i32).clamp(0, GRID_LEN as i32 - 1);
+            step[axis] = if dir[axis] > 0.0 { 1 } else { -1 };
+            delta[axis] = leaf_span / dir[axis].abs();
+
+            let face = (voxel[axis] + (step[axis] > 0) as i32) as f32 * leaf_span;
+            next[axis] = dist + (face - entry[axis]) / dir[axis];
+        }
+
+        loop {
+            if voxel.iter().any(|&value| value < 0 || value >= GRID_LEN as i32) {
+                return None;
+            }
+
+            let voxel_u = voxel.map(|value| value as u32);
+            if filled(voxel_u[0], voxel_u[1], voxel_u[2]) {
+                return Some((voxel_u, dist));
+            }
+
+            let axis = (0..3)
+                .min_by(|&a, &b| next[a].partial_cmp(&next[b]).unwrap())
+                .unwrap();
+            dist = next[axis];
+            next[axis] += delta[axis];
+            voxel[axis] += step[axis];
+        }
+    }
+
+    /// Walk down to the deepest node at pos, like node at pos,
+    /// but keep the branch data.
+    fn locate(octree: &Octree, pos: Vec4) -> ([BranchInfo; MAX_DEPTH], PosInfo) {
+        let (mut branch_data, mut pos_info) = octree.get_new_root_info(pos);
+
+        while pos_info.depth_idx() < MAX_DEPTH - 1
+            && pos_info.branch(&branch_data).node.is_subdiv()
+        {
+            pos_info.move_into_child(&mut branch_data, |mut branch| {
+                (branch.idx, branch.node) = branch.get_child(&octree.octant_data, branch.mask);
+                branch
+            });
+        }
+
+        (branch_data, pos_info)
+    }
+
+    const DIR_LIST: [(u32, bool, [i32; 3]); 6] = [
+        (1, false, [1, 0, 0]),
+        (1, true, [-1, 0, 0]),
+        (2, false, [0, 1, 0]),
+        (2, true, [0, -1, 0]),
+        (4, false, [0, 0, 1]),
+        (4, true, [0, 0, -1]),
+    ];
+
+    fn voxel_pos(octree: &Octree, voxel: [i32; 3]) -> Vec4 {
+        Vec4::new(voxel[0] as f32, voxel[1] as f32, voxel[2] as f32, 0.0) * octree.leaf_span()
+    }
+
+    #[test]
+    fn neighbor_all_directions() {
+        let octree = Octree::from_dense([8; 3], |_, _, _| Some(Material::default()));
+        let (branch_data, pos_info) = locate(&octree, voxel_pos(&octree, [3, 4, 3]));
+        assert_eq!(pos_info.depth_idx(), MAX_DEPTH - 1);
+
+        for (dir_mask, inv, offset) in DIR_LIST {
+            let mut neighbor_data = branch_data;
+            let neighbor = pos_info
+                .neighbor(&octree.octant_data, &mut neighbor_data, dir_mask, inv)
+                .expect("ERR_NO_NEIGHBOR");
+
+            let expected = voxel_pos(&octree, [3 + offset[0], 4 + offset[1], 3 + offset[2]]);
+            let (expected_data, expected_info) = locate(&octree, expected);
+
+            assert_eq!(neighbor.depth, pos_info.depth);
+            assert_eq!(neighbor.pos_on_edge, expected);
+            assert_eq!(neighbor.local_pos, pos_info.local_pos);
+            assert_eq!(
+                neighbor.branch(&neighbor_data).idx,
+                expected_info.branch(&expected_data).idx
+            );
+            assert!(neighbor.branch(&neighbor_data).node.is_leaf());
+        }
+    }
+
+    #[test]
+    fn neighbor_outside_of_root() {
+        let octree = Octree::from_dense([GRID_LEN; 3], |x, y, z| {
+            (x == 0 || y == GRID_LEN - 1 || z == 0).then(Material::default)
+        });
+
+        let (branch_data, pos_info) = locate(&octree, voxel_pos(&octree, [0, 127, 0]));
+
+        for (dir_mask, inv, _) in DIR_LIST {
+            let mut neighbor_data = branch_data;
+            let neighbor = pos_info.neighbor(&octree.octant_data, &mut neighbor_data, dir_mask, inv);
+
+            // Untouched branch data, if there is no neighbor
+            if neighbor.is_none() {
+                assert!((0..MAX_DEPTH).all(|depth| neighbor_data[depth].idx == branch_data[depth].idx));
+            }
+            assert_eq!(neighbor.is_none(), (dir_mask == 2) != inv);
+        }
+    }
+
+    #[test]
+    fn neighbor_coarser_node() {
+        let mut octree = Octree::default();
+        octree.insert_node(voxel_pos(&octree, [63, 10, 10]), Material::default());
+
+        // Right of the voxel is the empty upper half of the root
+        let (mut branch_data, pos_info) = locate(&octree, voxel_pos(&octree, [63, 10, 10]));
+        let neighbor = pos_info
+            .neighbor(&octree.octant_data, &mut branch_data, 1, false)
+            .expect("ERR_NO_NEIGHBOR");
+
+        assert_eq!(neighbor.depth, 1);
+        assert_eq!(neighbor.pos_on_edge, Vec4::new(128.0, 0.0, 0.0, 0.0));
+        assert!(!neighbor.branch(&branch_data).node.is_subdiv());
+    }
+
+    #[test]
+    fn neighbor_matches_lookup() {
+        let octree = Octree::from_dense([GRID_LEN; 3], |x, y, z| {
+            sphere(x, y, z).then(Material::default)
+        });
+        let mut rng = StdRng::seed_from_u64(13);
+
+        for _ in 0..2000 {
+            let voxel = [0; 3].map(|_: i32| rng.gen_range(0..GRID_LEN as i32));
+            let (branch_data, pos_info) = locate(&octree, voxel_pos(&octree, voxel));
+
+            // Neighbor is one span of the start node away
+            let span = pos_info.branch(&branch_data).span;
+
+            for (dir_mask, inv, offset) in DIR_LIST {
+                let offset = Vec4::new(offset[0] as f32, offset[1] as f32, offset[2] as f32, 0.0);
+                let target = pos_info.pos_on_edge + offset * span;
+                let mut neighbor_data = branch_data;
+                let neighbor =
+                    pos_info.neighbor(&octree.octant_data, &mut neighbor_data, dir_mask, inv);
+
+                if target.iter().any(|&value| value < 0.0 || value >= octree.root_span) {
+                    assert!(neighbor.is_none());
+                    continue;
+                }
+
+                let neighbor = neighbor.expect("ERR_NO_NEIGHBOR");
+                let (expected_data, expected_info) = locate(&octree, target);
+                let depth = neighbor.depth.min(expected_info.depth) as usize;
+
+                // Same node, as long as the lookup is not deeper than the start
+                assert!(neighbor.depth <= pos_info.depth);
+                assert_eq!(neighbor_data[depth].idx, expected_data[depth].idx);
+                if expected_info.depth <= pos_info.depth {
+                    assert_eq!(neighbor.depth, expected_info.depth);
+                    assert_eq!(neighbor.pos_on_edge, expected_info.pos_on_edge);
+                }
+
+                // Local pos is moved along with the node
+                assert_eq!(
+                    neighbor.pos_on_edge + neighbor.local_pos,
+                    pos_info.pos_on_edge + pos_info.local_pos + offset * span
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn move_into_child_tracks_corner() {
+        let octree = Octree::default();
+        // X is on the edge between both halves of the root
+        let pos = Vec4::new(128.0, 3.0, 200.5, 0.0);
+        let (mut branch_data, mut pos_info) = octree.get_new_root_info(pos);
+
+        for _ in 1..MAX_DEPTH {
+            pos_info.move_into_child(&mut branch_data, |branch| branch);
+            let span = pos_info.branch(&branch_data).span;
+
+            // Node corner is pos rounded down to the span, local pos is inside of the node
+            assert_eq!(pos_info.pos_on_edge, (pos / span).map(f32::floor) * span);
+            assert_eq!(pos_info.pos_on_edge + pos_info.local_pos, pos);
+        }
+    }
+
+    #[test]
+    fn cast_ray_empty() {
+        let octree = Octree::default();
+
+        assert_eq!(octree.cast_ray(&ray([1.0, 2.0, 3.0], [1.0, 1.0, 1.0]), 1000.0), None);
+        assert_eq!(octree.cast_ray(&ray([-10.0, 2.0, 3.0], [1.0, 0.0, 0.0]), 1000.0), None);
+    }
+
+    #[test]
+    fn cast_ray_single_voxel() {
+        let mut octree = Octree::default();
+        let leaf_span = octree.leaf_span();
+        let material = Material::from_color(Vec4::new(1.0, 0.0, 0.0, 1.0));
+        octree.insert_node(Vec4::new(10.0, 20.0, 30.0, 0.0) * leaf_span, material);
+
+        let center = [21.0 * leaf_span / 2.0, 41.0 * leaf_span / 2.0, 61.0 * leaf_span / 2.0];
+
+        // From outside of the root along +x
+        let hit = octree
+            .cast_ray(&ray([-5.0, center[1], center[2]], [1.0, 0.0, 0.0]), 1000.0)
+            .expect("ERR_NO_HIT");
+        assert_eq!(hit.pos.x, 10.0 * leaf_span);
+        assert_eq!(hit.normal, Vec4::new(-1.0, 0.0, 0.0, 0.0));
+        assert_eq!(hit.dist, 5.0 + 10.0 * leaf_span);
+        assert_eq!(hit.depth, 7);
+        assert_eq!(octree.material_data[hit.idx as usize], material);
+
+        // From inside of the root along -y
+        let hit = octree
+            .cast_ray(&ray([center[0], 200.0, center[2]], [0.0, -1.0, 0.0]), 1000.0)
+            .expect("ERR_NO_HIT");
+        assert_eq!(hit.pos.y, 21.0 * leaf_span);
+        assert_eq!(hit.normal, Vec4::new(0.0, 1.0, 0.0, 0.0));
+
+        // Too short and passing by
+        assert_eq!(
+            octree.cast_ray(&ray([-5.0, center[1], center[2]], [1.0, 0.0, 0.0]), 20.0),
+            None
+        );
+        assert_eq!(
+            octree.cast_ray(&ray([-5.0, center[1] + leaf_span, center[2]], [1.0, 0.0, 0.0]), 1000.0),
+            None
+        );
+
+        // Starting inside of the leaf
+        let hit = octree
+            .cast_ray(&ray(center, [0.3, -0.2, 1.0]), 1000.0)
+            .expect("ERR_NO_HIT");
+        assert_eq!(hit.dist, 0.0);
+        assert_eq!(hit.normal, Vec4::zeros());
+    }
+
+    #[test]
+    fn cast_ray_matches_reference() {
+        let octree = Octree::from_dense([GRID_LEN; 3], |x, y, z| {
+            sphere(x, y, z).then(Material::default)
+        });
+        let leaf_span = octree.leaf_span();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut hit_count = 0;
+
+        for _ in 0..2000 {
+            let origin = Vec4::new(
+                rng.gen_range(-64.0..320.0),
+                rng.gen_range(-64.0..320.0),
+                rng.gen_range(-64.0..320.0),
+                0.0,
+            );
+            let target = Vec4::new(
+                rng.gen_range(0.0..256.0),
+                rng.gen_range(0.0..256.0),
+                rng.gen_range(0.0..256.0),
+                0.0,
+            );
+            let dir = target - origin;
+
+            let hit = octree.cast_ray(&Ray { origin, dir }, f32::MAX);
+            let reference = reference_hit(leaf_span, origin, dir, sphere);
+
+            match (hit, reference) {
+                (None, None) => (),
+                (Some(hit), Some((voxel, dist))) => {
+                    hit_count += 1;
+                    assert!((hit.dist - dist).abs() < 1e-2, "{:?} {:?}", hit, dist);
+
+                    let cell = (hit.pos - hit.normal * leaf_span * 0.5) / leaf_span;
+                    let hit_voxel = [0, 1, 2].map(|axis| {
+                        (cell[axis].floor() as u32).min(GRID_LEN - 1)
+                    });
+                    assert!(sphere(hit_voxel[0], hit_voxel[1], hit_voxel[2]));
+                    if hit.normal != Vec4::zeros() {
+                        assert_eq!(hit_voxel, voxel);
+                    }
+                }
+                (hit, reference) => panic!("ERROR_MISMATCH -> {:?} {:?}", hit, reference),
+            }
+        }
+
+        assert!(hit_count > 500);
+    }
+}
@@ -0,0 +1,632 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{self, ErrorKind, Write},
+    path::Path,
+};
+
+use nalgebra_glm::Vec4;
+
+use super::{
+    material::Material,
+    octant::Octant,
+    octree::{Octree, GRID_LEN},
+    world::World,
+};
+
+// Version written by MagicaVoxel 0.99
+const VOX_VERSION: i32 = 150;
+
+// Material of every voxel by pos in octree space
+type VoxelMap = HashMap<[u32; 3], Material>;
+
+/// Single model from SIZE and XYZI chunk.
+/// Voxel are (x, y, z, palette idx) in MagicaVoxel space, z is up.
+#[derive(Clone, Debug)]
+pub struct VoxModel {
+    pub size: [i32; 3],
+    pub voxel_list: Vec<[u8; 4]>,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug)]
+enum VoxNode {
+    TRANSFORM { child: i32, translation: [i32; 3] },
+    GROUP { child_list: Vec<i32> },
+    SHAPE { model_list: Vec<i32> },
+}
+
+struct VoxReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn invalid_data(msg: &str) -> Box<dyn Error> {
+    Box::new(io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+impl<'a> VoxReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid_data("ERR_VOX_TRUNCATED"))?;
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, Box<dyn Error>> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn len(&mut self) -> Result<usize, Box<dyn Error>> {
+        usize::try_from(self.i32()?).map_err(|_| invalid_data("ERR_VOX_NEGATIVE_LEN"))
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let len = self.len()?;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        let len = self.len()?;
+        (0..len)
+            .map(|_| Ok((self.string()?, self.string()?)))
+            .collect()
+    }
+}
+
+/// Default MagicaVoxel palette, used when there is no RGBA chunk.
+/// Idx 1 - 215 is a color cube, the rest are red, green, blue and gray ramps.
+pub fn default_palette() -> [[u8; 4]; 256] {
+    let mut palette = [[0u8; 4]; 256];
+    let mut idx = 1;
+
+    for r in (0..6).rev() {
+        for g in (0..6).rev() {
+            for b in (0..6).rev() {
+                if idx < 216 {
+                    palette[idx] = [r * 0x33, g * 0x33, b * 0x33, 255];
+                    idx += 1;
+                }
+            }
+        }
+    }
+
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    for channel in 0..4 {
+        for value in ramp {
+            palette[idx] = match channel {
+                0 => [value, 0, 0, 255],
+                1 => [0, value, 0, 255],
+                2 => [0, 0, value, 255],
+                _ => [value, value, value, 255],
+            };
+            idx += 1;
+        }
+    }
+
+    palette
+}
+
+pub fn load_vox(path: &Path) -> Result<Octree, Box<dyn Error>> {
+    log::info!("Loading vox file {} ...", path.display());
+    parse_vox(&fs::read(path)?)
+}
+
+/// Parse vox file, place all models with the translation of the
+/// scene graph (rotation is ignored) and convert it into an octree.
+/// MagicaVoxel z is the octree y. Voxel outside of the root are skipped,
+/// see parse vox world for bigger scenes.
+pub fn parse_vox(data: &[u8]) -> Result<Octree, Box<dyn Error>> {
+    let (dims, voxel_map) = parse_vox_voxel(data)?;
+
+    if dims.iter().any(|&dim| dim > GRID_LEN) {
+        log::info!(
+            "Vox scene [ {} x {} x {} ] is bigger than root, cutting at {} ...",
+            dims[0],
+            dims[1],
+            dims[2],
+            GRID_LEN
+        );
+    }
+
+    Ok(Octree::from_dense(
+        dims.map(|dim| dim.min(GRID_LEN)),
+        |x, y, z| voxel_map.get(&[x, y, z]).copied(),
+    ))
+}
+
+pub fn load_vox_world(path: &Path, world: &mut World) -> Result<(), Box<dyn Error>> {
+    log::info!("Loading vox file {} into world ...", path.display());
+    parse_vox_world(&fs::read(path)?, world)
+}
+
+/// Like parse vox, but scenes bigger than one root are split over the
+/// chunks, starting at chunk (0, 0, 0).
+pub fn parse_vox_world(data: &[u8], world: &mut World) -> Result<(), Box<dyn Error>> {
+    let (dims, voxel_map) = parse_vox_voxel(data)?;
+    world.insert_dense(dims, |x, y, z| voxel_map.get(&[x, y, z]).copied());
+
+    Ok(())
+}
+
+/// Dims and material of every voxel in octree space, the smallest
+/// voxel is moved to the origin.
+fn parse_vox_voxel(data: &[u8]) -> Result<([u32; 3], VoxelMap), Box<dyn Error>> {
+    let mut reader = VoxReader { data, pos: 0 };
+
+    if reader.bytes(4)? != b"VOX " {
+        return Err(invalid_data("ERR_VOX_MAGIC"));
+    }
+    let version = reader.i32()?;
+    log::info!("Vox version is [ {} ]...", version);
+
+    if reader.bytes(4)? != b"MAIN" {
+        return Err(invalid_data("ERR_VOX_NO_MAIN"));
+    }
+    let main_len = reader.len()?;
+    let main_child_len = reader.len()?;
+    reader.bytes(main_len)?;
+
+    let end = reader.pos + main_child_len;
+    if end > data.len() {
+        return Err(invalid_data("ERR_VOX_TRUNCATED"));
+    }
+
+    let mut model_list: Vec<VoxModel> = vec![];
+    let mut palette = default_palette();
+    let mut node_list: HashMap<i32, VoxNode> = HashMap::new();
+
+    while reader.pos < end {
+        let id = reader.bytes(4)?;
+        let content_len = reader.len()?;
+        let child_len = reader.len()?;
+
+        let mut chunk = VoxReader {
+            data: reader.bytes(content_len)?,
+            pos: 0,
+        };
+        reader.bytes(child_len)?;
+
+        match id {
+            b"SIZE" => model_list.push(VoxModel {
+                size: [chunk.i32()?, chunk.i32()?, chunk.i32()?],
+                voxel_list: vec![],
+            }),
+            b"XYZI" => {
+                let model = model_list
+                    .last_mut()
+                    .ok_or_else(|| invalid_data("ERR_VOX_XYZI_WITHOUT_SIZE"))?;
+                let count = chunk.len()?;
+
+                model.voxel_list = chunk
+                    .bytes(count * 4)?
+                    .chunks_exact(4)
+                    .map(|voxel| [voxel[0], voxel[1], voxel[2], voxel[3]])
+                    .collect();
+            }
+            b"RGBA" => {
+                // Color idx 0 - 254 of chunk are palette idx 1 - 255
+                for idx in 0..255 {
+                    let color = chunk.bytes(4)?;
+                    palette[idx + 1] = [color[0], color[1], color[2], color[3]];
+                }
+            }
+            b"nTRN" => {
+                let node_id = chunk.i32()?;
+                chunk.dict()?;
+                let child = chunk.i32()?;
+                // Reserved id and layer id
+                chunk.i32()?;
+                chunk.i32()?;
+
+                let frame_count = chunk.len()?;
+                let mut translation = [0; 3];
+                for frame in 0..frame_count {
+                    let attrib = chunk.dict()?;
+                    if frame > 0 {
+                        continue;
+                    }
+
+                    if let Some(value) = attrib.get("_t") {
+                        let value_list: Vec<i32> = value
+                            .split_whitespace()
+                            .filter_map(|value| value.parse().ok())
+                            .collect();
+
+                        if value_list.len() == 3 {
+                            translation = [value_list[0], value_list[1], value_list[2]];
+                        }
+                    }
+                }
+
+                node_list.insert(node_id, VoxNode::TRANSFORM { child, translation });
+            }
+            b"nGRP" => {
+                let node_id = chunk.i32()?;
+                chunk.dict()?;
+                let child_count = chunk.len()?;
+                let child_list = (0..child_count)
+                    .map(|_| chunk.i32())
+                    .collect::<Result<_, _>>()?;
+
+                node_list.insert(node_id, VoxNode::GROUP { child_list });
+            }
+            b"nSHP" => {
+                let node_id = chunk.i32()?;
+                chunk.dict()?;
+                let model_count = chunk.len()?;
+
+                let mut model_idx_list = vec![];
+                for _ in 0..model_count {
+                    model_idx_list.push(chunk.i32()?);
+                    chunk.dict()?;
+                }
+
+                node_list.insert(
+                    node_id,
+                    VoxNode::SHAPE {
+                        model_list: model_idx_list,
+                    },
+                );
+            }
+            // PACK, MATL, LAYR, rOBJ, ... are not needed
+            _ => (),
+        }
+    }
+
+    // Get offset of each placed model
+    let mut placement_list: Vec<(usize, [i32; 3])> = vec![];
+    if node_list.contains_key(&0) {
+        collect_placement(&node_list, &model_list, 0, [0; 3], &mut placement_list, 0)?;
+    } else {
+        // No scene graph, place models next to each other
+        let mut offset = 0;
+        for (model_idx, model) in model_list.iter().enumerate() {
+            placement_list.push((model_idx, [offset, 0, 0]));
+            offset += model.size[0] + 1;
+        }
+    }
+
+    log::info!(
+        "Vox file has {} models and {} placements ...",
+        model_list.len(),
+        placement_list.len()
+    );
+
+    // Collect voxel in octree space
+    let mut voxel_map: HashMap<[i32; 3], Material> = HashMap::new();
+    for (model_idx, offset) in placement_list {
+        for voxel in &model_list[model_idx].voxel_list {
+            let color = palette[voxel[3] as usize];
+            let albedo = Vec4::new(
+                color[0] as f32 / 255.0,
+                color[1] as f32 / 255.0,
+                color[2] as f32 / 255.0,
+                color[3] as f32 / 255.0,
+            );
+            let material = Material::new(albedo, Vec4::zeros(), 1.0, voxel[3] as u32);
+
+            let pos = [
+                offset[0] + voxel[0] as i32,
+                offset[2] + voxel[2] as i32,
+                offset[1] + voxel[1] as i32,
+            ];
+            voxel_map.insert(pos, material);
+        }
+    }
+
+    // Move smallest voxel to the root origin
+    let mut min = [i32::MAX; 3];
+    voxel_map.keys().for_each(|pos| {
+        (0..3).for_each(|axis| min[axis] = min[axis].min(pos[axis]));
+    });
+
+    let mut dims = [0u32; 3];
+    let voxel_map: VoxelMap = voxel_map
+        .into_iter()
+        .map(|(pos, material)| {
+            let pos = [
+                (pos[0] - min[0]) as u32,
+                (pos[1] - min[1]) as u32,
+                (pos[2] - min[2]) as u32,
+            ];
+            (0..3).for_each(|axis| dims[axis] = dims[axis].max(pos[axis] + 1));
+
+            (pos, material)
+        })
+        .collect();
+
+    Ok((dims, voxel_map))
+}
+
+/// Walk scene graph and add every model of a shape node with the summed
+/// up translation. Models are centered at the translation.
+fn collect_placement(
+    node_list: &HashMap<i32, VoxNode>,
+    model_list: &Vec<VoxModel>,
+    node_id: i32,
+    translation: [i32; 3],
+    placement_list: &mut Vec<(usize, [i32; 3])>,
+    depth: usize,
+) -> Result<(), Box<dyn Error>> {
+    if depth > 64 {
+        return Err(invalid_data("ERR_VOX_NODE_CYCLE"));
+    }
+
+    match node_list.get(&node_id) {
+        Some(VoxNode::TRANSFORM { child, translation: offset }) => collect_placement(
+            node_list,
+            model_list,
+            *child,
+            [
+                translation[0] + offset[0],
+                translation[1] + offset[1],
+                translation[2] + offset[2],
+            ],
+            placement_list,
+            depth + 1,
+        ),
+        Some(VoxNode::GROUP { child_list }) => child_list.iter().try_for_each(|child| {
+            collect_placement(node_list, model_list, *child, translation, placement_list, depth + 1)
+        }),
+        Some(VoxNode::SHAPE { model_list: model_idx_list }) => {
+            for &model_idx in model_idx_list {
+                let model = model_list
+                    .get(model_idx as usize)
+                    .ok_or_else(|| invalid_data("ERR_VOX_MODEL_IDX"))?;
+
+                placement_list.push((
+                    model_idx as usize,
+                    [
+                        translation[0] - model.size[0] / 2,
+                        translation[1] - model.size[1] / 2,
+                        translation[2] - model.size[2] / 2,
+                    ],
+                ));
+            }
+
+            Ok(())
+        }
+        None => Err(invalid_data("ERR_VOX_NODE_ID")),
+    }
+}
+
+pub fn save_vox(octree: &Octree, path: &Path) -> Result<(), Box<dyn Error>> {
+    log::info!("Saving vox file {} ...", path.display());
+    fs::write(path, write_vox(octree)?)?;
+
+    Ok(())
+}
+
+/// Write the leaves as a single model without scene graph, one voxel per
+/// leaf span. Colors are put into the palette in order of appearance,
+/// once it is full the closest palette color is used. Only the color
+/// survives, emission and roughness are not part of the format.
+pub fn write_vox(octree: &Octree) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut voxel_list: Vec<([u32; 3], Material)> = vec![];
+    collect_voxel(octree, 0, [0; 3], GRID_LEN, &mut voxel_list);
+
+    // Palette idx 0 is empty
+    let mut palette: Vec<[u8; 4]> = vec![[0; 4]];
+    let mut palette_map: HashMap<[u8; 4], u8> = HashMap::new();
+
+    let mut size = [1u32; 3];
+    let mut xyzi = (voxel_list.len() as i32).to_le_bytes().to_vec();
+    for (pos, material) in &voxel_list {
+        let color = material
+            .albedo
+            .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8);
+        let color = [color.x, color.y, color.z, color.w];
+
+        let palette_idx = *palette_map.entry(color).or_insert_with(|| {
+            if palette.len() < 256 {
+                palette.push(color);
+                return (palette.len() - 1) as u8;
+            }
+
+            (1..palette.len())
+                .min_by_key(|&idx| {
+                    (0..4)
+                        .map(|channel| {
+                            (palette[idx][channel] as i32 - color[channel] as i32).pow(2)
+                        })
+                        .sum::<i32>()
+                })
+                .unwrap_or(1) as u8
+        });
+
+        // MagicaVoxel z is the octree y
+        let voxel = [pos[0], pos[2], pos[1]];
+        (0..3).for_each(|axis| size[axis] = size[axis].max(voxel[axis] + 1));
+        xyzi.extend_from_slice(&[voxel[0] as u8, voxel[1] as u8, voxel[2] as u8, palette_idx]);
+    }
+
+    if palette_map.len() >= palette.len() {
+        log::info!(
+            "Octree has {} colors, mapping them to the closest of {} ...",
+            palette_map.len(),
+            palette.len() - 1
+        );
+    }
+
+    let size: Vec<u8> = size
+        .iter()
+        .flat_map(|&len| (len as i32).to_le_bytes())
+        .collect();
+    // Color idx 0 - 254 of chunk are palette idx 1 - 255
+    palette.resize(257, [0; 4]);
+    let rgba: Vec<u8> = palette[1..].concat();
+
+    let mut child_data = vec![];
+    for (id, content) in [(b"SIZE", size), (b"XYZI", xyzi), (b"RGBA", rgba)] {
+        write_chunk(&mut child_data, id, &content, 0)?;
+    }
+
+    let mut data = vec![];
+    data.write_all(b"VOX ")?;
+    data.write_all(&VOX_VERSION.to_le_bytes())?;
+    write_chunk(&mut data, b"MAIN", &[], child_data.len())?;
+    data.write_all(&child_data)?;
+
+    Ok(data)
+}
+
+fn write_chunk(
+    data: &mut Vec<u8>,
+    id: &[u8; 4],
+    content: &[u8],
+    child_len: usize,
+) -> Result<(), Box<dyn Error>> {
+    data.write_all(id)?;
+    data.write_all(&i32::try_from(content.len())?.to_le_bytes())?;
+    data.write_all(&i32::try_from(child_len)?.to_le_bytes())?;
+    data.write_all(content)?;
+
+    Ok(())
+}
+
+/// Add every voxel of the cube at origin (in voxel) with len voxel along
+/// each axis, bigger leaves are split into single voxel.
+fn collect_voxel(
+    octree: &Octree,
+    idx: usize,
+    origin: [u32; 3],
+    len: u32,
+    voxel_list: &mut Vec<([u32; 3], Material)>,
+) {
+    let node = octree.octant_data[idx];
+
+    if node.is_subdiv() {
+        let first_child_idx = node.get_first_child_idx() as usize;
+        let child_len = len / 2;
+
+        for mask in (0..8u32).filter(|&mask| node.check_child_filled(mask)) {
+            let child_origin = [
+                origin[0] + (mask & 1) * child_len,
+                origin[1] + ((mask >> 1) & 1) * child_len,
+                origin[2] + ((mask >> 2) & 1) * child_len,
+            ];
+            collect_voxel(
+                octree,
+                first_child_idx + mask as usize,
+                child_origin,
+                child_len,
+                voxel_list,
+            );
+        }
+    } else if node.is_leaf() {
+        let material = octree.material_data[idx];
+
+        for x in 0..len {
+            for y in 0..len {
+                for z in 0..len {
+                    voxel_list.push(([origin[0] + x, origin[1] + y, origin[2] + z], material));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec4;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{collect_voxel, parse_vox, parse_vox_world, write_chunk, write_vox};
+    use crate::tree::{
+        material::Material,
+        octree::{Octree, GRID_LEN},
+        world::World,
+    };
+
+    #[test]
+    fn write_round_trip() {
+        let mut rng = StdRng::seed_from_u64(22);
+        let mut octree = Octree::default();
+        let leaf_span = octree.leaf_span();
+
+        // Corner voxel keeps the min at the origin, parse moves it there
+        octree.insert_node(Vec4::zeros(), Material::default());
+        for _ in 0..300 {
+            let pos = Vec4::new(
+                rng.gen_range(0..128) as f32,
+                rng.gen_range(0..128) as f32,
+                rng.gen_range(0..128) as f32,
+                0.0,
+            );
+            let color = Vec4::new(
+                rng.gen_range(0..4) as f32 / 3.0,
+                rng.gen_range(0..4) as f32 / 3.0,
+                0.2,
+                1.0,
+            );
+            octree.insert_node(pos * leaf_span, Material::from_color(color));
+        }
+
+        let loaded = parse_vox(&write_vox(&octree).unwrap()).unwrap();
+
+        let mut voxel_list = vec![];
+        let mut loaded_list = vec![];
+        collect_voxel(&octree, 0, [0; 3], GRID_LEN, &mut voxel_list);
+        collect_voxel(&loaded, 0, [0; 3], GRID_LEN, &mut loaded_list);
+        voxel_list.sort_by_key(|(pos, _)| *pos);
+        loaded_list.sort_by_key(|(pos, _)| *pos);
+
+        assert_eq!(voxel_list.len(), loaded_list.len());
+        for ((pos, material), (loaded_pos, loaded_material)) in voxel_list.iter().zip(&loaded_list)
+        {
+            assert_eq!(pos, loaded_pos);
+            assert!((material.albedo - loaded_material.albedo).abs().max() < 1.0 / 255.0);
+        }
+    }
+
+    #[test]
+    fn big_scene_over_chunks() {
+        let mut child_data = vec![];
+        let size: Vec<u8> = [200i32, 1, 1]
+            .iter()
+            .flat_map(|len| len.to_le_bytes())
+            .collect();
+        let xyzi: Vec<u8> = [2i32.to_le_bytes(), [0, 0, 0, 1], [199, 0, 0, 1]].concat();
+        write_chunk(&mut child_data, b"SIZE", &size, 0).unwrap();
+        write_chunk(&mut child_data, b"XYZI", &xyzi, 0).unwrap();
+
+        let mut data = b"VOX ".to_vec();
+        data.extend_from_slice(&150i32.to_le_bytes());
+        write_chunk(&mut data, b"MAIN", &[], child_data.len()).unwrap();
+        data.extend_from_slice(&child_data);
+
+        let mut world = World::new(256.0, [1; 3], Box::new(|_| Octree::default()));
+        parse_vox_world(&data, &mut world).unwrap();
+        assert_eq!(world.chunk_map.len(), 2);
+
+        // Voxel 199 is voxel 71 of the second chunk, leaf span is 2
+        for (chunk_pos, x) in [([0, 0, 0], 0.0), ([1, 0, 0], 142.0)] {
+            let octree = &world.chunk_map[&chunk_pos];
+            let mut voxel_list = vec![];
+            collect_voxel(octree, 0, [0; 3], GRID_LEN, &mut voxel_list);
+
+            assert_eq!(voxel_list.len(), 1);
+            assert_eq!(octree.node_at_pos(Vec4::new(x, 0.0, 0.0, 0.0)).depth, 7);
+        }
+    }
+
+    #[test]
+    fn filled_root_is_split_into_voxel() {
+        let material = Material::from_color(Vec4::new(1.0, 0.0, 0.0, 1.0));
+        let data = write_vox(&Octree::filled(256.0, material)).unwrap();
+
+        // Magic, version, MAIN header and SIZE chunk, then the XYZI header
+        let int_at = |pos: usize| i32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+        assert_eq!([int_at(32), int_at(36), int_at(40)], [128; 3]);
+        assert_eq!(int_at(56), 128 * 128 * 128);
+        assert_eq!(
+            &data[data.len() - 1024..data.len() - 1020],
+            &[255, 0, 0, 255]
+        );
+    }
+}
@@ -0,0 +1,437 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use nalgebra_glm::{Vec3, Vec4};
+
+use super::{
+    material::Material,
+    octree::{Octree, MAX_DEPTH},
+};
+
+/// Single triangle of a mesh, color is the per-face color if the file has one.
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle {
+    pub vert: [Vec3; 3],
+    pub color: Option<Vec4>,
+}
+
+fn invalid_data(msg: &str) -> Box<dyn Error> {
+    Box::new(io::Error::new(ErrorKind::InvalidData, msg))
+}
+
+fn parse_float_list(value_list: &[&str]) -> Result<Vec<f32>, Box<dyn Error>> {
+    value_list
+        .iter()
+        .map(|value| value.parse::<f32>().map_err(|_| invalid_data("ERR_OBJ_FLOAT")))
+        .collect()
+}
+
+/// Read diffuse color (Kd) of every material in a mtl file.
+fn read_mtl(path: &Path) -> Result<HashMap<String, Vec4>, Box<dyn Error>> {
+    let mut color_map = HashMap::new();
+    let mut name = String::new();
+
+    for line in fs::read_to_string(path)?.lines() {
+        let token_list: Vec<&str> = line.split_whitespace().collect();
+
+        match token_list.as_slice() {
+            ["newmtl", material_name, ..] => name = material_name.to_string(),
+            ["Kd", value_list @ ..] if value_list.len() >= 3 => {
+                let color = parse_float_list(&value_list[0..3])?;
+                color_map.insert(name.clone(), Vec4::new(color[0], color[1], color[2], 1.0));
+            }
+            _ => (),
+        }
+    }
+
+    Ok(color_map)
+}
+
+/// Read Wavefront OBJ, polygons are triangulated as fan.
+/// Face color is the Kd of the active material, otherwise the average vertex color.
+pub fn read_obj(path: &Path) -> Result<Vec<Triangle>, Box<dyn Error>> {
+    log::info!("Reading obj file {} ...", path.display());
+    parse_obj(&fs::read_to_string(path)?, path)
+}
+
+/// Parse OBJ text, mtl files are looked up next to path.
+pub fn parse_obj(text: &str, path: &Path) -> Result<Vec<Triangle>, Box<dyn Error>> {
+    let mut vert_list: Vec<(Vec3, Option<Vec4>)> = vec![];
+    let mut color_map: HashMap<String, Vec4> = HashMap::new();
+    let mut material_color: Option<Vec4> = None;
+    let mut triangle_list = vec![];
+
+    for line in text.lines() {
+        let token_list: Vec<&str> = line.split_whitespace().collect();
+
+        match token_list.as_slice() {
+            ["v", value_list @ ..] if value_list.len() >= 3 => {
+                let value_list = parse_float_list(value_list)?;
+                let color = (value_list.len() >= 6)
+                    .then(|| Vec4::new(value_list[3], value_list[4], value_list[5], 1.0));
+
+                vert_list.push((Vec3::new(value_list[0], value_list[1], value_list[2]), color));
+            }
+            ["f", corner_list @ ..] if corner_list.len() >= 3 => {
+                // Corner is v, v/vt, v//vn or v/vt/vn, negative idx are relative to the end
+                let idx_list = corner_list
+                    .iter()
+                    .map(|corner| {
+                        let idx: i64 = corner
+                            .split('/')
+                            .next()
+                            .unwrap_or_default()
+                            .parse()
+                            .map_err(|_| invalid_data("ERR_OBJ_FACE_IDX"))?;
+
+                        let idx = if idx < 0 {
+                            vert_list.len() as i64 + idx
+                        } else {
+                            idx - 1
+                        };
+
+                        if idx < 0 || idx as usize >= vert_list.len() {
+                            return Err(invalid_data("ERR_OBJ_FACE_IDX"));
+                        }
+
+                        Ok(idx as usize)
+                    })
+                    .collect::<Result<Vec<usize>, Box<dyn Error>>>()?;
+
+                for corner in 1..idx_list.len() - 1 {
+                    let vert = [
+                        vert_list[idx_list[0]],
+                        vert_list[idx_list[corner]],
+                        vert_list[idx_list[corner + 1]],
+                    ];
+
+                    let vert_color = match (vert[0].1, vert[1].1, vert[2].1) {
+                        (Some(a), Some(b), Some(c)) => Some((a + b + c) / 3.0),
+                        _ => None,
+                    };
+
+                    triangle_list.push(Triangle {
+                        vert: [vert[0].0, vert[1].0, vert[2].0],
+                        color: material_color.or(vert_color),
+                    });
+                }
+            }
+            ["mtllib", name, ..] => {
+                let mtl_path = path.with_file_name(name);
+                match read_mtl(&mtl_path) {
+                    Ok(map) => color_map.extend(map),
+                    Err(error) => log::info!(
+                        "Skipping mtl file {} -> {} ...",
+                        mtl_path.display(),
+                        error
+                    ),
+                }
+            }
+            ["usemtl", name, ..] => material_color = color_map.get(*name).copied(),
+            _ => (),
+        }
+    }
+
+    log::info!("Obj file has {} triangles ...", triangle_list.len());
+
+    Ok(triangle_list)
+}
+
+/// Read binary STL, ascii STL is not supported.
+/// Face color uses the attribute word like VisCAM / SolidView,
+/// bit 15 marks a valid color and bit 0 - 14 are RGB555 with red at the top.
+pub fn read_stl(path: &Path) -> Result<Vec<Triangle>, Box<dyn Error>> {
+    log::info!("Reading stl file {} ...", path.display());
+    parse_stl(&fs::read(path)?)
+}
+
+pub fn parse_stl(data: &[u8]) -> Result<Vec<Triangle>, Box<dyn Error>> {
+    if data.len() < 84 {
+        return Err(invalid_data("ERR_STL_TRUNCATED"));
+    }
+
+    let count = u32::from_le_bytes(data[80..84].try_into()?) as usize;
+    if data.len() < 84 + count * 50 {
+        if data.starts_with(b"solid") {
+            return Err(invalid_data("ERR_STL_ASCII_NOT_SUPPORTED"));
+        }
+        return Err(invalid_data("ERR_STL_TRUNCATED"));
+    }
+
+    let float = |bytes: &[u8], pos: usize| {
+        f32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+    };
+
+    // Normal is skipped, the overlap test does not need it
+    let triangle_list: Vec<Triangle> = data[84..84 + count * 50]
+        .chunks_exact(50)
+        .map(|chunk| {
+            let vert = [12, 24, 36].map(|pos| {
+                Vec3::new(float(chunk, pos), float(chunk, pos + 4), float(chunk, pos + 8))
+            });
+
+            let attrib = u16::from_le_bytes([chunk[48], chunk[49]]);
+            let color = (attrib & 0x8000 != 0).then(|| {
+                Vec4::new(
+                    ((attrib >> 10) & 31) as f32 / 31.0,
+                    ((attrib >> 5) & 31) as f32 / 31.0,
+                    (attrib & 31) as f32 / 31.0,
+                    1.0,
+                )
+            });
+
+            Triangle { vert, color }
+        })
+        .collect();
+
+    log::info!("Stl file has {} triangles ...", triangle_list.len());
+
+    Ok(triangle_list)
+}
+
+/// Separating axis test of triangle and axis aligned box (Akenine-Moeller).
+/// Tests the 3 box normals, the triangle normal and the 9 edge cross products.
+pub fn tri_box_overlap(center: Vec3, half: Vec3, vert: &[Vec3; 3]) -> bool {
+    let vert = vert.map(|vert| vert - center);
+    let edge = [vert[1] - vert[0], vert[2] - vert[1], vert[0] - vert[2]];
+
+    let separated = |axis: Vec3| {
+        if axis.norm_squared() < f32::EPSILON {
+            return false;
+        }
+
+        let proj = vert.map(|vert| vert.dot(&axis));
+        let min = proj[0].min(proj[1]).min(proj[2]);
+        let max = proj[0].max(proj[1]).max(proj[2]);
+        let radius = half.x * axis.x.abs() + half.y * axis.y.abs() + half.z * axis.z.abs();
+
+        min > radius || max < -radius
+    };
+
+    let box_axis = [Vec3::x(), Vec3::y(), Vec3::z()];
+
+    let cross_separated = box_axis
+        .iter()
+        .any(|box_axis| edge.iter().any(|edge| separated(box_axis.cross(edge))));
+
+    !(cross_separated
+        || box_axis.iter().any(|&axis| separated(axis))
+        || separated(edge[0].cross(&edge[1])))
+}
+
+/// Voxelize mesh into a new octree with leaves at depth - 1.
+/// The mesh bounds are scaled uniformly to fill the root, every cell touched by a
+/// triangle is set. The box of the test is a bit bigger, so thin and axis aligned
+/// triangles never fall through between cells.
+pub fn voxelize(triangle_list: &[Triangle], depth: usize, use_color: bool) -> Octree {
+    let depth = depth.clamp(1, MAX_DEPTH);
+    let grid_len = 1u32 << (depth - 1);
+    let mut octree = Octree::default();
+
+    if triangle_list.is_empty() {
+        return octree;
+    }
+
+    let mut min = Vec3::repeat(f32::MAX);
+    let mut max = Vec3::repeat(f32::MIN);
+    triangle_list.iter().flat_map(|triangle| triangle.vert.iter()).for_each(|vert| {
+        min = min.inf(vert);
+        max = max.sup(vert);
+    });
+
+    let extent = (max - min).max().max(f32::EPSILON);
+    let scale = grid_len as f32 / extent;
+
+    log::info!(
+        "Voxelizing {} triangles into [ {} ] grid ...",
+        triangle_list.len(),
+        grid_len
+    );
+
+    let half = Vec3::repeat(0.5 + 1e-3);
+    let mut voxel_map: HashMap<[u32; 3], Material> = HashMap::new();
+
+    for triangle in triangle_list {
+        let vert = triangle.vert.map(|vert| (vert - min) * scale);
+
+        let tri_min = vert[0].inf(&vert[1]).inf(&vert[2]);
+        let tri_max = vert[0].sup(&vert[1]).sup(&vert[2]);
+        let start = tri_min.map(|value| (value.floor() - 1.0).clamp(0.0, (grid_len - 1) as f32) as u32);
+        let end = tri_max.map(|value| (value.floor() + 1.0).clamp(0.0, (grid_len - 1) as f32) as u32);
+
+        let material = match (use_color, triangle.color) {
+            (true, Some(color)) => Material::from_color(color),
+            _ => Material::default(),
+        };
+
+        for x in start.x..=end.x {
+            for y in start.y..=end.y {
+                for z in start.z..=end.z {
+                    let center = Vec3::new(x as f32, y as f32, z as f32).add_scalar(0.5);
+
+                    if tri_box_overlap(center, half, &vert) {
+                        voxel_map.entry([x, y, z]).or_insert(material);
+                    }
+                }
+            }
+        }
+    }
+
+    // Format is widened by the insert, once the blocks no longer fit
+    let cell_span = octree.root_span / grid_len as f32;
+    for (cell, material) in voxel_map.iter() {
+        let pos = Vec4::new(cell[0] as f32, cell[1] as f32, cell[2] as f32, 0.0) * cell_span;
+        octree.insert_node_at_depth(pos, *material, depth);
+    }
+
+    log::info!("Voxelized mesh has {} cells ...", voxel_map.len());
+
+    octree
+}
+
+/// Read OBJ or STL mesh and voxelize it.
+pub fn load_mesh(path: &Path, depth: usize, use_color: bool) -> Result<Octree, Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let triangle_list = match extension.as_str() {
+        "obj" => read_obj(path)?,
+        "stl" => read_stl(path)?,
+        _ => return Err(invalid_data("ERR_MESH_EXTENSION")),
+    };
+
+    Ok(voxelize(&triangle_list, depth, use_color))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use nalgebra_glm::{Vec3, Vec4};
+
+    use super::{parse_obj, parse_stl, tri_box_overlap, voxelize, Triangle};
+    use crate::tree::octant::Octant;
+
+    const HALF: Vec3 = Vec3::new(0.5, 0.5, 0.5);
+
+    #[test]
+    fn overlap_separating_axis() {
+        let vert = [
+            Vec3::new(-1.0, -1.0, 0.2),
+            Vec3::new(1.0, -1.0, 0.2),
+            Vec3::new(0.0, 1.0, 0.2),
+        ];
+
+        // Box normal separates
+        assert!(tri_box_overlap(Vec3::zeros(), HALF, &vert));
+        assert!(!tri_box_overlap(Vec3::new(0.0, 0.0, 1.0), HALF, &vert));
+        assert!(!tri_box_overlap(Vec3::new(2.0, 0.0, 0.0), HALF, &vert));
+
+        // Only the triangle normal separates, every box axis overlaps
+        let vert = [
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+        ];
+        assert!(!tri_box_overlap(Vec3::zeros(), HALF, &vert));
+        assert!(tri_box_overlap(Vec3::repeat(0.6), HALF, &vert));
+
+        // Only an edge cross product separates
+        let vert = [
+            Vec3::new(-1.5, 1.5, -2.0),
+            Vec3::new(1.0, 1.0, -2.0),
+            Vec3::new(1.5, 0.0, -0.5),
+        ];
+        assert!(!tri_box_overlap(Vec3::zeros(), HALF, &vert));
+        assert!(tri_box_overlap(Vec3::new(0.0, 0.0, -0.5), HALF, &vert));
+    }
+
+    fn stl_triangle(vert: [[f32; 3]; 3], attrib: u16) -> Vec<u8> {
+        let mut data = vec![0u8; 12];
+        vert.iter()
+            .flatten()
+            .for_each(|value| data.extend_from_slice(&value.to_le_bytes()));
+        data.extend_from_slice(&attrib.to_le_bytes());
+
+        data
+    }
+
+    #[test]
+    fn stl_vertices_and_color() {
+        let mut data = vec![0u8; 80];
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend(stl_triangle(
+            [[0.0, 1.0, 2.0], [3.0, 4.0, 5.0], [6.0, 7.0, 8.0]],
+            0,
+        ));
+        data.extend(stl_triangle([[0.0; 3]; 3], 0x8000 | (31 << 10) | 31));
+
+        let triangle_list = parse_stl(&data).unwrap();
+        assert_eq!(triangle_list.len(), 2);
+        assert_eq!(triangle_list[0].vert[1], Vec3::new(3.0, 4.0, 5.0));
+        assert_eq!(triangle_list[0].color, None);
+        assert_eq!(triangle_list[1].color, Some(Vec4::new(1.0, 0.0, 1.0, 1.0)));
+
+        assert!(parse_stl(&data[..data.len() - 1]).is_err());
+        assert!(parse_stl(b"solid cube").is_err());
+    }
+
+    #[test]
+    fn obj_fan_and_relative_idx() {
+        let text = "
+            # quad with vertex colors and one triangle with negative idx
+            v 0 0 0 1 0 0
+            v 1 0 0 1 0 0
+            v 1 1 0 0 0 1
+            v 0 1 0 0 0 1
+            f 1/1/1 2/2/1 3/3/1 4/4/1
+            v 0 0 1
+            f -1 -2 -3
+        ";
+
+        let triangle_list = parse_obj(text, Path::new("mesh.obj")).unwrap();
+        assert_eq!(triangle_list.len(), 3);
+        assert_eq!(triangle_list[1].vert[0], Vec3::zeros());
+        assert_eq!(triangle_list[1].vert[2], Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(
+            triangle_list[0].color,
+            Some(Vec4::new(2.0 / 3.0, 0.0, 1.0 / 3.0, 1.0))
+        );
+        assert_eq!(triangle_list[2].vert[0], Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(triangle_list[2].color, None);
+
+        assert!(parse_obj("v 0 0 0\nf 1 2 3", Path::new("mesh.obj")).is_err());
+        assert!(parse_obj("v 0 0 x", Path::new("mesh.obj")).is_err());
+    }
+
+    #[test]
+    fn plane_leaf_count() {
+        // Half of a plane of 64 x 64 cells, the diagonal touches a few more
+        let triangle = Triangle {
+            vert: [
+                Vec3::zeros(),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ],
+            color: None,
+        };
+
+        let octree = voxelize(&[triangle], 7, false);
+        let leaf_count = octree
+            .octant_data
+            .iter()
+            .filter(|node| node.is_leaf())
+            .count();
+
+        assert!(leaf_count > 64 * 64 / 2);
+        assert!(leaf_count < 64 * 64 / 2 + 2 * 64);
+    }
+}
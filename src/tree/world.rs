@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+
+use nalgebra_glm::Vec4;
+
+use super::{
+    material::Material,
+    octree::{Octree, GRID_LEN},
+    trace::{Hit, Ray},
+};
+
+/// Chunk coordinate, chunk (x, y, z) starts at (x, y, z) * root span.
+pub type ChunkPos = [i32; 3];
+
+/// Creates the octree of a chunk when it is loaded.
+pub type ChunkGenerator = Box<dyn Fn(ChunkPos) -> Octree + Send + Sync>;
+
+/// Scene made of many octree roots, each root is one chunk of the world.
+/// Chunks around the cam are loaded with the generator, far ones are dropped.
+pub struct World {
+    pub chunk_map: HashMap<ChunkPos, Octree>,
+    pub root_span: f32,
+
+    // Chunks loaded around the cam, in chunks along each axis
+    pub load_radius: [i32; 3],
+
+    generator: ChunkGenerator,
+}
+
+impl World {
+    pub fn new(root_span: f32, load_radius: [i32; 3], generator: ChunkGenerator) -> Self {
+        Self {
+            chunk_map: HashMap::new(),
+            root_span,
+            load_radius,
+            generator,
+        }
+    }
+
+    pub fn chunk_pos(&self, pos: Vec4) -> ChunkPos {
+        [0, 1, 2].map(|axis| (pos[axis] / self.root_span).floor() as i32)
+    }
+
+    pub fn chunk_origin(&self, chunk_pos: ChunkPos) -> Vec4 {
+        Vec4::new(
+            chunk_pos[0] as f32,
+            chunk_pos[1] as f32,
+            chunk_pos[2] as f32,
+            0.0,
+        ) * self.root_span
+    }
+
+    /// Load every chunk in load radius around pos (like Uniform::pos)
+    /// and drop chunks that are more than one chunk outside of it,
+    /// so moving along a chunk border does not load and drop all the time.
+    /// Return true if the set of loaded chunks changed.
+    pub fn update(&mut self, pos: Vec4) -> bool {
+        let center = self.chunk_pos(pos);
+        let radius = self.load_radius;
+
+        let chunk_count = self.chunk_map.len();
+        self.chunk_map.retain(|chunk_pos, _| {
+            (0..3).all(|axis| (chunk_pos[axis] - center[axis]).abs() <= radius[axis] + 1)
+        });
+        let unloaded = chunk_count - self.chunk_map.len();
+
+        let mut loaded = 0;
+        for x in -radius[0]..=radius[0] {
+            for y in -radius[1]..=radius[1] {
+                for z in -radius[2]..=radius[2] {
+                    let chunk_pos = [center[0] + x, center[1] + y, center[2] + z];
+
+                    if !self.chunk_map.contains_key(&chunk_pos) {
+                        // Solid chunks are kept as a single leaf
+                        let mut octree = (self.generator)(chunk_pos);
+                        octree.collapse_uniform();
+
+                        self.chunk_map.insert(chunk_pos, octree);
+                        loaded += 1;
+                    }
+                }
+            }
+        }
+
+        if loaded + unloaded > 0 {
+            log::info!(
+                "World around chunk {:?}, loaded {} and dropped {} chunks ...",
+                center,
+                loaded,
+                unloaded
+            );
+        }
+
+        loaded + unloaded > 0
+    }
+
+    /// Chunk at chunk pos is taken out of the map, like the uploaded one, and
+    /// pos is relative to it. Once pos leaves the chunk, it goes back into
+    /// the map and the chunk pos enters is taken out instead, marked dirty
+    /// for a full upload. Chunks around pos are loaded and far ones dropped.
+    /// Return the offset, that has to be subtracted from pos.
+    pub fn follow(
+        &mut self,
+        chunk_pos: &mut ChunkPos,
+        octree: &mut Octree,
+        pos: Vec4,
+    ) -> Option<Vec4> {
+        let offset = self.chunk_pos(pos);
+        if offset == [0; 3] {
+            return None;
+        }
+
+        let next_chunk_pos = [0, 1, 2].map(|axis| chunk_pos[axis] + offset[axis]);
+        let shift = self.chunk_origin(offset);
+
+        self.chunk_map.insert(*chunk_pos, std::mem::take(octree));
+        self.update(self.chunk_origin(next_chunk_pos) + pos - shift);
+
+        let root_span = self.root_span;
+        *octree = self
+            .chunk_map
+            .remove(&next_chunk_pos)
+            .unwrap_or_else(|| Octree {
+                root_span,
+
+                ..Default::default()
+            });
+        octree.mark_dirty(0..octree.octant_data.len());
+        *chunk_pos = next_chunk_pos;
+
+        Some(shift)
+    }
+
+    /// Build the chunks of a dense grid that is bigger than one root,
+    /// like a 256³ volume. Voxel (x, y, z) is the leaf at (x, y, z) * leaf
+    /// span from the world origin, every chunk is built with from_dense.
+    pub fn insert_dense<Function: Fn(u32, u32, u32) -> Option<Material>>(
+        &mut self,
+        dims: [u32; 3],
+        sample: Function,
+    ) {
+        let chunk_count = dims.map(|dim| dim.div_ceil(GRID_LEN) as i32);
+
+        for x in 0..chunk_count[0] {
+            for y in 0..chunk_count[1] {
+                for z in 0..chunk_count[2] {
+                    let base = [x, y, z].map(|pos| pos as u32 * GRID_LEN);
+                    let chunk_dims = [0, 1, 2].map(|axis| (dims[axis] - base[axis]).min(GRID_LEN));
+
+                    let octree = Octree::from_dense(chunk_dims, |x, y, z| {
+                        sample(base[0] + x, base[1] + y, base[2] + z)
+                    });
+
+                    self.chunk_map.insert(
+                        [x, y, z],
+                        Octree {
+                            root_span: self.root_span,
+
+                            ..octree
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn material(&self, chunk_pos: ChunkPos, hit: &Hit) -> Material {
+        self.chunk_map[&chunk_pos].material_data[hit.idx as usize]
+    }
+
+    /// First leaf along the ray over all loaded chunks. Chunks are visited in
+    /// ray order (3D DDA on the chunk grid), so the first hit is the nearest.
+    /// Hit pos is in world space.
+    pub fn cast_ray(&self, ray: &Ray, max_dist: f32) -> Option<(ChunkPos, Hit)> {
+        let dir = Vec4::new(ray.dir.x, ray.dir.y, ray.dir.z, 0.0);
+        if dir.norm() == 0.0 || self.chunk_map.is_empty() {
+            return None;
+        }
+        let dir = dir.normalize();
+
+        // Bounds of the loaded chunks, the ray stops when it leaves them
+        let mut min = [i32::MAX; 3];
+        let mut max = [i32::MIN; 3];
+        self.chunk_map.keys().for_each(|chunk_pos| {
+            (0..3).for_each(|axis| {
+                min[axis] = min[axis].min(chunk_pos[axis]);
+                max[axis] = max[axis].max(chunk_pos[axis]);
+            })
+        });
+
+        let mut chunk_pos = self.chunk_pos(ray.origin);
+        let step = [0, 1, 2].map(|axis| dir[axis].signum() as i32);
+        let delta = [0, 1, 2].map(|axis| self.root_span / dir[axis].abs());
+        let mut next = [0, 1, 2].map(|axis| {
+            let edge = (chunk_pos[axis] + (step[axis] > 0) as i32) as f32 * self.root_span;
+
+            if dir[axis] == 0.0 {
+                f32::MAX
+            } else {
+                (edge - ray.origin[axis]) / dir[axis]
+            }
+        });
+
+        loop {
+            if let Some(octree) = self.chunk_map.get(&chunk_pos) {
+                let origin = self.chunk_origin(chunk_pos);
+                let local_ray = Ray {
+                    origin: ray.origin - origin,
+                    dir,
+                };
+
+                if let Some(mut hit) = octree.cast_ray(&local_ray, max_dist) {
+                    hit.pos += origin;
+                    return Some((chunk_pos, hit));
+                }
+            }
+
+            let axis = (0..3)
+                .min_by(|&a, &b| next[a].total_cmp(&next[b]))
+                .unwrap_or_default();
+
+            if next[axis] > max_dist {
+                return None;
+            }
+
+            chunk_pos[axis] += step[axis];
+            next[axis] += delta[axis];
+
+            let leaving = (0..3).any(|axis| {
+                (chunk_pos[axis] < min[axis] && step[axis] <= 0)
+                    || (chunk_pos[axis] > max[axis] && step[axis] >= 0)
+            });
+            if leaving {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use nalgebra_glm::Vec4;
+
+    use super::World;
+    use crate::tree::{
+        material::Material,
+        octree::{Octree, GRID_LEN},
+        trace::Ray,
+    };
+
+    fn empty_world(load_radius: [i32; 3]) -> World {
+        World::new(256.0, load_radius, Box::new(|_| Octree::default()))
+    }
+
+    /// Insert leaf at world pos, a missing chunk is created empty.
+    fn insert_node(world: &mut World, pos: Vec4, material: Material) {
+        let chunk_pos = world.chunk_pos(pos);
+        let local_pos = pos - world.chunk_origin(chunk_pos);
+
+        world
+            .chunk_map
+            .entry(chunk_pos)
+            .or_default()
+            .insert_node(local_pos, material);
+    }
+
+    fn remove_node(world: &mut World, pos: Vec4) -> bool {
+        let chunk_pos = world.chunk_pos(pos);
+        let local_pos = pos - world.chunk_origin(chunk_pos);
+
+        world
+            .chunk_map
+            .get_mut(&chunk_pos)
+            .and_then(|octree| octree.remove_node(local_pos))
+            .is_some()
+    }
+
+    #[test]
+    fn chunk_pos_of_negative_pos() {
+        let world = empty_world([1; 3]);
+
+        assert_eq!(
+            world.chunk_pos(Vec4::new(0.0, 255.9, 256.0, 0.0)),
+            [0, 0, 1]
+        );
+        assert_eq!(
+            world.chunk_pos(Vec4::new(-0.1, -256.0, -256.1, 0.0)),
+            [-1, -1, -2]
+        );
+        assert_eq!(
+            world.chunk_origin([-1, 0, 2]),
+            Vec4::new(-256.0, 0.0, 512.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn load_and_drop_around_cam() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let generated = count.clone();
+        let mut world = World::new(
+            256.0,
+            [1, 0, 1],
+            Box::new(move |_| {
+                generated.fetch_add(1, Ordering::Relaxed);
+                Octree::default()
+            }),
+        );
+
+        assert!(world.update(Vec4::new(10.0, 10.0, 10.0, 0.0)));
+        assert_eq!(world.chunk_map.len(), 9);
+        assert!(!world.update(Vec4::new(200.0, 10.0, 10.0, 0.0)));
+
+        // One chunk further, the old row is kept until it is two chunks away
+        assert!(world.update(Vec4::new(300.0, 10.0, 10.0, 0.0)));
+        assert_eq!(world.chunk_map.len(), 12);
+        assert!(world.update(Vec4::new(600.0, 10.0, 10.0, 0.0)));
+        assert_eq!(world.chunk_map.len(), 12);
+        assert!(!world.chunk_map.contains_key(&[-1, 0, 0]));
+        assert_eq!(count.load(Ordering::Relaxed), 15);
+    }
+
+    #[test]
+    fn solid_chunks_collapse() {
+        let mut world = World::new(
+            256.0,
+            [0, 1, 0],
+            Box::new(|chunk_pos| match chunk_pos[1] {
+                -1 => Octree::from_dense([GRID_LEN; 3], |_, _, _| Some(Material::default())),
+                _ => Octree::default(),
+            }),
+        );
+        world.update(Vec4::new(10.0, 10.0, 10.0, 0.0));
+
+        assert_eq!(world.chunk_map[&[0, -1, 0]].octant_data.len(), 1);
+        let ray = Ray {
+            origin: Vec4::new(10.0, 10.0, 10.0, 0.0),
+            dir: Vec4::new(0.0, -1.0, 0.0, 0.0),
+        };
+        let (chunk_pos, hit) = world.cast_ray(&ray, f32::MAX).unwrap();
+        assert_eq!(chunk_pos, [0, -1, 0]);
+        assert_eq!(hit.pos.y, 0.0);
+    }
+
+    #[test]
+    fn follow_cam_over_chunks() {
+        let mut world = World::new(
+            256.0,
+            [1, 0, 1],
+            Box::new(|chunk_pos| {
+                let mut octree = Octree::default();
+                let pos = Vec4::new(chunk_pos[0] as f32, 0.0, chunk_pos[2] as f32, 0.0);
+                octree.insert_node(pos.abs() * 2.0, Material::default());
+
+                octree
+            }),
+        );
+        world.update(Vec4::zeros());
+
+        let mut chunk_pos = [0; 3];
+        let mut octree = world.chunk_map.remove(&chunk_pos).unwrap();
+        let edited_len = octree.octant_data.len();
+        assert!(world
+            .follow(&mut chunk_pos, &mut octree, Vec4::new(255.0, 1.0, 1.0, 0.0))
+            .is_none());
+
+        // Cam enters chunk (1, 0, -1), pos is relative to it from now on
+        let shift = world
+            .follow(
+                &mut chunk_pos,
+                &mut octree,
+                Vec4::new(300.0, 1.0, -1.0, 0.0),
+            )
+            .unwrap();
+        assert_eq!(shift, Vec4::new(256.0, 0.0, -256.0, 0.0));
+        assert_eq!(chunk_pos, [1, 0, -1]);
+        assert!(!world.chunk_map.contains_key(&chunk_pos));
+        assert_eq!(world.chunk_map[&[0; 3]].octant_data.len(), edited_len);
+        assert!(world.chunk_map.contains_key(&[2, 0, -2]));
+
+        let pos_info = octree.node_at_pos(Vec4::new(2.0, 0.0, 2.0, 0.0));
+        assert_eq!(pos_info.depth as usize, 7);
+        assert_eq!(octree.take_dirty(), vec![0..octree.octant_data.len()]);
+    }
+
+    #[test]
+    fn cast_ray_over_chunks() {
+        let mut world = empty_world([1; 3]);
+        let material = Material::from_color(Vec4::new(0.0, 1.0, 0.0, 1.0));
+
+        insert_node(
+            &mut world,
+            Vec4::new(-1.0, 1.0, 1.0, 0.0),
+            Material::default(),
+        );
+        insert_node(&mut world, Vec4::new(600.0, 1.0, 1.0, 0.0), material);
+        world.update(Vec4::new(10.0, 10.0, 10.0, 0.0));
+
+        // Through the empty chunks in between
+        let ray = Ray {
+            origin: Vec4::new(10.0, 1.5, 1.5, 0.0),
+            dir: Vec4::new(1.0, 0.0, 0.0, 0.0),
+        };
+        let (chunk_pos, hit) = world.cast_ray(&ray, f32::MAX).unwrap();
+        assert_eq!(chunk_pos, [2, 0, 0]);
+        assert_eq!(hit.pos.x, 600.0);
+        assert_eq!(hit.dist, 590.0);
+        assert_eq!(world.material(chunk_pos, &hit), material);
+        assert!(world.cast_ray(&ray, 500.0).is_none());
+
+        // Leaf at negative pos
+        let ray = Ray {
+            dir: Vec4::new(-1.0, 0.0, 0.0, 0.0),
+
+            ..ray
+        };
+        let (chunk_pos, hit) = world.cast_ray(&ray, f32::MAX).unwrap();
+        assert_eq!(chunk_pos, [-1, 0, 0]);
+        assert_eq!(hit.pos.x, 0.0);
+        assert_eq!(hit.normal, Vec4::new(1.0, 0.0, 0.0, 0.0));
+
+        assert!(remove_node(&mut world, Vec4::new(-1.0, 1.0, 1.0, 0.0)));
+        assert!(world.cast_ray(&ray, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn insert_dense_over_chunks() {
+        let mut world = empty_world([1; 3]);
+        let filled = |x: u32, y: u32, z: u32| (x + 2 * y + z).is_multiple_of(5);
+
+        world.insert_dense([300, 3, 140], |x, y, z| {
+            filled(x, y, z).then(Material::default)
+        });
+        assert_eq!(world.chunk_map.len(), 3 * 2);
+
+        // Leaf span is 2 for a root span of 256
+        for (x, y, z) in [
+            (0, 0, 0),
+            (129, 1, 2),
+            (255, 2, 139),
+            (299, 0, 131),
+            (1, 1, 1),
+        ] {
+            let pos = Vec4::new(x as f32, y as f32, z as f32, 0.0) * 2.0;
+            assert_eq!(remove_node(&mut world, pos), filled(x, y, z));
+        }
+    }
+}
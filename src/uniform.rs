@@ -43,6 +43,18 @@ impl Uniform {
         }
     }
 
+    /// Cam above the root corner, looking at the center of the root.
+    /// Used when there is no mouse input, like for rendering single images.
+    pub fn overview(root_span: f32, res: [u32; 2]) -> Self {
+        let mut uniform = Self::new(root_span);
+
+        uniform.res = Vec2::new(res[0] as f32, res[1] as f32);
+        uniform.cam_pos = Vec4::new(-0.3, 1.1, -0.3, 0.0) * root_span;
+        uniform.look_at(Vec4::new(0.5, 0.3, 0.5, 0.0) * root_span);
+
+        uniform
+    }
+
     pub fn apply_resolution(&mut self, resolution: vk::Extent2D) {
         self.res = Vec2::new(resolution.width as f32, resolution.height as f32);
     }
@@ -72,6 +84,18 @@ impl Uniform {
         );
     }
 
+    /// Turn cam towards target, mouse rot is updated as well,
+    /// so the next mouse move does not jump.
+    pub fn look_at(&mut self, target: Vec4) {
+        let dir = (target - self.cam_pos).xyz().normalize();
+
+        self.mouse_rot = Vec2::new(
+            dir.z.atan2(dir.x).to_degrees(),
+            dir.y.asin().to_degrees(),
+        );
+        self.look_dir = Vec4::new(dir.x, dir.y, dir.z, 0.0);
+    }
+
     pub fn update_uniform(&mut self, cur_time: Duration) {
         self.time = cur_time.as_millis() as u32;
 
@@ -51,10 +51,10 @@ pub trait Vector {
 impl Vector for Vec4 {
     fn step(&self, edge: Self) -> Self {
         Self::new(
-            (edge.x < self.x).into(),
-            (edge.y < self.y).into(),
-            (edge.z < self.z).into(),
-            (edge.w < self.w).into(),
+            (edge.x <= self.x).into(),
+            (edge.y <= self.y).into(),
+            (edge.z <= self.z).into(),
+            (edge.w <= self.w).into(),
         )
     }
 
@@ -97,9 +97,9 @@ impl Vector for Vec4 {
 impl Vector for Vec3 {
     fn step(&self, edge: Self) -> Self {
         Self::new(
-            (edge.x < self.x).into(),
-            (edge.y < self.y).into(),
-            (edge.z < self.z).into(),
+            (edge.x <= self.x).into(),
+            (edge.y <= self.y).into(),
+            (edge.z <= self.z).into(),
         )
     }
 
@@ -130,7 +130,7 @@ impl Vector for Vec3 {
 
 impl Vector for Vec2 {
     fn step(&self, edge: Self) -> Self {
-        Self::new((edge.x < self.x).into(), (edge.y < self.y).into())
+        Self::new((edge.x <= self.x).into(), (edge.y <= self.y).into())
     }
 
     fn floor(&self) -> Self {
@@ -153,3 +153,24 @@ impl Vector for Vec2 {
         Self::new(num, num)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3, Vec4};
+
+    use super::Vector;
+
+    #[test]
+    fn step_like_glsl() {
+        // GLSL step is 1.0 for x >= edge, a pos on the edge is in the upper half
+        assert_eq!(
+            Vec4::new(1.0, 2.0, 3.0, 2.0).step(Vec4::ftv(2.0)),
+            Vec4::new(0.0, 1.0, 1.0, 1.0)
+        );
+        assert_eq!(
+            Vec3::new(2.0, 1.9, 2.1).step(Vec3::ftv(2.0)),
+            Vec3::new(1.0, 0.0, 1.0)
+        );
+        assert_eq!(Vec2::new(2.0, 0.0).step(Vec2::ftv(2.0)), Vec2::new(1.0, 0.0));
+    }
+}